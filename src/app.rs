@@ -9,23 +9,27 @@ use std::sync::Arc;
 
 use crate::zip_fs;
 use anyhow::{anyhow, Context, Result};
-use glam::{vec2, Vec2};
+use glam::{vec2, IVec2, Vec2};
 use log::error;
 use miniquad::{FilterMode, Pipeline, Texture, TextureFormat, TextureParams, TextureWrap};
 use realtime_drawing::{MiniquadBatch, VertexPos3UvColor};
 use rimui::{FontManager, FrameLook, SpriteContext, SpriteKey, StyleKey, UI};
 use serde_derive::{Deserialize, Serialize};
-use slotmap::SlotMap;
 use zip::write::FileOptions;
 use zip::{ZipArchive, ZipWriter};
 
 use cbmap::{BuiltinMaterial, MapJson, MaterialSlot};
 
-use crate::document::{ChangeMask, Document, DocumentLocalState, SelectRef, View};
-use crate::field::Field;
-use crate::graphics::{create_pipeline, create_pipeline_sdf, DocumentGraphics};
-use crate::grid::Grid;
-use crate::math::Rect;
+use crate::document::{
+    ChangeMask, Document, DocumentLocalState, LayerEntry, LayerGroupKey, SelectRef, View,
+};
+use crate::generation::GenerationWorker;
+use crate::gizmo::GizmoPivot;
+use crate::graph::GraphNode;
+use crate::graphics::{
+    create_pipeline, create_pipeline_sdf, DocumentGraphics, FINISH_TEXTURE_PIXELS,
+};
+use crate::lsystem::LSystem;
 use crate::mouse_operation::MouseOperation;
 use crate::net_client_connection::ClientConnection;
 use crate::profiler::Profiler;
@@ -53,6 +57,7 @@ pub struct App {
     pub network_operation: Option<Box<dyn FnMut(&mut App) -> bool>>,
     pub play_state: PlayState,
     pub locked_hover: Option<SelectRef>,
+    pub gizmo_pivot: Option<GizmoPivot>,
 
     pub active_material: u8,
     pub operation: MouseOperation,
@@ -66,6 +71,7 @@ pub struct App {
     pub undo_saved_position: RefCell<usize>,
     pub confirm_unsaved_changes: Option<Box<dyn FnMut(&mut App, &mut miniquad::Context)>>,
     pub graphics: RefCell<DocumentGraphics>,
+    pub generation: GenerationWorker,
     pub generation_profiler: Profiler,
     pub generation_profiler_show: bool,
     pub view: View,
@@ -74,6 +80,22 @@ pub struct App {
     pub green_style: StyleKey,
 
     pub show_material_bounds: bool,
+    pub show_history: bool,
+    /// Selected in the layer list instead of the current layer
+    pub current_layer_group: Option<LayerGroupKey>,
+    /// Layer list entry that is being dragged
+    pub layer_drag: Option<LayerEntry>,
+    pub number_drag: Option<NumberDrag>,
+    /// Grammar of the plant panel with its validation error. Validation expands the grammar,
+    /// so it is repeated only when the grammar changes.
+    pub lsystem_error: Option<(LSystem, Option<String>)>,
+}
+
+/// Number field that is being dragged, with the mouse position and value it started from
+pub struct NumberDrag {
+    pub id: String,
+    pub start_x: i32,
+    pub start_value: f32,
 }
 
 pub const MODIFIER_CONTROL: usize = 0;
@@ -103,16 +125,9 @@ impl App {
                 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
             ],
         );
-        #[rustfmt::skip]
-        let finish_pixels: [u32; 4 * 4] = [
-            0xff736556, 0xff736556, 0xff000000, 0xff000000,
-            0xff736556, 0xff736556, 0xff000000, 0xff000000,
-            0xff000000, 0xff000000, 0xff736556, 0xff736556,
-            0xff000000, 0xff000000, 0xff736556, 0xff736556,
-        ];
         let finish_texture = Texture::from_data_and_format(
             context,
-            &finish_pixels.as_bytes(),
+            FINISH_TEXTURE_PIXELS.as_bytes(),
             TextureParams {
                 format: TextureFormat::RGBA8,
                 wrap: TextureWrap::Repeat,
@@ -153,20 +168,7 @@ impl App {
 
         ui.set_context(Some(font_manager.clone()), Some(sprites));
 
-        let graphics = DocumentGraphics {
-            cell_size: 4,
-            generated_grid: Grid {
-                default_value: 0,
-                bounds: Rect::zero(),
-                cells: vec![],
-            },
-            generated_distances: Field::new(),
-            distance_textures: Default::default(),
-            reference_texture: None,
-            resolved_materials: Vec::new(),
-            materials: Vec::new(),
-            plant_segments: SlotMap::with_key(),
-        };
+        let graphics = DocumentGraphics::new();
 
         let app_state = App::load_app_state().ok().flatten();
         let (doc, local_state, doc_path) =
@@ -212,8 +214,9 @@ impl App {
         });
 
         let dirty_mask = ChangeMask {
-            cell_layers: u64::MAX,
+            all_layers: true,
             reference_path: true,
+            ..ChangeMask::default()
         };
 
         let clipboard = arboard::Clipboard::new().expect("Failed to open clipboard");
@@ -244,6 +247,7 @@ impl App {
             last_mouse_pos: vec2(0.0, 0.0),
             window_size: [context.screen_size().0, context.screen_size().1],
             graphics: RefCell::new(graphics),
+            generation: GenerationWorker::new(),
             generation_profiler: Profiler::new(),
             view,
             doc_path,
@@ -254,8 +258,14 @@ impl App {
             network_operation: None,
             play_state: PlayState::Offline,
             show_material_bounds: false,
+            show_history: false,
+            current_layer_group: None,
+            layer_drag: None,
+            number_drag: None,
+            lsystem_error: None,
             clipboard,
             locked_hover: None,
+            gizmo_pivot: None,
         }
     }
 
@@ -323,6 +333,7 @@ impl App {
                 .cloned(),
             );
         }
+        GraphNode::upgrade_no_outline(&mut document.nodes, &mut document.edges);
         document.side_load = side_load;
 
         Ok(document)
@@ -353,6 +364,36 @@ impl App {
         {
             path.set_extension(OsString::try_from("cbmap")?);
         }
+        let (image, image_bounds) =
+            graphics.render_map_image(doc, white_pixel, finish_texture, sdf_pipeline, context);
+        App::write_map_archive(&path, doc, graphics, &image, image_bounds)?;
+
+        let mut sidecar_path = PathBuf::from(path);
+        let mut extension = sidecar_path
+            .extension()
+            .map(|e| e.to_owned())
+            .unwrap_or(OsString::new());
+        extension.push(OsString::try_from(".state")?);
+        sidecar_path.set_extension(extension);
+
+        let local_state = DocumentLocalState {
+            view: view.clone(),
+            active_material,
+        };
+        let state_serialized =
+            serde_json::to_vec_pretty(&local_state).context("Serializing local state")?;
+        write(sidecar_path, state_serialized).context("Writing local state")?;
+        Ok(())
+    }
+
+    /// Writes `.cbmap` archive: source document together with the generated map files
+    pub(crate) fn write_map_archive(
+        path: &Path,
+        doc: &Document,
+        graphics: &DocumentGraphics,
+        image: &[u8],
+        image_bounds: [IVec2; 2],
+    ) -> Result<()> {
         let serialized = serde_json::to_vec_pretty(doc).context("Serializing document")?;
 
         let mut zip_bytes = Vec::new();
@@ -360,9 +401,6 @@ impl App {
         zip.start_file("source.json", FileOptions::default())?;
         zip.write(&serialized)?;
 
-        let (image, image_bounds) =
-            graphics.render_map_image(doc, white_pixel, finish_texture, sdf_pipeline, context);
-
         if !doc.markup.is_empty() {
             let mut translated_markup = doc.markup.clone();
             // adjust all markup to match image coordinates
@@ -385,7 +423,7 @@ impl App {
             encoder.set_color(png::ColorType::RGBA);
             encoder.set_depth(png::BitDepth::Eight);
             let mut writer = encoder.write_header()?;
-            writer.write_image_data(&image)?;
+            writer.write_image_data(image)?;
         }
         zip.start_file("main.png", FileOptions::default())?;
         zip.write_all(&png_bytes)?;
@@ -412,29 +450,13 @@ impl App {
         let temp_path = PathBuf::from(&path).with_extension(OsString::try_from(".tmp")?);
         write(&temp_path, &zip_bytes)
             .with_context(|| format!("Saving {}", temp_path.to_string_lossy()))?;
-        rename(&temp_path, &path).with_context(|| {
+        rename(&temp_path, path).with_context(|| {
             format!(
                 "Renaming {} to {}",
                 temp_path.to_string_lossy(),
                 path.to_string_lossy()
             )
         })?;
-
-        let mut sidecar_path = PathBuf::from(path);
-        let mut extension = sidecar_path
-            .extension()
-            .map(|e| e.to_owned())
-            .unwrap_or(OsString::new());
-        extension.push(OsString::try_from(".state")?);
-        sidecar_path.set_extension(extension);
-
-        let local_state = DocumentLocalState {
-            view: view.clone(),
-            active_material,
-        };
-        let state_serialized =
-            serde_json::to_vec_pretty(&local_state).context("Serializing local state")?;
-        write(sidecar_path, state_serialized).context("Writing local state")?;
         Ok(())
     }
 
@@ -502,6 +524,7 @@ pub struct ShaderUniforms {
 pub struct SDFUniforms {
     pub fill_color: [f32; 4],
     pub outline_color: [f32; 4],
+    pub outline_width: f32,
     pub world_to_screen_xy: [f32; 4],
     pub world_to_screen_pos: [f32; 2],
     pub screen_size: [f32; 2],
@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use glam::{IVec2, Vec2};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::document::{Document, SelectRef};
+use crate::graph::{GraphEdge, GraphNode, GraphNodeKey};
+use crate::grid::Grid;
+use crate::math::Rect;
+use crate::plant::Plant;
+
+/// Prefix used to recognize our own payload among arbitrary clipboard text
+const CLIPBOARD_HEADER: &str = "cba-editor-clipboard\n";
+
+/// Selected part of the document, as it travels through the system clipboard
+#[derive(Serialize, Deserialize)]
+pub struct ClipboardContent {
+    /// World position that is placed under the cursor when pasting
+    pub origin: Vec2,
+    pub nodes: Vec<(GraphNodeKey, GraphNode)>,
+    pub edges: Vec<GraphEdge>,
+    pub plants: Vec<Plant>,
+    /// Selected cells of the current layer, zero cells are not pasted
+    pub cells: Option<Grid<u8>>,
+}
+
+impl ClipboardContent {
+    pub fn from_selection(doc: &Document) -> ClipboardContent {
+        let mut nodes = Vec::new();
+        let mut plants = Vec::new();
+        for &selection in &doc.selected {
+            match selection {
+                SelectRef::Node(key)
+                | SelectRef::NodeRadius(key)
+                | SelectRef::NodeVertex(key, _) => {
+                    let Some(node) = doc.nodes.get(key) else { continue };
+                    if !nodes.iter().any(|(k, _)| *k == key) {
+                        nodes.push((key, node.clone()));
+                    }
+                }
+                SelectRef::Plant(key) | SelectRef::PlantDirection(key) => {
+                    let Some(plant) = doc.plants.get(key) else { continue };
+                    plants.push(plant.clone());
+                }
+                _ => {}
+            }
+        }
+
+        // only edges with both ends selected are copied
+        let edges = doc
+            .edges
+            .values()
+            .filter(|e| {
+                nodes.iter().any(|(k, _)| *k == e.start) && nodes.iter().any(|(k, _)| *k == e.end)
+            })
+            .cloned()
+            .collect();
+
+        let cells = doc.selected_cells();
+
+        let mut bounds = <[Vec2; 2]>::invalid();
+        for (_, node) in &nodes {
+            bounds = bounds.union(node.bounds());
+        }
+        for plant in &plants {
+            bounds = bounds.union(Rect::from_point(plant.pos.as_vec2()));
+        }
+        if let Some(cells) = &cells {
+            let cell_size = doc.cell_size as f32;
+            bounds = bounds.union([
+                cells.bounds[0].as_vec2() * cell_size,
+                cells.bounds[1].as_vec2() * cell_size,
+            ]);
+        }
+        let origin = bounds
+            .valid()
+            .map(|b| Document::snap_to_grid((b[0] + b[1]) * 0.5, doc.cell_size))
+            .unwrap_or(Vec2::ZERO);
+
+        ClipboardContent {
+            origin,
+            nodes,
+            edges,
+            plants,
+            cells,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.plants.is_empty() && self.cells.is_none()
+    }
+
+    pub fn to_text(&self) -> Result<String> {
+        let json = serde_json::to_string(self).context("Serializing clipboard content")?;
+        Ok(format!("{}{}", CLIPBOARD_HEADER, json))
+    }
+
+    /// Returns `None` when clipboard holds something that was not copied by the editor
+    pub fn from_text(text: &str) -> Result<Option<ClipboardContent>> {
+        let json = match text.strip_prefix(CLIPBOARD_HEADER) {
+            Some(json) => json,
+            None => return Ok(None),
+        };
+        let content = serde_json::from_str(json)
+            .map_err(|e| anyhow!("Deserializing clipboard content: {}", e))?;
+        Ok(Some(content))
+    }
+
+    /// Inserts content into the document with fresh keys and returns new selection
+    pub fn paste(&self, doc: &mut Document, delta: IVec2) -> Vec<SelectRef> {
+        let layer = doc.current_layer;
+        let mut selection = Vec::new();
+
+        let mut new_node_keys = Vec::with_capacity(self.nodes.len());
+        for (old_key, node) in &self.nodes {
+            let new_key = doc.nodes.insert(GraphNode {
+                pos: node.pos + delta,
+                layer,
+                symmetry_group: None,
+                ..node.clone()
+            });
+            new_node_keys.push((*old_key, new_key));
+            selection.push(SelectRef::Node(new_key));
+        }
+        let new_node_key = |old: GraphNodeKey| {
+            new_node_keys
+                .iter()
+                .find(|(k, _)| *k == old)
+                .map(|(_, new)| *new)
+        };
+        for edge in &self.edges {
+            let Some((start, end)) = new_node_key(edge.start).zip(new_node_key(edge.end)) else { continue };
+            doc.edges.insert(GraphEdge {
+                control_points: edge.control_points.iter().map(|p| *p + delta).collect(),
+                ..GraphEdge::new(start, end)
+            });
+        }
+
+        for plant in &self.plants {
+            let key = doc.plants.insert(Plant {
+                pos: plant.pos + delta,
+                layer,
+                ..plant.clone()
+            });
+            selection.push(SelectRef::Plant(key));
+        }
+
+        if let Some(cells) = &self.cells {
+            let cell_delta = delta / doc.cell_size;
+            let target_bounds = [cells.bounds[0] + cell_delta, cells.bounds[1] + cell_delta];
+            let grid_key = Document::get_or_add_layer_grid(&mut doc.layers, layer, &mut doc.grids);
+            if let Some(grid) = doc.grids.get_mut(grid_key).map(Arc::make_mut) {
+                grid.resize_to_include_amortized(target_bounds);
+                for y in cells.bounds[0].y..cells.bounds[1].y {
+                    for x in cells.bounds[0].x..cells.bounds[1].x {
+                        let value = cells.cells[cells.grid_pos_index(x, y)];
+                        if value == 0 {
+                            continue;
+                        }
+                        let index = grid.grid_pos_index(x + cell_delta.x, y + cell_delta.y);
+                        grid.cells[index] = value;
+                    }
+                }
+            }
+            doc.selection = Grid {
+                default_value: 0,
+                bounds: target_bounds,
+                cells: vec![1; cells.cells.len()],
+            };
+        }
+
+        selection
+    }
+}
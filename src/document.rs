@@ -1,21 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use cbmap::{BuiltinMaterial, MapMarkup, MaterialSlot, MaterialsJson};
-use glam::{vec2, Affine2, Vec2};
+use glam::{vec2, Affine2, IVec2, Vec2};
 use ordered_float::NotNan;
 use realtime_drawing::{MiniquadBatch, VertexPos3UvColor};
 use serde_derive::{Deserialize, Serialize};
 use slotmap::{new_key_type, Key};
 
 use crate::app::App;
-use crate::graph::{GraphEdge, GraphEdgeKey, GraphNode, GraphNodeKey};
+use crate::graph::{GraphEdge, GraphEdgeKey, GraphNode, GraphNodeKey, GraphNodeShape};
 use crate::graphics::DocumentGraphics;
 use crate::grid::Grid;
-use crate::math::{closest_point_on_segment, Rect};
+use crate::math::{bezier_point, closest_point_on_segment, Rect};
 use crate::plant::{Plant, PlantKey};
 use crate::sdf::sd_segment;
 use crate::some_or::some_or;
+use crate::symmetry::Symmetry;
 use crate::zone::ZoneRef;
 use slotmap::SlotMap;
 
@@ -23,13 +25,46 @@ new_key_type! {
     pub struct GridKey;
     pub struct FieldKey;
     pub struct LayerKey;
+    pub struct LayerGroupKey;
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Layer {
     #[serde(default)]
     pub grid: GridKey,
     pub hidden: bool,
+    /// Empty for layers of older documents, see `Layer::label`
+    #[serde(default)]
+    pub name: String,
+    /// Locked layers can not be painted or selected
+    #[serde(default)]
+    pub locked: bool,
+    /// Opacity in the editor view, exported maps are always opaque
+    #[serde(default = "Layer::default_opacity")]
+    pub opacity: f32,
+}
+
+/// Group of layers that are shown, hidden and locked together
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LayerGroup {
+    pub name: String,
+    pub hidden: bool,
+    pub locked: bool,
+    /// Children are not listed in the layer list
+    pub collapsed: bool,
+    /// From bottom to top, same as `Document::layer_order`
+    pub children: Vec<LayerEntry>,
+}
+
+/// Element of the layer tree
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LayerEntry {
+    /// Goes first: plain layer keys of older documents do not have the `group` field
+    Group {
+        group: LayerGroupKey,
+    },
+    Layer(LayerKey),
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
@@ -65,22 +100,34 @@ impl PartialOrd for Vec2Ord {
 pub enum SelectRef {
     Node(GraphNodeKey),
     NodeRadius(GraphNodeKey),
+    /// Index into the points of `GraphNodeShape::Polygon`
+    NodeVertex(GraphNodeKey, usize),
     Edge(GraphEdgeKey),
+    /// Position along the curve of the edge
     EdgePoint(GraphEdgeKey, NotNan<f32>),
+    /// Index into `GraphEdge::control_points`
+    EdgeControl(GraphEdgeKey, usize),
     Plant(PlantKey),
     PlantDirection(PlantKey),
     Point(Vec2Ord),
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Document {
     pub materials: Vec<MaterialSlot>,
+    /// Outline widths of materials in world units, indexed by slot. Default width is used
+    /// when not set.
+    #[serde(default)]
+    pub outline_widths: Vec<Option<f32>>,
     pub cell_size: i32,
 
     #[serde(default, rename = "layer_map")]
     pub layers: SlotMap<LayerKey, Layer>,
+    /// Top level of the layer tree, from bottom to top
+    #[serde(default)]
+    pub layer_order: Vec<LayerEntry>,
     #[serde(default)]
-    pub layer_order: Vec<LayerKey>,
+    pub layer_groups: SlotMap<LayerGroupKey, LayerGroup>,
 
     #[serde(default)]
     pub current_layer: LayerKey,
@@ -101,8 +148,10 @@ pub struct Document {
     pub reference_scale: i32,
     pub show_reference: bool,
 
+    /// Grids are shared with generation jobs and copied only when edited while shared,
+    /// use `Arc::make_mut` to change them.
     #[serde(default)]
-    pub grids: SlotMap<GridKey, Grid<u8>>,
+    pub grids: SlotMap<GridKey, Arc<Grid<u8>>>,
 
     #[serde(default)]
     pub selected: Vec<SelectRef>,
@@ -112,6 +161,9 @@ pub struct Document {
     pub edges: SlotMap<GraphEdgeKey, GraphEdge>,
     #[serde(default)]
     pub plants: SlotMap<PlantKey, Plant>,
+
+    #[serde(default)]
+    pub symmetry: Symmetry,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -134,9 +186,14 @@ pub struct DocumentLocalState {
     pub active_material: u8,
 }
 
-#[derive(Default, Copy, Clone, PartialEq)]
+#[derive(Default, Clone, PartialEq)]
 pub struct ChangeMask {
-    pub cell_layers: u64,
+    pub cell_layers: HashSet<LayerKey>,
+    /// Every layer has changed, including removed ones
+    pub all_layers: bool,
+    /// World-space rectangle that contains all changes of `cell_layers`,
+    /// `None` when whole layers have to be regenerated.
+    pub cell_rect: Option<[Vec2; 2]>,
     pub reference_path: bool,
 }
 
@@ -144,11 +201,9 @@ impl Document {
     pub fn new() -> Document {
         let grids = SlotMap::with_key();
         let mut layers = SlotMap::with_key();
-        let current_layer = layers.insert(Layer {
-            hidden: false,
-            grid: GridKey::default(),
-        });
-        let layer_order = vec![current_layer];
+        let current_layer: LayerKey = layers.insert(Layer::new());
+        layers[current_layer].name = current_layer.label();
+        let layer_order = vec![LayerEntry::Layer(current_layer)];
         Document {
             reference_path: None,
             reference_scale: 2,
@@ -160,6 +215,7 @@ impl Document {
             },
             selected: vec![],
             layer_order,
+            layer_groups: SlotMap::with_key(),
             layers,
             materials: vec![
                 MaterialSlot::None,
@@ -170,6 +226,7 @@ impl Document {
                 MaterialSlot::BuiltIn(BuiltinMaterial::Bumper),
                 MaterialSlot::BuiltIn(BuiltinMaterial::Finish),
             ],
+            outline_widths: Vec::new(),
             side_load: HashMap::new(),
             markup: MapMarkup::new(),
             zone_selection: None,
@@ -179,13 +236,14 @@ impl Document {
             edges: SlotMap::with_key(),
             nodes: SlotMap::with_key(),
             plants: SlotMap::with_key(),
+            symmetry: Symmetry::default(),
         }
     }
     pub fn pre_save_cleanup(&mut self) {
         for layer in self.grids.values_mut() {
             let bounds = layer.find_used_bounds().inflate(1);
             if bounds != layer.bounds {
-                layer.resize(bounds);
+                Arc::make_mut(layer).resize(bounds);
             }
         }
     }
@@ -229,10 +287,176 @@ impl Document {
         Ok((materials_png, materials_json))
     }
 
+    /// Layers of the tree from bottom to top
+    pub fn flat_layers(&self) -> Vec<LayerKey> {
+        fn add(doc: &Document, entries: &[LayerEntry], result: &mut Vec<LayerKey>) {
+            for &entry in entries {
+                match entry {
+                    LayerEntry::Layer(layer_key) => result.push(layer_key),
+                    LayerEntry::Group { group } => {
+                        if let Some(group) = doc.layer_groups.get(group) {
+                            add(doc, &group.children, result);
+                        }
+                    }
+                }
+            }
+        }
+        let mut result = Vec::new();
+        add(self, &self.layer_order, &mut result);
+        result
+    }
+
+    /// Entries of `parent` group, or of the top level
+    pub fn layer_entries(&self, parent: Option<LayerGroupKey>) -> &[LayerEntry] {
+        match parent.and_then(|parent| self.layer_groups.get(parent)) {
+            Some(group) => &group.children,
+            None => &self.layer_order,
+        }
+    }
+
+    fn layer_entries_mut(&mut self, parent: Option<LayerGroupKey>) -> &mut Vec<LayerEntry> {
+        match parent.and_then(|parent| self.layer_groups.get_mut(parent)) {
+            Some(group) => &mut group.children,
+            None => &mut self.layer_order,
+        }
+    }
+
+    /// Parent group and index of `entry`
+    pub fn find_layer_entry(&self, entry: LayerEntry) -> Option<(Option<LayerGroupKey>, usize)> {
+        if let Some(index) = self.layer_order.iter().position(|e| *e == entry) {
+            return Some((None, index));
+        }
+        self.layer_groups.iter().find_map(|(group_key, group)| {
+            let index = group.children.iter().position(|e| *e == entry)?;
+            Some((Some(group_key), index))
+        })
+    }
+
+    /// Groups that contain `entry`, starting with the innermost one
+    pub fn layer_entry_ancestors(&self, entry: LayerEntry) -> Vec<LayerGroupKey> {
+        let mut result = Vec::new();
+        let mut entry = entry;
+        while let Some((Some(parent), _)) = self.find_layer_entry(entry) {
+            if result.contains(&parent) {
+                break;
+            }
+            result.push(parent);
+            entry = LayerEntry::Group { group: parent };
+        }
+        result
+    }
+
+    pub fn insert_layer_entry(
+        &mut self,
+        parent: Option<LayerGroupKey>,
+        index: usize,
+        entry: LayerEntry,
+    ) {
+        let entries = self.layer_entries_mut(parent);
+        entries.insert(index.min(entries.len()), entry);
+    }
+
+    pub fn remove_layer_entry(
+        &mut self,
+        entry: LayerEntry,
+    ) -> Option<(Option<LayerGroupKey>, usize)> {
+        let (parent, index) = self.find_layer_entry(entry)?;
+        self.layer_entries_mut(parent).remove(index);
+        Some((parent, index))
+    }
+
+    /// Moves `entry` to `index` of `parent`, where `index` is counted before the removal.
+    /// Groups can not be moved into themselves.
+    pub fn move_layer_entry(
+        &mut self,
+        entry: LayerEntry,
+        parent: Option<LayerGroupKey>,
+        mut index: usize,
+    ) {
+        if let (LayerEntry::Group { group }, Some(parent)) = (entry, parent) {
+            let parent_entry = LayerEntry::Group { group: parent };
+            if group == parent || self.layer_entry_ancestors(parent_entry).contains(&group) {
+                return;
+            }
+        }
+        let Some((old_parent, old_index)) = self.remove_layer_entry(entry) else { return };
+        if old_parent == parent && old_index < index {
+            index -= 1;
+        }
+        self.insert_layer_entry(parent, index, entry);
+    }
+
+    /// Replaces `entry` with a new group that contains it
+    pub fn group_layer_entry(&mut self, entry: LayerEntry) -> Option<LayerGroupKey> {
+        let (parent, index) = self.remove_layer_entry(entry)?;
+        let group_key = self.layer_groups.insert(LayerGroup {
+            name: String::new(),
+            hidden: false,
+            locked: false,
+            collapsed: false,
+            children: vec![entry],
+        });
+        self.layer_groups[group_key].name =
+            format!("Group {}", group_key.data().as_ffi() & 0xffffffff);
+        self.insert_layer_entry(parent, index, LayerEntry::Group { group: group_key });
+        Some(group_key)
+    }
+
+    /// Removes the group, keeping its children in place
+    pub fn ungroup_layers(&mut self, group_key: LayerGroupKey) {
+        let entry = LayerEntry::Group { group: group_key };
+        let Some((parent, index)) = self.remove_layer_entry(entry) else { return };
+        let Some(group) = self.layer_groups.remove(group_key) else { return };
+        self.layer_entries_mut(parent)
+            .splice(index..index, group.children);
+    }
+
+    pub fn is_layer_hidden(&self, layer_key: LayerKey) -> bool {
+        self.layers.get(layer_key).is_some_and(|layer| layer.hidden)
+            || self
+                .layer_entry_ancestors(LayerEntry::Layer(layer_key))
+                .into_iter()
+                .any(|group| self.layer_groups[group].hidden)
+    }
+
+    /// Layers are locked together with their groups
+    pub fn is_layer_locked(&self, layer_key: LayerKey) -> bool {
+        self.layers.get(layer_key).is_some_and(|layer| layer.locked)
+            || self
+                .layer_entry_ancestors(LayerEntry::Layer(layer_key))
+                .into_iter()
+                .any(|group| self.layer_groups[group].locked)
+    }
+
+    /// Elements of locked layers can not be selected
+    pub fn is_selectable(&self, select_ref: SelectRef) -> bool {
+        let node_selectable = |key| {
+            self.nodes
+                .get(key)
+                .is_none_or(|node| !self.is_layer_locked(node.layer))
+        };
+        match select_ref {
+            SelectRef::Node(key) | SelectRef::NodeRadius(key) | SelectRef::NodeVertex(key, _) => {
+                node_selectable(key)
+            }
+            SelectRef::Edge(key)
+            | SelectRef::EdgePoint(key, _)
+            | SelectRef::EdgeControl(key, _) => self
+                .edges
+                .get(key)
+                .is_none_or(|edge| node_selectable(edge.start) && node_selectable(edge.end)),
+            SelectRef::Plant(key) | SelectRef::PlantDirection(key) => self
+                .plants
+                .get(key)
+                .is_none_or(|plant| !self.is_layer_locked(plant.layer)),
+            SelectRef::Point(_) => true,
+        }
+    }
+
     pub(crate) fn get_or_add_layer_grid(
         layers: &mut SlotMap<LayerKey, Layer>,
         layer_key: LayerKey,
-        grids: &mut SlotMap<GridKey, Grid<u8>>,
+        grids: &mut SlotMap<GridKey, Arc<Grid<u8>>>,
     ) -> GridKey {
         let grid_key = layers
             .get(layer_key)
@@ -242,7 +466,7 @@ impl Document {
             grid_key
         } else {
             if let Some(layer) = layers.get_mut(layer_key) {
-                let grid_key = grids.insert(Grid::new(0));
+                let grid_key = grids.insert(Arc::new(Grid::new(0)));
                 layer.grid = grid_key;
                 grid_key
             } else {
@@ -253,10 +477,14 @@ impl Document {
 
     pub fn hit_test(&self, screen_pos: Vec2, view: &View) -> Option<SelectRef> {
         let world_to_screen = view.world_to_screen();
+        let screen_to_world = view.screen_to_world();
         let mut result = None;
         let mut best_distance = f32::MAX;
         let mut outside_distance = f32::MAX;
         for (key, plant) in &self.plants {
+            if self.is_layer_locked(plant.layer) {
+                continue;
+            }
             let node_screen_pos = world_to_screen.transform_point2(plant.pos.as_vec2());
 
             let distance = (node_screen_pos - screen_pos).length();
@@ -276,38 +504,85 @@ impl Document {
             }
         }
         for (key, node) in &self.nodes {
+            if self.is_layer_locked(node.layer) {
+                continue;
+            }
             let node_screen_pos = world_to_screen.transform_point2(node.pos.as_vec2());
 
-            let screen_radius = world_to_screen
-                .transform_vector2(vec2(node.radius as f32, 0.0))
-                .x;
+            let screen_scale = world_to_screen.transform_vector2(vec2(1.0, 0.0)).x;
+            let shape_distance = node.distance(screen_to_world.transform_point2(screen_pos));
+            let shape_screen_distance = shape_distance * screen_scale;
             let distance = (node_screen_pos - screen_pos).length();
-            if distance < screen_radius + 16.0 && distance < best_distance {
+            if shape_screen_distance < 16.0 && distance < best_distance {
                 result = Some(SelectRef::Node(key));
                 best_distance = distance;
-                outside_distance = distance - screen_radius;
+                outside_distance = shape_screen_distance;
             }
 
-            let radius_screen_pos = world_to_screen
-                .transform_point2(node.pos.as_vec2() + vec2(0.0, node.radius as f32));
+            let radius_screen_pos = world_to_screen.transform_point2(node.radius_handle());
             let distance = ((radius_screen_pos - screen_pos).length() - 8.0).max(0.0);
             if distance < 8.0 && distance < best_distance {
                 result = Some(SelectRef::NodeRadius(key));
                 best_distance = distance;
                 outside_distance = distance;
             }
+
+            if let GraphNodeShape::Polygon { .. } = node.shape {
+                for (index, corner) in node.corners().into_iter().enumerate() {
+                    let corner_screen = world_to_screen.transform_point2(corner);
+                    let distance = ((corner_screen - screen_pos).length() - 8.0).max(0.0);
+                    if distance < 8.0 && distance < best_distance {
+                        result = Some(SelectRef::NodeVertex(key, index));
+                        best_distance = distance;
+                        outside_distance = distance;
+                    }
+                }
+            }
         }
 
         for (key, edge) in &self.edges {
-            let start = self
-                .nodes
-                .get(edge.start)
-                .map(|n| (n.pos.as_vec2(), n.radius as f32));
-            let end = self
-                .nodes
-                .get(edge.end)
-                .map(|n| (n.pos.as_vec2(), n.radius as f32));
-            if let Some(((start, start_r), (end, end_r))) = start.zip(end) {
+            if !self.is_selectable(SelectRef::Edge(key)) {
+                continue;
+            }
+            for (index, point) in edge.control_points.iter().enumerate() {
+                let point_screen = world_to_screen.transform_point2(point.as_vec2());
+                let distance = ((point_screen - screen_pos).length() - 8.0).max(0.0);
+                if distance < 8.0 && distance < best_distance {
+                    result = Some(SelectRef::EdgeControl(key, index));
+                    best_distance = distance;
+                    outside_distance = distance;
+                }
+            }
+        }
+
+        for (key, edge) in &self.edges {
+            if !self.is_selectable(SelectRef::Edge(key)) {
+                continue;
+            }
+            if !edge.control_points.is_empty() {
+                let Some(segments) = edge.segments(&self.nodes) else { continue };
+                for segment in &segments {
+                    let start_screen = world_to_screen.transform_point2(segment.start);
+                    let end_screen = world_to_screen.transform_point2(segment.end);
+                    let r_screen = world_to_screen
+                        .transform_vector2(vec2(segment.start_radius.min(segment.end_radius), 0.0))
+                        .x;
+                    let dist = sd_segment(screen_pos, start_screen, end_screen);
+                    if dist < best_distance
+                        && dist <= r_screen
+                        && !(matches!(result, Some(SelectRef::Node(_))) && outside_distance < 0.0)
+                    {
+                        result = Some(SelectRef::Edge(key));
+                        best_distance = dist;
+                        outside_distance = dist;
+                    }
+                }
+                continue;
+            }
+            let start = self.nodes.get(edge.start).map(|n| n.pos.as_vec2());
+            let end = self.nodes.get(edge.end).map(|n| n.pos.as_vec2());
+            let radii = edge.radii(&self.nodes);
+            if let Some(((start, end), [start_r, end_r])) = start.zip(end).zip(radii) {
                 let start_screen = world_to_screen.transform_point2(start);
                 let end_screen = world_to_screen.transform_point2(end);
                 let start_r_screen = world_to_screen.transform_vector2(vec2(start_r, 0.0)).x;
@@ -318,12 +593,7 @@ impl Document {
                     // give nodes priority, but only within their radius
                     && !(matches!(result, Some(SelectRef::Node(_))) && outside_distance < 0.0)
                 {
-                    let (_, position_on_segment) =
-                        closest_point_on_segment(start_screen, end_screen, screen_pos);
-                    result = Some(SelectRef::EdgePoint(
-                        key,
-                        NotNan::new(position_on_segment).unwrap(),
-                    ));
+                    result = Some(SelectRef::Edge(key));
                     best_distance = dist;
                     outside_distance = dist;
                 }
@@ -332,6 +602,26 @@ impl Document {
         result
     }
 
+    /// Curve position of the point of the edge that is closest to `world_pos`
+    pub fn edge_position(&self, key: GraphEdgeKey, world_pos: Vec2) -> Option<NotNan<f32>> {
+        let edge = self.edges.get(key)?;
+        let positions = edge.segment_positions(&self.nodes);
+        let points: Vec<Vec2> = positions
+            .iter()
+            .map(|&t| edge.point(&self.nodes, t))
+            .collect::<Option<_>>()?;
+        let (_, position) = points
+            .windows(2)
+            .zip(positions.windows(2))
+            .filter_map(|(p, t)| {
+                let (closest, fraction) = closest_point_on_segment(p[0], p[1], world_pos);
+                let distance = NotNan::new((closest - world_pos).length()).ok()?;
+                Some((distance, t[0] + (t[1] - t[0]) * fraction))
+            })
+            .min_by_key(|&(distance, _)| distance)?;
+        NotNan::new(position).ok()
+    }
+
     pub fn draw_selectable(
         &self,
         batch: &mut MiniquadBatch<VertexPos3UvColor>,
@@ -354,9 +644,6 @@ impl Document {
 
         for (key, node) in &self.nodes {
             let pos_screen = world_to_screen.transform_point2(node.pos.as_vec2());
-            let screen_radius = world_to_screen
-                .transform_vector2(vec2(node.radius as f32, 0.0))
-                .x;
 
             let (color, thickness) = colorize(SelectRef::Node(key));
             batch
@@ -365,11 +652,31 @@ impl Document {
 
             let (color, thickness) = colorize(SelectRef::NodeRadius(key));
             batch.geometry.fill_circle_aa(
-                pos_screen + vec2(0.0, screen_radius),
+                world_to_screen.transform_point2(node.radius_handle()),
                 3.0 + thickness,
                 12,
                 color,
             );
+
+            if let GraphNodeShape::Polygon { .. } = node.shape {
+                let corners: Vec<Vec2> = node
+                    .corners()
+                    .into_iter()
+                    .map(|c| world_to_screen.transform_point2(c))
+                    .collect();
+                for (index, &corner) in corners.iter().enumerate() {
+                    let next = corners[(index + 1) % corners.len()];
+                    batch
+                        .geometry
+                        .stroke_line_aa(corner, next, 1.0, [128, 128, 128, 128]);
+                }
+                for (index, &corner) in corners.iter().enumerate() {
+                    let (color, thickness) = colorize(SelectRef::NodeVertex(key, index));
+                    batch
+                        .geometry
+                        .fill_circle_aa(corner, 3.0 + thickness, 12, color);
+                }
+            }
         }
 
         for (key, edge) in &self.edges {
@@ -381,6 +688,49 @@ impl Document {
                 .nodes
                 .get(edge.end)
                 .map(|n| (n.pos.as_vec2(), n.radius as f32));
+            if !edge.control_points.is_empty() {
+                let Some(segments) = edge.segments(&self.nodes) else { continue };
+                let Some(((pos_a, r_a), (pos_b, r_b))) = a.zip(b) else { continue };
+                let (color, thickness) = colorize(SelectRef::Edge(key));
+                // parts within nodes are not drawn, same as for straight edges
+                for segment in segments {
+                    if segment.start.distance(pos_a) < r_a && segment.end.distance(pos_a) < r_a
+                        || segment.start.distance(pos_b) < r_b && segment.end.distance(pos_b) < r_b
+                    {
+                        continue;
+                    }
+                    batch.geometry.stroke_line_aa(
+                        world_to_screen.transform_point2(segment.start),
+                        world_to_screen.transform_point2(segment.end),
+                        thickness,
+                        color,
+                    );
+                }
+
+                // first and last control points are connected to the ends of the curve
+                let last_index = edge.control_points.len() - 1;
+                for (index, point) in edge.control_points.iter().enumerate() {
+                    let point_screen = world_to_screen.transform_point2(point.as_vec2());
+                    for (connected, end) in [(index == 0, pos_a), (index == last_index, pos_b)] {
+                        if connected {
+                            batch.geometry.stroke_line_aa(
+                                point_screen,
+                                world_to_screen.transform_point2(end),
+                                1.0,
+                                [128, 128, 128, 128],
+                            );
+                        }
+                    }
+                }
+                for (index, point) in edge.control_points.iter().enumerate() {
+                    let point_screen = world_to_screen.transform_point2(point.as_vec2());
+                    let (color, thickness) = colorize(SelectRef::EdgeControl(key, index));
+                    batch
+                        .geometry
+                        .fill_circle_aa(point_screen, 3.0 + thickness, 12, color);
+                }
+                continue;
+            }
             if let Some(((pos_a, r_a), (pos_b, r_b))) = a.zip(b) {
                 let a_to_b = pos_b - pos_a;
                 if a_to_b.length() > r_a + r_b {
@@ -422,12 +772,15 @@ impl Document {
             match selection {
                 SelectRef::EdgePoint(key, pos) => {
                     let edge = some_or!(self.edges.get(key), continue);
-                    let start = some_or!(self.nodes.get(edge.start), continue);
-                    let end = some_or!(self.nodes.get(edge.end), continue);
-                    let screen_a = world_to_screen.transform_point2(start.pos.as_vec2());
-                    let screen_b = world_to_screen.transform_point2(end.pos.as_vec2());
-                    let pos = screen_a.lerp(screen_b, *pos);
-                    let n = (screen_b - screen_a).perp().normalize_or_zero();
+                    let points = some_or!(edge.curve_points(&self.nodes), continue);
+                    let t = *pos;
+                    let pos = world_to_screen.transform_point2(bezier_point(&points, t));
+                    let tangent = bezier_point(&points, (t + 0.01).min(1.0))
+                        - bezier_point(&points, (t - 0.01).max(0.0));
+                    let n = world_to_screen
+                        .transform_vector2(tangent)
+                        .perp()
+                        .normalize_or_zero();
                     let (color, thickness) = colorize(selection);
                     batch
                         .geometry
@@ -452,6 +805,125 @@ impl Document {
                 _ => {}
             }
         }
+
+        if !self.selection.bounds.is_null() {
+            let cell_size = self.cell_size as f32;
+            batch.geometry.stroke_rect(
+                world_to_screen.transform_point2(self.selection.bounds[0].as_vec2() * cell_size),
+                world_to_screen.transform_point2(self.selection.bounds[1].as_vec2() * cell_size),
+                1.0,
+                [0, 128, 255, 255],
+            );
+        }
+    }
+
+    /// Cells of the current layer covered by `selection` mask, unselected cells are zero
+    pub fn selected_cells(&self) -> Option<Grid<u8>> {
+        let layer = self.layers.get(self.current_layer)?;
+        let grid = self.grids.get(layer.grid)?;
+        let bounds = self.selection.bounds.intersect(grid.bounds)?;
+        if bounds.is_null() {
+            return None;
+        }
+        let mut result = Grid::new(0);
+        result.resize(bounds);
+        let mut any_selected = false;
+        for y in bounds[0].y..bounds[1].y {
+            for x in bounds[0].x..bounds[1].x {
+                if self.selection.cells[self.selection.grid_pos_index(x, y)] == 0 {
+                    continue;
+                }
+                any_selected = true;
+                let index = result.grid_pos_index(x, y);
+                result.cells[index] = grid.cells[grid.grid_pos_index(x, y)];
+            }
+        }
+        if !any_selected {
+            return None;
+        }
+        Some(result)
+    }
+
+    /// Returns true if any of the current layer cells were changed
+    pub fn clear_selected_cells(&mut self) -> bool {
+        let Some(layer) = self.layers.get(self.current_layer) else { return false };
+        let Some(grid) = self.grids.get_mut(layer.grid) else { return false };
+        let Some(bounds) = self.selection.bounds.intersect(grid.bounds) else { return false };
+        let grid = Arc::make_mut(grid);
+        let mut changed = false;
+        for y in bounds[0].y..bounds[1].y {
+            for x in bounds[0].x..bounds[1].x {
+                if self.selection.cells[self.selection.grid_pos_index(x, y)] == 0 {
+                    continue;
+                }
+                let index = grid.grid_pos_index(x, y);
+                if grid.cells[index] != 0 {
+                    grid.cells[index] = 0;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// World-space bounds of the nodes along with their edges
+    pub fn nodes_bounds(&self, keys: &[GraphNodeKey]) -> [Vec2; 2] {
+        let mut bounds: [Vec2; 2] = Rect::invalid();
+        if keys.is_empty() {
+            return bounds;
+        }
+        for &key in keys {
+            let Some(node) = self.nodes.get(key) else { continue };
+            bounds = bounds.union(node.bounds());
+        }
+        for edge in self.edges.values() {
+            if !keys.contains(&edge.start) && !keys.contains(&edge.end) {
+                continue;
+            }
+            let Some(edge_bounds) = edge.bounds(&self.nodes) else { continue };
+            bounds = bounds.union(edge_bounds);
+        }
+        bounds
+    }
+
+    /// World-space bounds of selected nodes, edges and plants, including edges that are
+    /// connected to selected nodes.
+    pub fn selected_bounds(&self) -> [Vec2; 2] {
+        let mut bounds: [Vec2; 2] = Rect::invalid();
+        let mut selected_nodes = Vec::new();
+        for selection in &self.selected {
+            match *selection {
+                SelectRef::Node(key)
+                | SelectRef::NodeRadius(key)
+                | SelectRef::NodeVertex(key, _) => {
+                    let Some(node) = self.nodes.get(key) else { continue };
+                    bounds = bounds.union(node.bounds());
+                    selected_nodes.push(key);
+                }
+                SelectRef::Edge(key)
+                | SelectRef::EdgePoint(key, _)
+                | SelectRef::EdgeControl(key, _) => {
+                    let Some(edge) = self.edges.get(key) else { continue };
+                    let Some(edge_bounds) = edge.bounds(&self.nodes) else { continue };
+                    bounds = bounds.union(edge_bounds);
+                }
+                SelectRef::Plant(key) | SelectRef::PlantDirection(key) => {
+                    let Some(plant) = self.plants.get(key) else { continue };
+                    bounds = bounds.union(plant.bounds());
+                }
+                SelectRef::Point(_) => {}
+            }
+        }
+        if !selected_nodes.is_empty() {
+            for edge in self.edges.values() {
+                if !selected_nodes.contains(&edge.start) && !selected_nodes.contains(&edge.end) {
+                    continue;
+                }
+                let Some(edge_bounds) = edge.bounds(&self.nodes) else { continue };
+                bounds = bounds.union(edge_bounds);
+            }
+        }
+        bounds
     }
 
     pub fn snap_to_grid(pos: Vec2, snap_step: i32) -> Vec2 {
@@ -475,22 +947,111 @@ impl View {
 
 impl App {
     pub fn push_undo(&self, text: &str) {
-        if *self.undo_saved_position.borrow() > self.undo.borrow().records.len() {
+        let position = self.undo.borrow().position();
+        if *self.undo_saved_position.borrow() > position {
             // impossible to reach anymore
             self.undo_saved_position.replace(usize::MAX);
         }
-        let err = self.undo.borrow_mut().push(&self.doc, text);
+        // saved state should stay reachable, so edits after saving are not merged
+        let allow_merge = *self.undo_saved_position.borrow() != position;
+        // changes made earlier in this frame belong to the previous record
+        self.undo.borrow_mut().mark_changes(&self.dirty_mask);
+        let err = self.undo.borrow_mut().push(&self.doc, text, allow_merge);
         self.redo.borrow_mut().clear();
         self.report_error(err);
     }
+
+    /// Undoes or redoes records until undo stack reaches `position`.
+    pub fn jump_to_undo_position(&mut self, position: usize) {
+        self.undo.borrow_mut().mark_changes(&self.dirty_mask);
+        loop {
+            let current = self.undo.borrow().position();
+            let result = if current > position && !self.undo.borrow().is_empty() {
+                self.undo
+                    .borrow_mut()
+                    .apply(&mut self.doc, &mut self.redo.borrow_mut())
+            } else if current < position && !self.redo.borrow().is_empty() {
+                self.redo
+                    .borrow_mut()
+                    .apply(&mut self.doc, &mut self.undo.borrow_mut())
+            } else {
+                break;
+            };
+            let Some(changes) = self.report_error(result) else { break };
+            self.dirty_mask.merge(changes);
+        }
+    }
 }
 
 impl ChangeMask {
+    pub fn has_cell_changes(&self) -> bool {
+        self.all_layers || !self.cell_layers.is_empty()
+    }
+
     pub fn mark_dirty_layer(&mut self, layer_key: LayerKey) {
-        let layer_index = layer_key.data().as_ffi() & 0xffffffff;
-        let bit_index = layer_index.min(63);
-        let bit = 1 << bit_index;
-        self.cell_layers |= bit;
+        self.cell_layers.insert(layer_key);
+        self.cell_rect = None;
+    }
+
+    /// Marks a part of the layer as changed, `rect` has to contain the changed area both
+    /// before and after the change.
+    pub fn mark_dirty_rect(&mut self, layer_key: LayerKey, rect: [Vec2; 2]) {
+        if !rect.is_valid() {
+            return;
+        }
+        if !self.has_cell_changes() {
+            self.cell_rect = Some(rect);
+        } else if let Some(cell_rect) = &mut self.cell_rect {
+            *cell_rect = cell_rect.union(rect);
+        }
+        self.cell_layers.insert(layer_key);
+    }
+
+    pub fn mark_dirty_cells(&mut self, layer_key: LayerKey, cells: [IVec2; 2], cell_size: i32) {
+        let cell_size = cell_size as f32;
+        let rect = [
+            cells[0].as_vec2() * cell_size,
+            cells[1].as_vec2() * cell_size,
+        ];
+        self.mark_dirty_rect(layer_key, rect);
+    }
+
+    /// Adds changes of `other`, as if they were marked on this mask
+    pub fn merge(&mut self, other: ChangeMask) {
+        if other.has_cell_changes() {
+            self.cell_rect = match (self.has_cell_changes(), self.cell_rect, other.cell_rect) {
+                (false, _, rect) => rect,
+                (true, Some(a), Some(b)) => Some(a.union(b)),
+                _ => None,
+            };
+            self.cell_layers.extend(other.cell_layers);
+            self.all_layers |= other.all_layers;
+        }
+        self.reference_path |= other.reference_path;
+    }
+}
+
+impl Layer {
+    pub fn new() -> Layer {
+        Layer {
+            grid: GridKey::default(),
+            hidden: false,
+            name: String::new(),
+            locked: false,
+            opacity: 1.0,
+        }
+    }
+
+    fn default_opacity() -> f32 {
+        1.0
+    }
+
+    pub fn label(&self, layer_key: LayerKey) -> String {
+        if self.name.is_empty() {
+            layer_key.label()
+        } else {
+            self.name.clone()
+        }
     }
 }
 
@@ -0,0 +1,58 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use log::info;
+
+use crate::app::App;
+use crate::document::{ChangeMask, Document};
+use crate::graphics::DocumentGraphics;
+use crate::profiler::Profiler;
+
+pub const EXPORT_USAGE: &str = "Usage: editor export <input.cbmap> <output.cbmap>";
+
+/// Entry point of `editor export` subcommand, `args` exclude the subcommand name
+pub fn run_export(args: &[String]) -> Result<()> {
+    let (input, output) = match args {
+        [input, output] => (Path::new(input), Path::new(output)),
+        _ => bail!("{}", EXPORT_USAGE),
+    };
+    export_map(input, output)
+}
+
+/// Regenerates `main.png`, `materials.png`, `materials.json` and `map.json` of a map
+/// without opening a window.
+pub fn export_map(input: &Path, output: &Path) -> Result<()> {
+    let mut doc =
+        App::load_doc(input).with_context(|| format!("Loading {}", input.to_string_lossy()))?;
+    doc.pre_save_cleanup();
+
+    let graphics = generate_graphics(&doc);
+
+    let (image, image_bounds) = graphics.render_map_image_cpu(&doc);
+    App::write_map_archive(output, &doc, &graphics, &image, image_bounds)
+        .with_context(|| format!("Writing {}", output.to_string_lossy()))?;
+    info!(
+        "Exported {} to {}",
+        input.to_string_lossy(),
+        output.to_string_lossy()
+    );
+    Ok(())
+}
+
+/// Generates distance field of every layer, including hidden ones, as it goes into the map
+pub fn generate_graphics(doc: &Document) -> DocumentGraphics {
+    let mut graphics = DocumentGraphics::new();
+    let mut profiler = Profiler::new();
+    profiler.begin_frame();
+    graphics.generate(
+        doc,
+        &ChangeMask {
+            all_layers: true,
+            ..ChangeMask::default()
+        },
+        true,
+        None,
+        &mut profiler,
+    );
+    graphics
+}
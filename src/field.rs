@@ -11,7 +11,7 @@ use rayon::iter::{
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Field {
     pub tile_size: usize,
     pub materials: Vec<HashMap<(i32, i32), Vec<f32>>>,
@@ -25,17 +25,37 @@ impl Field {
         }
     }
 
-    pub fn from_grid(grid: &Grid<u8>, num_materials: usize, cell_size: i32) -> Field {
+    /// Computes distances for tiles within `tile_range`, or for the whole grid when the
+    /// range is omitted. Distances depend on every cell of the grid, so all of them are
+    /// processed either way.
+    pub fn from_grid(
+        grid: &Grid<u8>,
+        num_materials: usize,
+        cell_size: i32,
+        tile_range: Option<[IVec2; 2]>,
+    ) -> Field {
         let _span = span!("Field::from_grid");
         let mut field = Field::new();
         let tile_size = field.tile_size as i32;
         field.materials.push(Default::default());
+
+        // distances are computed for upscaled grid, its cells match cells of the field
+        let bounds = [grid.bounds[0] * 2, grid.bounds[1] * 2];
+        let grid_tiles = Field::grid_to_tile_range(bounds, tile_size as usize);
+        let tile_range = match tile_range {
+            Some(tile_range) => grid_tiles.intersect(tile_range).unwrap_or(Rect::zero()),
+            None => grid_tiles,
+        };
+        let grid = upscale_epx(grid);
+
         field
             .materials
             .par_extend((1..num_materials).into_par_iter().map(|material_index| {
                 let mut tiles = HashMap::new();
+                if tile_range.is_null() {
+                    return tiles;
+                }
 
-                let grid = upscale_epx(grid);
                 let w = grid.bounds[1].x - grid.bounds[0].x;
                 let h = grid.bounds[1].y - grid.bounds[0].y;
 
@@ -61,10 +81,6 @@ impl Field {
                     }
                 }
 
-                let bounds = [grid.bounds[0], grid.bounds[1]];
-                let w = bounds[1].x - bounds[0].x;
-                let tile_range = Field::grid_to_tile_range(bounds, tile_size as usize);
-
                 // split distances into tiles
                 for tile_y in tile_range[0].y..tile_range[1].y {
                     for tile_x in tile_range[0].x..tile_range[1].x {
@@ -122,6 +138,18 @@ impl Field {
         });
     }
 
+    /// Distance stored in the cell that contains `world_pos`, `f32::MAX` outside of tiles
+    pub(crate) fn distance_at(&self, material: usize, world_pos: Vec2, cell_size: i32) -> f32 {
+        let tiles = some_or!(self.materials.get(material), return f32::MAX);
+        let tile_size = self.tile_size as i32;
+        let cell = (world_pos / cell_size as f32).floor().as_ivec2();
+        let tile_key = (cell.x.div_euclid(tile_size), cell.y.div_euclid(tile_size));
+        let tile = some_or!(tiles.get(&tile_key), return f32::MAX);
+        let tx = cell.x & (tile_size - 1);
+        let ty = cell.y & (tile_size - 1);
+        tile[(ty * tile_size + tx) as usize]
+    }
+
     pub(crate) fn grid_to_tile_range(tile_rect: [IVec2; 2], tile_size: usize) -> [IVec2; 2] {
         [
             ivec2(
@@ -0,0 +1,378 @@
+use std::collections::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc::{channel, Receiver, Sender};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+
+#[cfg(not(target_arch = "wasm32"))]
+use cbmap::MapMarkup;
+use glam::{ivec2, IVec2, Vec2};
+use log::error;
+use miniquad::Context;
+
+use crate::document::{ChangeMask, Document};
+use crate::graphics::{DocumentGraphics, OpacityGroup};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::grid::Grid;
+use crate::math::Rect;
+use crate::profiler::{ProfileMarker, Profiler};
+use tracy_client::span;
+
+/// Tiles are generated and displayed in square chunks of this many tiles
+const CHUNK_TILES: i32 = 4;
+
+#[cfg(not(target_arch = "wasm32"))]
+struct GenerationJob {
+    id: u64,
+    /// Only the parts needed for generation, see `generation_snapshot`
+    doc: Document,
+    change_mask: ChangeMask,
+    /// Chunks closer to this world position are generated first
+    focus: Vec2,
+    cancel: Arc<AtomicBool>,
+}
+
+pub enum GenerationEvent {
+    /// Whole map is being regenerated, tiles outside of `extent` are not going to exist
+    Started { extent: [IVec2; 2] },
+    /// Tiles within `tile_range` are regenerated
+    Tiles {
+        tile_range: [IVec2; 2],
+        materials: Vec<HashMap<(i32, i32), Vec<f32>>>,
+    },
+    /// All changes up to and including job `id` are generated
+    Finished {
+        id: u64,
+        markers: Vec<(ProfileMarker, f64)>,
+    },
+}
+
+/// Generates distance tiles on a background thread, while the previous ones are still
+/// displayed. Falls back to generation on the main thread where threads are not available.
+pub struct GenerationWorker {
+    #[cfg(not(target_arch = "wasm32"))]
+    thread: Option<std::thread::JoinHandle<()>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    job_tx: Option<Sender<GenerationJob>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    event_rx: Option<Receiver<GenerationEvent>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    cancel: Arc<AtomicBool>,
+    last_job: u64,
+    finished_job: u64,
+}
+
+impl GenerationWorker {
+    pub fn new() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let (job_tx, job_rx) = channel();
+            let (event_tx, event_rx) = channel();
+            let thread = std::thread::Builder::new()
+                .name("generation".into())
+                .spawn(move || worker_thread(job_rx, event_tx));
+            match thread {
+                Ok(thread) => GenerationWorker {
+                    thread: Some(thread),
+                    job_tx: Some(job_tx),
+                    event_rx: Some(event_rx),
+                    cancel: Arc::new(AtomicBool::new(false)),
+                    last_job: 0,
+                    finished_job: 0,
+                },
+                Err(err) => {
+                    error!("Failed to start generation thread: {}", err);
+                    GenerationWorker {
+                        thread: None,
+                        job_tx: None,
+                        event_rx: None,
+                        cancel: Arc::new(AtomicBool::new(false)),
+                        last_job: 0,
+                        finished_job: 0,
+                    }
+                }
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        GenerationWorker {
+            last_job: 0,
+            finished_job: 0,
+        }
+    }
+
+    /// True while changes are being generated
+    pub fn is_pending(&self) -> bool {
+        self.finished_job != self.last_job
+    }
+
+    /// Starts generation of changes in `change_mask`, cancelling unfinished generation of
+    /// earlier changes.
+    pub fn start(
+        &mut self,
+        doc: &Document,
+        change_mask: ChangeMask,
+        focus: Vec2,
+        graphics: &mut DocumentGraphics,
+        context: &mut Context,
+        profiler: &mut Profiler,
+    ) {
+        if change_mask.reference_path {
+            graphics.generate(
+                doc,
+                &ChangeMask {
+                    reference_path: true,
+                    ..ChangeMask::default()
+                },
+                false,
+                Some(context),
+                profiler,
+            );
+        }
+        if !change_mask.has_cell_changes() {
+            return;
+        }
+        graphics.update_materials(doc);
+        graphics.opacity_groups = OpacityGroup::from_document(doc, false);
+        #[cfg(target_arch = "wasm32")]
+        let _ = focus;
+
+        #[cfg(not(target_arch = "wasm32"))]
+        let change_mask = match self.send_job(doc, change_mask, focus) {
+            Ok(()) => {
+                graphics.cell_size = doc.cell_size;
+                return;
+            }
+            Err(change_mask) => change_mask,
+        };
+
+        profiler.begin_frame();
+        graphics.generate(doc, &change_mask, false, Some(context), profiler);
+    }
+
+    /// Returns `change_mask` back when the generation thread is not running
+    #[cfg(not(target_arch = "wasm32"))]
+    fn send_job(
+        &mut self,
+        doc: &Document,
+        change_mask: ChangeMask,
+        focus: Vec2,
+    ) -> Result<(), ChangeMask> {
+        let Some(job_tx) = &self.job_tx else { return Err(change_mask) };
+        self.cancel.store(true, Ordering::Relaxed);
+        self.cancel = Arc::new(AtomicBool::new(false));
+        let job = GenerationJob {
+            id: self.last_job + 1,
+            doc: generation_snapshot(doc),
+            change_mask,
+            focus,
+            cancel: self.cancel.clone(),
+        };
+        match job_tx.send(job) {
+            Ok(()) => {
+                self.last_job += 1;
+                Ok(())
+            }
+            Err(err) => {
+                error!("Generation thread has stopped, generating on the main thread.");
+                self.job_tx = None;
+                self.event_rx = None;
+                self.thread = None;
+                self.finished_job = self.last_job;
+                Err(err.0.change_mask)
+            }
+        }
+    }
+
+    /// Displays tiles that were generated since the last call
+    pub fn receive(
+        &mut self,
+        graphics: &mut DocumentGraphics,
+        context: &mut Context,
+        profiler: &mut Profiler,
+    ) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let Some(event_rx) = &self.event_rx else { return };
+            while let Ok(event) = event_rx.try_recv() {
+                match event {
+                    GenerationEvent::Started { extent } => {
+                        graphics.retain_tiles(extent);
+                    }
+                    GenerationEvent::Tiles {
+                        tile_range,
+                        materials,
+                    } => {
+                        graphics.replace_tiles(context, tile_range, materials);
+                    }
+                    GenerationEvent::Finished { id, markers } => {
+                        self.finished_job = id;
+                        profiler.last_frame_markers = markers;
+                    }
+                }
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        let _ = (graphics, context, profiler);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for GenerationWorker {
+    fn drop(&mut self) {
+        // closed channel stops the thread once the current chunk is done
+        self.cancel.store(true, Ordering::Relaxed);
+        self.job_tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Copy of the document for a generation job. Grids are shared with the document instead
+/// of being copied, and parts that do not affect generated distances are left out, so
+/// sending a job stays cheap for large maps.
+#[cfg(not(target_arch = "wasm32"))]
+fn generation_snapshot(doc: &Document) -> Document {
+    Document {
+        materials: doc.materials.clone(),
+        outline_widths: doc.outline_widths.clone(),
+        cell_size: doc.cell_size,
+        layers: doc.layers.clone(),
+        layer_order: doc.layer_order.clone(),
+        layer_groups: doc.layer_groups.clone(),
+        current_layer: doc.current_layer,
+        selection: Grid::new(0),
+        zone_selection: None,
+        side_load: HashMap::new(),
+        markup: MapMarkup::new(),
+        reference_path: None,
+        reference_scale: doc.reference_scale,
+        show_reference: false,
+        grids: doc.grids.clone(),
+        selected: Vec::new(),
+        nodes: doc.nodes.clone(),
+        edges: doc.edges.clone(),
+        plants: doc.plants.clone(),
+        symmetry: doc.symmetry,
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn worker_thread(job_rx: Receiver<GenerationJob>, event_tx: Sender<GenerationEvent>) {
+    let mut graphics = DocumentGraphics::new();
+    let mut profiler = Profiler::new();
+    // chunks of cancelled jobs that are yet to be generated
+    let mut remaining_chunks: Vec<[IVec2; 2]> = Vec::new();
+
+    while let Ok(mut job) = job_rx.recv() {
+        // only the latest document is generated, with changes of skipped jobs included
+        while let Ok(next) = job_rx.try_recv() {
+            let mut change_mask = job.change_mask;
+            change_mask.merge(next.change_mask);
+            job = GenerationJob {
+                change_mask,
+                ..next
+            };
+        }
+        let _span = span!("generation job");
+        profiler.begin_frame();
+        let doc = &job.doc;
+
+        let mut chunks = match graphics.dirty_tile_range(doc, &job.change_mask) {
+            Some(tile_range) => {
+                let mut chunks = split_into_chunks(tile_range);
+                chunks.extend(
+                    remaining_chunks
+                        .drain(..)
+                        .filter(|c| !tile_range.contains(*c)),
+                );
+                chunks
+            }
+            None => {
+                let extent = graphics.document_tile_range(doc);
+                graphics.clear_cells();
+                remaining_chunks.clear();
+                if event_tx.send(GenerationEvent::Started { extent }).is_err() {
+                    return;
+                }
+                split_into_chunks(extent)
+            }
+        };
+        graphics.cell_size = doc.cell_size;
+
+        let tile_world_size =
+            (doc.cell_size / 2) as f32 * graphics.generated_distances.tile_size as f32;
+        let focus_tile = job.focus / tile_world_size;
+        let focus_distance =
+            |c: &[IVec2; 2]| ((c[0] + c[1]).as_vec2() * 0.5 - focus_tile).length_squared();
+        chunks.sort_by(|a, b| focus_distance(a).total_cmp(&focus_distance(b)));
+
+        let mut cancelled = false;
+        for (index, &chunk) in chunks.iter().enumerate() {
+            graphics.generate_cells(doc, Some(chunk), false, &mut profiler);
+            let materials = graphics
+                .generated_distances
+                .materials
+                .iter()
+                .map(|tiles| {
+                    tiles
+                        .iter()
+                        .filter(|(&(x, y), _)| chunk.contains_point(ivec2(x, y)))
+                        .map(|(&key, tile)| (key, tile.clone()))
+                        .collect()
+                })
+                .collect();
+            let event = GenerationEvent::Tiles {
+                tile_range: chunk,
+                materials,
+            };
+            if event_tx.send(event).is_err() {
+                return;
+            }
+            // finished chunks are kept, the rest is left to the next job
+            if job.cancel.load(Ordering::Relaxed) {
+                remaining_chunks.extend_from_slice(&chunks[index + 1..]);
+                cancelled = true;
+                break;
+            }
+        }
+
+        if !cancelled {
+            profiler.begin_frame();
+            let event = GenerationEvent::Finished {
+                id: job.id,
+                markers: profiler.last_frame_markers.clone(),
+            };
+            if event_tx.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Splits `tile_range` along a grid of `CHUNK_TILES`
+fn split_into_chunks(tile_range: [IVec2; 2]) -> Vec<[IVec2; 2]> {
+    let mut chunks = Vec::new();
+    if tile_range.is_null() {
+        return chunks;
+    }
+    let first = ivec2(
+        tile_range[0].x.div_euclid(CHUNK_TILES),
+        tile_range[0].y.div_euclid(CHUNK_TILES),
+    );
+    let last = ivec2(
+        (tile_range[1].x - 1).div_euclid(CHUNK_TILES),
+        (tile_range[1].y - 1).div_euclid(CHUNK_TILES),
+    );
+    for y in first.y..=last.y {
+        for x in first.x..=last.x {
+            let chunk = [ivec2(x, y) * CHUNK_TILES, ivec2(x + 1, y + 1) * CHUNK_TILES];
+            if let Some(chunk) = chunk.intersect(tile_range) {
+                chunks.push(chunk);
+            }
+        }
+    }
+    chunks
+}
@@ -0,0 +1,175 @@
+use glam::{vec2, Vec2};
+use realtime_drawing::{MiniquadBatch, VertexPos3UvColor};
+
+use crate::app::App;
+use crate::document::SelectRef;
+use crate::math::Rect;
+use crate::tool::Tool;
+use crate::zone::{AnyZone, ZoneRef};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum GizmoHandle {
+    Rotate,
+    /// Corners scale both axes uniformly, sides scale one axis
+    Scale {
+        x: bool,
+        y: bool,
+    },
+    Pivot,
+}
+
+/// Pivot moved away from the center of the selection, kept while selection stays the same
+pub struct GizmoPivot {
+    pub selected: Vec<SelectRef>,
+    pub zone: Option<ZoneRef>,
+    pub pos: Vec2,
+}
+
+/// Rotate and scale handles around the selection, in screen space
+pub struct Gizmo {
+    pub bounds: [Vec2; 2],
+    pub pivot: Vec2,
+}
+
+const HANDLE_RADIUS: f32 = 5.0;
+const ROTATE_OFFSET: f32 = 24.0;
+
+impl Gizmo {
+    pub fn handles(&self) -> Vec<(GizmoHandle, Vec2)> {
+        let [min, max] = self.bounds;
+        let center = (min + max) * 0.5;
+        let scale = |x, y| GizmoHandle::Scale { x, y };
+        vec![
+            (GizmoHandle::Pivot, self.pivot),
+            (GizmoHandle::Rotate, vec2(center.x, min.y - ROTATE_OFFSET)),
+            (scale(true, true), min),
+            (scale(true, true), vec2(max.x, min.y)),
+            (scale(true, true), max),
+            (scale(true, true), vec2(min.x, max.y)),
+            (scale(false, true), vec2(center.x, min.y)),
+            (scale(false, true), vec2(center.x, max.y)),
+            (scale(true, false), vec2(min.x, center.y)),
+            (scale(true, false), vec2(max.x, center.y)),
+        ]
+    }
+
+    /// Pivot can be excluded, as it often lies on top of the selected element
+    pub fn hit_test(&self, screen_pos: Vec2, include_pivot: bool) -> Option<(GizmoHandle, Vec2)> {
+        self.handles()
+            .into_iter()
+            .filter(|&(handle, _)| include_pivot || handle != GizmoHandle::Pivot)
+            .find(|&(_, pos)| pos.distance(screen_pos) <= HANDLE_RADIUS + 2.0)
+    }
+
+    pub fn draw(&self, batch: &mut MiniquadBatch<VertexPos3UvColor>, hover: Option<GizmoHandle>) {
+        let color = [255, 255, 255, 160];
+        let hover_color = [255, 128, 0, 255];
+        let [min, max] = self.bounds;
+        batch.geometry.stroke_rect(min, max, 1.0, color);
+        let top = vec2((min.x + max.x) * 0.5, min.y);
+        batch
+            .geometry
+            .stroke_line_aa(top, top - vec2(0.0, ROTATE_OFFSET), 1.0, color);
+        for (handle, pos) in self.handles() {
+            let color = if Some(handle) == hover {
+                hover_color
+            } else {
+                color
+            };
+            match handle {
+                GizmoHandle::Rotate => {
+                    batch.geometry.fill_circle_aa(pos, HANDLE_RADIUS, 12, color);
+                }
+                GizmoHandle::Scale { .. } => {
+                    let extent = Vec2::splat(HANDLE_RADIUS - 1.0);
+                    batch.geometry.fill_rect(pos - extent, pos + extent, color);
+                }
+                GizmoHandle::Pivot => {
+                    batch
+                        .geometry
+                        .stroke_circle_aa(pos, HANDLE_RADIUS, 1.5, 12, color);
+                    let (dx, dy) = (
+                        vec2(HANDLE_RADIUS * 2.0, 0.0),
+                        vec2(0.0, HANDLE_RADIUS * 2.0),
+                    );
+                    batch
+                        .geometry
+                        .stroke_line_aa(pos - dx, pos + dx, 1.0, color);
+                    batch
+                        .geometry
+                        .stroke_line_aa(pos - dy, pos + dy, 1.0, color);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn zone_bounds(zone: &AnyZone) -> [Vec2; 2] {
+    let point = |p: [i32; 2]| vec2(p[0] as f32, p[1] as f32);
+    match zone {
+        AnyZone::Point(p) => [point(p.pos), point(p.pos)],
+        AnyZone::Rect(r) => [point(r.start), point(r.end)],
+        AnyZone::Segment(s) => [
+            point(s.start).min(point(s.end)),
+            point(s.start).max(point(s.end)),
+        ],
+    }
+}
+
+impl App {
+    /// Nodes and plants in the select tool, zone in the zone tool
+    pub(crate) fn gizmo_selection_bounds(&self) -> Option<[Vec2; 2]> {
+        match self.tool {
+            Tool::Select => {
+                let transformable = self
+                    .doc
+                    .selected
+                    .iter()
+                    .any(|s| matches!(s, SelectRef::Node(_) | SelectRef::Plant(_)));
+                if !transformable {
+                    return None;
+                }
+                self.doc.selected_bounds().valid()
+            }
+            Tool::Zone => {
+                let zone = self.doc.zone_selection?;
+                if !zone.is_valid(&self.doc.markup) {
+                    return None;
+                }
+                Some(zone_bounds(&zone.fetch(&self.doc.markup)))
+            }
+            _ => None,
+        }
+    }
+
+    /// World position of the pivot
+    pub(crate) fn gizmo_pivot(&self) -> Option<Vec2> {
+        let [min, max] = self.gizmo_selection_bounds()?;
+        match &self.gizmo_pivot {
+            Some(pivot)
+                if pivot.selected == self.doc.selected && pivot.zone == self.doc.zone_selection =>
+            {
+                Some(pivot.pos)
+            }
+            _ => Some((min + max) * 0.5),
+        }
+    }
+
+    pub(crate) fn gizmo(&self) -> Option<Gizmo> {
+        let [min, max] = self.gizmo_selection_bounds()?;
+        let pivot = self.gizmo_pivot()?;
+        let world_to_screen = self.view.world_to_screen();
+        // small selections still get handles that can be told apart
+        let min_size = Vec2::splat(24.0);
+        let [min, max] = [
+            world_to_screen.transform_point2(min),
+            world_to_screen.transform_point2(max),
+        ];
+        let center = (min + max) * 0.5;
+        let extent = ((max - min) * 0.5).max(min_size * 0.5) + Vec2::splat(8.0);
+        Some(Gizmo {
+            bounds: [center - extent, center + extent],
+            pivot: world_to_screen.transform_point2(pivot),
+        })
+    }
+}
@@ -0,0 +1,388 @@
+// Golden-image regression tests for map generation.
+//
+// Each document in `tests/fixtures` is generated the same way as `editor export` does it.
+// Resulting distance tiles and map image are compared against `tests/golden`.
+// Run `UPDATE_GOLDEN=1 cargo test golden` to accept changed output.
+use std::fs::{create_dir_all, read, write, File};
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use glam::IVec2;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::app::App;
+use crate::document::ChangeMask;
+use crate::export::generate_graphics;
+use crate::field::Field;
+use crate::graphics::DocumentGraphics;
+use crate::math::Rect;
+use crate::profiler::Profiler;
+
+/// Largest accepted difference of a distance, in world units
+const DISTANCE_TOLERANCE: f32 = 0.01;
+/// Largest accepted difference of a premultiplied color channel
+const IMAGE_TOLERANCE: i32 = 2;
+/// Distances above this value are considered to be "far away"
+const FAR_DISTANCE: f32 = 1e30;
+
+fn fixture_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+fn diff_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("target/golden_diff")
+}
+
+struct Image {
+    size: IVec2,
+    pixels: Vec<u8>,
+}
+
+fn check_golden(name: &str) -> Result<()> {
+    let mut doc = App::load_doc(&fixture_dir().join(format!("{}.json", name)))?;
+    doc.pre_save_cleanup();
+    let graphics = generate_graphics(&doc);
+    let (pixels, bounds) = graphics.render_map_image_cpu(&doc);
+    let image = Image {
+        size: bounds[1] - bounds[0],
+        pixels,
+    };
+
+    let field_path = golden_dir().join(format!("{}.field.zip", name));
+    let image_path = golden_dir().join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        create_dir_all(golden_dir())?;
+        write_field(&field_path, &graphics.generated_distances)?;
+        write_png(&image_path, &image)?;
+        return Ok(());
+    }
+
+    let mut errors = Vec::new();
+
+    match read(&field_path) {
+        Ok(bytes) => {
+            let expected = read_field(&bytes)
+                .with_context(|| format!("Reading {}", field_path.to_string_lossy()))?;
+            compare_fields(&expected, &graphics.generated_distances, &mut errors);
+        }
+        Err(_) => errors.push(format!("missing {}", field_path.to_string_lossy())),
+    }
+
+    match read_png(&image_path) {
+        Ok(expected) => {
+            if let Some(diff) = compare_images(&expected, &image, &mut errors) {
+                create_dir_all(diff_dir())?;
+                let actual_path = diff_dir().join(format!("{}.actual.png", name));
+                let diff_path = diff_dir().join(format!("{}.diff.png", name));
+                write_png(&actual_path, &image)?;
+                write_png(&diff_path, &diff)?;
+                errors.push(format!("diff written to {}", diff_path.to_string_lossy()));
+            }
+        }
+        Err(_) => errors.push(format!("missing {}", image_path.to_string_lossy())),
+    }
+
+    if !errors.is_empty() {
+        bail!(
+            "{} does not match golden files (rerun with UPDATE_GOLDEN=1 to accept):\n{}",
+            name,
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
+
+fn compare_fields(expected: &Field, actual: &Field, errors: &mut Vec<String>) {
+    if expected.tile_size != actual.tile_size {
+        errors.push(format!(
+            "tile size {} != {}",
+            actual.tile_size, expected.tile_size
+        ));
+        return;
+    }
+    let num_materials = expected.materials.len().max(actual.materials.len());
+    for material in 0..num_materials {
+        let empty = Default::default();
+        let expected_tiles = expected.materials.get(material).unwrap_or(&empty);
+        let actual_tiles = actual.materials.get(material).unwrap_or(&empty);
+
+        let mut tile_keys: Vec<_> = expected_tiles
+            .keys()
+            .chain(actual_tiles.keys())
+            .copied()
+            .collect();
+        tile_keys.sort();
+        tile_keys.dedup();
+
+        for tile_key in tile_keys {
+            let (expected_tile, actual_tile) =
+                match (expected_tiles.get(&tile_key), actual_tiles.get(&tile_key)) {
+                    (Some(e), Some(a)) => (e, a),
+                    (Some(_), None) => {
+                        errors.push(format!(
+                            "material {} tile {:?} is missing",
+                            material, tile_key
+                        ));
+                        continue;
+                    }
+                    (None, Some(_)) => {
+                        errors.push(format!(
+                            "material {} tile {:?} is extra",
+                            material, tile_key
+                        ));
+                        continue;
+                    }
+                    (None, None) => continue,
+                };
+
+            let mut max_difference = 0.0f32;
+            let mut num_different = 0;
+            for (&e, &a) in expected_tile.iter().zip(actual_tile.iter()) {
+                if e >= FAR_DISTANCE && a >= FAR_DISTANCE {
+                    continue;
+                }
+                let difference = (e - a).abs();
+                if difference.is_nan() || difference > DISTANCE_TOLERANCE {
+                    num_different += 1;
+                    max_difference = max_difference.max(difference);
+                }
+            }
+            if num_different > 0 {
+                errors.push(format!(
+                    "material {} tile {:?}: {} distances differ, up to {}",
+                    material, tile_key, num_different, max_difference
+                ));
+            }
+        }
+    }
+}
+
+/// Returns diff image when images do not match
+fn compare_images(expected: &Image, actual: &Image, errors: &mut Vec<String>) -> Option<Image> {
+    if expected.size != actual.size {
+        errors.push(format!("image size {} != {}", actual.size, expected.size));
+        return None;
+    }
+
+    // compare premultiplied colors, straight color of nearly transparent pixels is noise
+    let premultiplied = |p: &[u8]| {
+        let a = p[3] as i32;
+        [
+            p[0] as i32 * a / 255,
+            p[1] as i32 * a / 255,
+            p[2] as i32 * a / 255,
+            a,
+        ]
+    };
+
+    let mut num_different = 0;
+    let mut diff_pixels = Vec::with_capacity(actual.pixels.len());
+    for (e, a) in expected
+        .pixels
+        .chunks_exact(4)
+        .zip(actual.pixels.chunks_exact(4))
+    {
+        let e_pma = premultiplied(e);
+        let a_pma = premultiplied(a);
+        let different = e_pma
+            .iter()
+            .zip(a_pma.iter())
+            .any(|(e, a)| (e - a).abs() > IMAGE_TOLERANCE);
+        if different {
+            num_different += 1;
+            diff_pixels.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            // dimmed expected image for context
+            let gray = ((e_pma[0] + e_pma[1] + e_pma[2]) / 6) as u8;
+            diff_pixels.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+
+    if num_different == 0 {
+        return None;
+    }
+    errors.push(format!("{} pixels differ", num_different));
+    Some(Image {
+        size: actual.size,
+        pixels: diff_pixels,
+    })
+}
+
+/// Distances are stored compressed, they are mostly made of repeating far values
+fn write_field(path: &Path, field: &Field) -> Result<()> {
+    let mut zip_bytes = Vec::new();
+    let mut zip = ZipWriter::new(Cursor::new(&mut zip_bytes));
+    zip.start_file("field.bin", FileOptions::default())?;
+    zip.write_all(&bincode::serialize(field)?)?;
+    zip.finish()?;
+    drop(zip);
+    write(path, zip_bytes).with_context(|| format!("Writing {}", path.to_string_lossy()))
+}
+
+fn read_field(zip_bytes: &[u8]) -> Result<Field> {
+    let mut zip = ZipArchive::new(Cursor::new(zip_bytes))?;
+    let mut bytes = Vec::new();
+    zip.by_name("field.bin")?.read_to_end(&mut bytes)?;
+    Ok(bincode::deserialize(&bytes)?)
+}
+
+fn read_png(path: &Path) -> Result<Image> {
+    let decoder = png::Decoder::new(File::open(path)?);
+    let (info, mut reader) = decoder.read_info()?;
+    if info.color_type != png::ColorType::RGBA || info.bit_depth != png::BitDepth::Eight {
+        bail!("{} is expected to be 8-bit RGBA", path.to_string_lossy());
+    }
+    let mut pixels = vec![0; info.buffer_size()];
+    reader.next_frame(&mut pixels)?;
+    Ok(Image {
+        size: IVec2::new(info.width as i32, info.height as i32),
+        pixels,
+    })
+}
+
+fn write_png(path: &Path, image: &Image) -> Result<()> {
+    let mut png_bytes = Vec::new();
+    {
+        let mut encoder =
+            png::Encoder::new(&mut png_bytes, image.size.x as u32, image.size.y as u32);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&image.pixels)?;
+    }
+    write(path, png_bytes).with_context(|| format!("Writing {}", path.to_string_lossy()))
+}
+
+#[test]
+fn golden_node_shapes() -> Result<()> {
+    check_golden("node_shapes")
+}
+
+#[test]
+fn golden_rotated_shapes() -> Result<()> {
+    check_golden("rotated_shapes")
+}
+
+#[test]
+fn golden_node_operations() -> Result<()> {
+    check_golden("node_operations")
+}
+
+#[test]
+fn golden_outline_widths() -> Result<()> {
+    check_golden("outline_widths")
+}
+
+#[test]
+fn golden_edges() -> Result<()> {
+    check_golden("edges")
+}
+
+#[test]
+fn golden_curved_edges() -> Result<()> {
+    check_golden("curved_edges")
+}
+
+#[test]
+fn golden_edge_overrides() -> Result<()> {
+    check_golden("edge_overrides")
+}
+
+#[test]
+fn golden_grid() -> Result<()> {
+    check_golden("grid")
+}
+
+#[test]
+fn golden_plants() -> Result<()> {
+    check_golden("plants")
+}
+
+#[test]
+fn golden_seeded_plants() -> Result<()> {
+    check_golden("seeded_plants")
+}
+
+#[test]
+fn golden_surface_plants() -> Result<()> {
+    check_golden("surface_plants")
+}
+
+#[test]
+fn golden_lsystem_plants() -> Result<()> {
+    check_golden("lsystem_plants")
+}
+
+#[test]
+fn golden_layers() -> Result<()> {
+    check_golden("layers")
+}
+
+/// Tiles regenerated around an edit should match a full generation of the edited document
+#[test]
+fn incremental_generation_matches_full() -> Result<()> {
+    let mut doc = App::load_doc(&fixture_dir().join("layers.json"))?;
+    let mut profiler = Profiler::new();
+    profiler.begin_frame();
+    let all_layers = ChangeMask {
+        all_layers: true,
+        ..ChangeMask::default()
+    };
+    let mut graphics = DocumentGraphics::new();
+    graphics.generate(&doc, &all_layers, false, None, &mut profiler);
+
+    // paint cells and move a node
+    let mut changes = ChangeMask::default();
+    let (layer_key, grid_key) = doc
+        .layers
+        .iter()
+        .find(|(_, layer)| doc.grids.contains_key(layer.grid))
+        .map(|(key, layer)| (key, layer.grid))
+        .context("No layer with cells")?;
+    let grid = Arc::make_mut(&mut doc.grids[grid_key]);
+    let cells = [
+        grid.bounds[0] + IVec2::new(1, 1),
+        grid.bounds[0] + IVec2::new(4, 3),
+    ];
+    for y in cells[0].y..cells[1].y {
+        for x in cells[0].x..cells[1].x {
+            let index = grid.grid_pos_index(x, y);
+            grid.cells[index] = 2;
+        }
+    }
+    changes.mark_dirty_cells(layer_key, cells, doc.cell_size);
+
+    let node_key = doc.nodes.keys().next().context("No nodes")?;
+    let old_bounds = doc.nodes_bounds(&[node_key]);
+    doc.nodes[node_key].pos += IVec2::new(24, 16);
+    let bounds = old_bounds.union(doc.nodes_bounds(&[node_key]));
+    changes.mark_dirty_rect(doc.nodes[node_key].layer, bounds);
+
+    if graphics.dirty_tile_range(&doc, &changes).is_none() {
+        bail!("Edit is expected to regenerate only some of the tiles");
+    }
+    graphics.generate(&doc, &changes, false, None, &mut profiler);
+
+    let mut full = DocumentGraphics::new();
+    full.generate(&doc, &all_layers, false, None, &mut profiler);
+    let mut errors = Vec::new();
+    compare_fields(
+        &full.generated_distances,
+        &graphics.generated_distances,
+        &mut errors,
+    );
+    if !errors.is_empty() {
+        bail!(
+            "Incremental generation differs from full one:\n{}",
+            errors.join("\n")
+        );
+    }
+    Ok(())
+}
@@ -1,31 +1,173 @@
 use crate::document::LayerKey;
 use crate::field::Field;
-use crate::math::Rect;
+use crate::math::{bezier_point, rotate_vec2, split_bezier, Rect};
 use crate::plant::{Plant, PlantKey, PlantSegment, PlantSegmentKey};
-use crate::sdf::{sd_box, sd_circle, sd_octogon, sd_outline, sd_trapezoid};
+use crate::sdf::{
+    sd_box, sd_circle, sd_ellipse, sd_hexagon, sd_octogon, sd_outline, sd_polygon,
+    sd_regular_polygon, sd_rounded_box, sd_trapezoid, sd_uneven_capsule, smooth_max, smooth_min,
+    smooth_min_weight,
+};
 use glam::{ivec2, vec2, IVec2, Vec2};
 use ordered_float::NotNan;
-use rayon::iter::{IntoParallelRefIterator, ParallelExtend, ParallelIterator};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelExtend,
+    ParallelIterator,
+};
 use slotmap::{new_key_type, SlotMap};
 use std::collections::HashMap;
 use tracy_client::span;
 
+/// Graph elements contribute to distance tiles that are within this distance (in world
+/// units) from their bounds
+pub(crate) const GRAPH_TILE_PADDING: f32 = 32.0;
+
 new_key_type! {
     pub struct GraphNodeKey;
     pub struct GraphEdgeKey;
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Clone)]
+/// Outline thickness of nodes that have none set, in world units
+const DEFAULT_THICKNESS: f32 = 8.0;
+
+/// Curved edges are approximated with segments of about this length, in world units
+const EDGE_SEGMENT_LENGTH: f32 = 16.0;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq)]
 pub struct GraphEdge {
     pub start: GraphNodeKey,
     pub end: GraphNodeKey,
+    /// Bézier control points: none for a straight edge, one for a quadratic and two for a
+    /// cubic curve
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub control_points: Vec<IVec2>,
+    /// Material of the start node is used when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<u8>,
+    /// Radius at the start and the end, radii of the nodes are used when not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius: Option<[usize; 2]>,
+    /// When not set, edges between nodes with the same operation share it, other edges
+    /// are united
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<NodeOperation>,
+    /// Read from older documents only, replaced by `operation` on load
+    #[serde(default, skip_serializing)]
+    pub no_outline: Option<bool>,
+}
+
+/// Shape of a subtracting or intersecting operation
+#[derive(Clone, Copy)]
+enum Shape {
+    Node(GraphNodeKey),
+    Edge(GraphEdgeKey),
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Copy, Clone)]
+/// Straight piece of an edge, radius changes linearly along it
+#[derive(Clone, Copy)]
+pub struct EdgeSegment {
+    pub start: Vec2,
+    pub end: Vec2,
+    pub start_radius: f32,
+    pub end_radius: f32,
+}
+
+/// Size of a shape is given by the radius of the node, other dimensions are relative to it
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq)]
 pub enum GraphNodeShape {
     Octogon,
     Circle,
     Square,
+    /// Half-height is `aspect` times the radius
+    Rectangle {
+        aspect: f32,
+    },
+    /// Corners are rounded by `rounding` of the smaller half-extent
+    RoundedBox {
+        aspect: f32,
+        rounding: f32,
+    },
+    Hexagon,
+    /// Vertical half-axis is `aspect` times the radius
+    Ellipse {
+        aspect: f32,
+    },
+    /// Radius is the distance to the corners
+    RegularPolygon {
+        sides: u8,
+    },
+    /// Vertices in units of the radius
+    Polygon {
+        points: Vec<Vec2>,
+    },
+}
+
+impl GraphNodeShape {
+    /// Corners of the shape without rotation, in units of the radius
+    fn corners(&self) -> Option<Vec<Vec2>> {
+        let regular = |sides: u32, first: f32, scale: f32| {
+            (0..sides)
+                .map(|i| {
+                    let angle = first + i as f32 * std::f32::consts::TAU / sides as f32;
+                    vec2(angle.cos(), angle.sin()) * scale
+                })
+                .collect()
+        };
+        match self {
+            GraphNodeShape::Octogon => {
+                let k = (std::f32::consts::PI / 8.0).tan();
+                Some(vec![
+                    vec2(1.0, k),
+                    vec2(k, 1.0),
+                    vec2(-k, 1.0),
+                    vec2(-1.0, k),
+                    vec2(-1.0, -k),
+                    vec2(-k, -1.0),
+                    vec2(k, -1.0),
+                    vec2(1.0, -k),
+                ])
+            }
+            GraphNodeShape::Square => GraphNodeShape::Rectangle { aspect: 1.0 }.corners(),
+            GraphNodeShape::Rectangle { aspect } => Some(vec![
+                vec2(1.0, *aspect),
+                vec2(-1.0, *aspect),
+                vec2(-1.0, -aspect),
+                vec2(1.0, -aspect),
+            ]),
+            GraphNodeShape::Hexagon => {
+                Some(regular(6, 0.0, 1.0 / (std::f32::consts::PI / 6.0).cos()))
+            }
+            GraphNodeShape::RegularPolygon { sides } => Some(regular(
+                (*sides).max(3) as u32,
+                std::f32::consts::FRAC_PI_2,
+                1.0,
+            )),
+            GraphNodeShape::Polygon { points } => Some(points.clone()),
+            GraphNodeShape::Circle
+            | GraphNodeShape::RoundedBox { .. }
+            | GraphNodeShape::Ellipse { .. } => None,
+        }
+    }
+
+    /// Polygon with the same corners, used to start editing of custom polygons
+    pub fn to_polygon(&self) -> GraphNodeShape {
+        let points = self.corners().unwrap_or_else(|| {
+            GraphNodeShape::RegularPolygon { sides: 5 }
+                .corners()
+                .unwrap_or_default()
+        });
+        GraphNodeShape::Polygon { points }
+    }
+}
+
+/// How the shape of a node is combined with the other shapes of its layer
+#[derive(serde::Serialize, serde::Deserialize, Copy, Clone, PartialEq, Default)]
+pub enum NodeOperation {
+    #[default]
+    Union,
+    /// Removes the shape from all materials of the layer
+    Subtract,
+    /// Keeps only the parts of the layer that are within the shape
+    Intersect,
 }
 
 fn graph_node_shape_default() -> GraphNodeShape {
@@ -35,13 +177,14 @@ fn graph_node_material_default() -> u8 {
     1
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, PartialEq)]
 pub struct GraphNode {
     pub pos: IVec2,
     pub radius: usize,
     #[serde(default = "graph_node_shape_default")]
     pub shape: GraphNodeShape,
-    #[serde(default = "Default::default")]
+    /// Read from older documents only, replaced by `NodeOperation::Subtract` on load
+    #[serde(default, skip_serializing)]
     pub no_outline: bool,
     #[serde(default = "graph_node_material_default")]
     pub material: u8,
@@ -49,6 +192,17 @@ pub struct GraphNode {
     pub layer: LayerKey,
     #[serde(default)]
     pub thickness: usize,
+    /// Counter-clockwise, in radians
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default)]
+    pub operation: NodeOperation,
+    /// Radius of the smooth blend with other shapes, in world units
+    #[serde(default)]
+    pub blend: usize,
+    /// Nodes of the same group are mirrored copies of each other, see `Symmetry`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symmetry_group: Option<GraphNodeKey>,
 }
 
 impl GraphNode {
@@ -61,15 +215,90 @@ impl GraphNode {
             material: graph_node_material_default(),
             layer: LayerKey::default(),
             thickness: 8,
+            rotation: 0.0,
+            operation: NodeOperation::Union,
+            blend: 0,
+            symmetry_group: None,
         }
     }
     pub(crate) fn bounds(&self) -> [Vec2; 2] {
-        [
-            self.pos.as_vec2() - Vec2::splat(self.radius as f32),
-            self.pos.as_vec2() + Vec2::splat(self.radius as f32),
-        ]
+        let pos = self.pos.as_vec2();
+        let radius = self.radius as f32;
+        let (sin, cos) = self.rotation.sin_cos();
+        let (sin, cos) = (sin.abs(), cos.abs());
+        let half_size = match self.shape {
+            GraphNodeShape::Circle => Vec2::splat(radius),
+            GraphNodeShape::Ellipse { aspect } => {
+                let (a, b) = (radius, radius * aspect);
+                vec2(
+                    (a * a * cos * cos + b * b * sin * sin).sqrt(),
+                    (a * a * sin * sin + b * b * cos * cos).sqrt(),
+                )
+            }
+            GraphNodeShape::RoundedBox { aspect, rounding } => {
+                let half_extents = vec2(radius, radius * aspect);
+                let r = half_extents.min_element() * rounding;
+                let inner = half_extents - Vec2::splat(r);
+                vec2(inner.x * cos + inner.y * sin, inner.x * sin + inner.y * cos) + Vec2::splat(r)
+            }
+            _ => self
+                .corners()
+                .iter()
+                .fold(Vec2::ZERO, |size, corner| size.max((*corner - pos).abs())),
+        };
+        // smooth blend can grow the shape by up to a quarter of its radius
+        let half_size = half_size + Vec2::splat(self.blend as f32 * 0.25);
+        [pos - half_size, pos + half_size]
     }
 
+    /// Thickness of the outline of the node and its edges, zero stands for the default
+    pub fn outline_thickness(&self) -> f32 {
+        if self.thickness == 0 {
+            DEFAULT_THICKNESS
+        } else {
+            self.thickness as f32
+        }
+    }
+
+    /// Position of the handle that changes the radius
+    pub fn radius_handle(&self) -> Vec2 {
+        self.pos.as_vec2() + rotate_vec2(vec2(0.0, self.radius as f32), self.rotation)
+    }
+
+    /// Corners of polygonal shapes in world space
+    pub fn corners(&self) -> Vec<Vec2> {
+        let radius = self.radius as f32;
+        self.shape
+            .corners()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|c| self.pos.as_vec2() + rotate_vec2(c * radius, self.rotation))
+            .collect()
+    }
+
+    /// Signed distance from `pos` in world space to the shape of the node
+    pub fn distance(&self, pos: Vec2) -> f32 {
+        let p = rotate_vec2(pos - self.pos.as_vec2(), -self.rotation);
+        let radius = self.radius as f32;
+        match &self.shape {
+            GraphNodeShape::Octogon => sd_octogon(p, radius),
+            GraphNodeShape::Circle => sd_circle(p, Vec2::ZERO, radius),
+            GraphNodeShape::Square => sd_box(p, Vec2::splat(radius)),
+            GraphNodeShape::Rectangle { aspect } => sd_box(p, vec2(radius, radius * aspect)),
+            GraphNodeShape::RoundedBox { aspect, rounding } => {
+                let half_extents = vec2(radius, radius * aspect);
+                sd_rounded_box(p, half_extents, half_extents.min_element() * rounding)
+            }
+            GraphNodeShape::Hexagon => sd_hexagon(p, radius),
+            GraphNodeShape::Ellipse { aspect } => sd_ellipse(p, vec2(radius, radius * aspect)),
+            GraphNodeShape::RegularPolygon { sides } => {
+                sd_regular_polygon(p, radius, (*sides).max(3) as u32)
+            }
+            GraphNodeShape::Polygon { points } => sd_polygon(p / radius, points) * radius,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn render_distances(
         field: &mut Field,
         cell_size: i32,
@@ -77,33 +306,26 @@ impl GraphNode {
         nodes: &SlotMap<GraphNodeKey, GraphNode>,
         edges: &SlotMap<GraphEdgeKey, GraphEdge>,
         plants: &SlotMap<PlantKey, Plant>,
-        plant_segments: &mut SlotMap<PlantSegmentKey, PlantSegment>,
+        plant_segments: &SlotMap<PlantSegmentKey, PlantSegment>,
+        tile_range: Option<[IVec2; 2]>,
     ) {
         let _span = span!("GraphNode::render_distances");
-        {
-            Plant::grow_plants(field, plants, plant_segments);
-        }
-        let plant_segments: &SlotMap<_, _> = plant_segments;
 
         let cell_size_f = cell_size as f32;
-        let outline_width = 8.0;
-        let half_thickness = outline_width * 0.5;
 
         let mut node_cache: Vec<HashMap<(i32, i32), Vec<_>>> = vec![];
+        let mut edge_segments: HashMap<GraphEdgeKey, Vec<EdgeSegment>> = HashMap::new();
         let mut edge_cache: Vec<HashMap<(i32, i32), Vec<_>>> = vec![];
         let mut plant_cache: Vec<HashMap<(i32, i32), Vec<_>>> = vec![];
-        // material 0 has to come last for "no-outline" to work
         let mut used_materials: Vec<_> = nodes
             .values()
             .map(|n| n.material as usize)
+            .chain(edges.values().map(|e| e.material(nodes) as usize))
             .chain(plants.values().map(|p| p.material as usize))
+            .filter(|&m| m != 0)
             .collect();
         used_materials.sort();
         used_materials.dedup();
-        if used_materials.get(0).copied() == Some(0) {
-            used_materials.remove(0);
-        }
-        used_materials.push(0);
 
         for &material in &used_materials {
             while node_cache.len() <= material as usize {
@@ -120,15 +342,17 @@ impl GraphNode {
         {
             let _span = span!("node_cache");
 
-            for (key, node) in nodes.iter().filter(|(_, n)| n.layer == layer_key) {
-                let padding = 32.0;
+            // subtracted and intersected shapes are applied by `apply_operations`
+            for (key, node) in nodes.iter().filter(|(_, n)| {
+                n.layer == layer_key && n.material != 0 && n.operation == NodeOperation::Union
+            }) {
+                let padding = GRAPH_TILE_PADDING;
                 let node_bounds = node.bounds().inflate(padding);
                 let tile_range =
                     Field::world_to_tile_range(node_bounds, cell_size, field.tile_size);
-                let material = if node.no_outline { 0 } else { node.material };
                 for y in tile_range[0].y..tile_range[1].y {
                     for x in tile_range[0].x..tile_range[1].x {
-                        node_cache[material as usize]
+                        node_cache[node.material as usize]
                             .entry((x, y))
                             .or_insert_with(|| Vec::new())
                             .push(key);
@@ -139,27 +363,27 @@ impl GraphNode {
         {
             let _span = span!("edge_cache");
             for (key, edge) in edges {
-                let padding = 32.0;
-                let a = nodes.get(edge.start);
-                let b = nodes.get(edge.end);
-                if a.map(|a| a.layer) != Some(layer_key) && b.map(|b| b.layer) != Some(layer_key) {
+                if !edge.is_in_layer(nodes, layer_key)
+                    || edge.operation(nodes) != NodeOperation::Union
+                {
+                    continue;
+                }
+                let material = edge.material(nodes);
+                if material == 0 {
                     continue;
                 }
+                let padding = GRAPH_TILE_PADDING;
                 let node_bounds = match edge.bounds(nodes) {
                     Some(v) => v.inflate(padding),
                     None => continue,
                 };
+                if !edge.control_points.is_empty() {
+                    if let Some(segments) = edge.segments(nodes) {
+                        edge_segments.insert(key, segments);
+                    }
+                }
                 let tile_range =
                     Field::world_to_tile_range(node_bounds, cell_size, field.tile_size);
-                let a_no_outline = a.map(|n| n.no_outline).unwrap_or(false);
-                let b_no_outline = b.map(|n| n.no_outline).unwrap_or(false);
-                let material = if a_no_outline | b_no_outline {
-                    0
-                } else {
-                    a.map(|a| a.material)
-                        .or_else(|| b.map(|b| b.material))
-                        .unwrap_or(1)
-                };
                 for y in tile_range[0].y..tile_range[1].y {
                     for x in tile_range[0].x..tile_range[1].x {
                         edge_cache[material as usize]
@@ -173,12 +397,13 @@ impl GraphNode {
         {
             let _span = span!("plant_cache");
 
+            // segments belong to plants of this layer
             for (key, segment) in plant_segments {
                 let Some(plant ) = plants.get(segment.plant) else { continue };
-                if plant.layer != layer_key {
+                if plant.material == 0 {
                     continue;
                 }
-                let padding = 32.0;
+                let padding = GRAPH_TILE_PADDING;
                 let bounds = segment.bounds().inflate(padding);
                 let tile_range = Field::world_to_tile_range(bounds, cell_size, field.tile_size);
                 let material = plant.material;
@@ -206,83 +431,69 @@ impl GraphNode {
                     .collect::<Vec<_>>();
                 all_tile_keys.sort();
                 all_tile_keys.dedup();
+                if let Some(tile_range) = tile_range {
+                    all_tile_keys.retain(|&(x, y)| tile_range.contains_point(ivec2(x, y)));
+                }
 
                 field.materials[material].par_extend(all_tile_keys.par_iter().copied().map(
                     |tile_key| {
                         let _span = span!("tile");
                         let mut tile = vec![f32::MAX; tile_size * tile_size];
-                        for material in [material, 0] {
-                            let tile_nodes = node_cache[material]
-                                .get(&tile_key)
-                                .map(|v| v.as_slice())
-                                .unwrap_or(&[]);
-                            let tile_edges = edge_cache[material]
-                                .get(&tile_key)
-                                .map(|v| v.as_slice())
-                                .unwrap_or(&[]);
-                            let tile_plant_segments = plant_cache[material]
-                                .get(&tile_key)
-                                .map(|v| v.as_slice())
-                                .unwrap_or(&[]);
-                            for index in 0..tile_size * tile_size {
-                                let x = (index & (tile_size - 1)) as i32
-                                    + tile_key.0 * tile_size as i32;
-                                let y = (index / tile_size) as i32 + tile_key.1 * tile_size as i32;
-                                let pos = (ivec2(x, y).as_vec2() + vec2(0.5, 0.5)) * cell_size_f;
-                                let mut closest_d = f32::MAX;
-
-                                for node in tile_nodes.iter().map(|k| nodes.get(*k).unwrap()) {
-                                    let d = match node.shape {
-                                        GraphNodeShape::Octogon => {
-                                            sd_octogon(pos - node.pos.as_vec2(), node.radius as f32)
-                                        }
-                                        GraphNodeShape::Circle => {
-                                            sd_circle(pos, node.pos.as_vec2(), node.radius as f32)
-                                        }
-                                        GraphNodeShape::Square => sd_box(
-                                            pos - node.pos.as_vec2(),
-                                            Vec2::splat(node.radius as f32),
-                                        ),
-                                    };
-                                    closest_d = d.min(closest_d);
-                                }
-                                for edge in tile_edges.iter().map(|k| edges.get(*k).unwrap()) {
-                                    let a = nodes
-                                        .get(edge.start)
-                                        .map(|n| (n.pos.as_vec2(), n.radius as f32));
-                                    let b = nodes
-                                        .get(edge.end)
-                                        .map(|n| (n.pos.as_vec2(), n.radius as f32));
-                                    if let Some(((a_pos, a_r), (b_pos, b_r))) = a.zip(b) {
-                                        let d = sd_trapezoid(pos, a_pos, b_pos, a_r, b_r);
-                                        closest_d = d.min(closest_d);
-                                    }
-                                }
-
-                                if material != 0 {
-                                    closest_d = sd_outline(closest_d, half_thickness);
-
-                                    for segment in tile_plant_segments
-                                        .iter()
-                                        .map(|k| plant_segments.get(*k).unwrap())
-                                    {
-                                        let d = sd_trapezoid(
-                                            pos,
-                                            segment.start,
-                                            segment.end,
-                                            segment.start_thickness,
-                                            segment.end_thickness,
-                                        );
-                                        closest_d = d.min(closest_d);
-                                    }
-                                }
-
-                                if material == 0 {
-                                    tile[index] = tile[index].max(-closest_d);
-                                } else {
-                                    tile[index] = tile[index].min(closest_d);
-                                }
+                        let tile_nodes = node_cache[material]
+                            .get(&tile_key)
+                            .map(|v| v.as_slice())
+                            .unwrap_or(&[]);
+                        let tile_edges = edge_cache[material]
+                            .get(&tile_key)
+                            .map(|v| v.as_slice())
+                            .unwrap_or(&[]);
+                        let tile_plant_segments = plant_cache[material]
+                            .get(&tile_key)
+                            .map(|v| v.as_slice())
+                            .unwrap_or(&[]);
+                        for index in 0..tile_size * tile_size {
+                            let x =
+                                (index & (tile_size - 1)) as i32 + tile_key.0 * tile_size as i32;
+                            let y = (index / tile_size) as i32 + tile_key.1 * tile_size as i32;
+                            let pos = (ivec2(x, y).as_vec2() + vec2(0.5, 0.5)) * cell_size_f;
+                            let mut closest_d = f32::MAX;
+                            // outline thickness is blended the same way as the shapes
+                            let mut thickness = DEFAULT_THICKNESS;
+
+                            for node in tile_nodes.iter().map(|k| nodes.get(*k).unwrap()) {
+                                let d = node.distance(pos);
+                                let blend = node.blend as f32;
+                                let weight = smooth_min_weight(closest_d, d, blend);
+                                thickness += (node.outline_thickness() - thickness) * weight;
+                                closest_d = smooth_min(closest_d, d, blend);
+                            }
+                            for &edge_key in tile_edges {
+                                let edge = &edges[edge_key];
+                                let segments = edge_segments.get(&edge_key).map(|v| v.as_slice());
+                                let d = edge.distance(nodes, segments, pos);
+                                let blend = edge.blend(nodes);
+                                let weight = smooth_min_weight(closest_d, d, blend);
+                                thickness += (edge.outline_thickness(nodes) - thickness) * weight;
+                                closest_d = smooth_min(closest_d, d, blend);
+                            }
+
+                            closest_d = sd_outline(closest_d, thickness * 0.5);
+
+                            for segment in tile_plant_segments
+                                .iter()
+                                .map(|k| plant_segments.get(*k).unwrap())
+                            {
+                                let d = sd_trapezoid(
+                                    pos,
+                                    segment.start,
+                                    segment.end,
+                                    segment.start_thickness,
+                                    segment.end_thickness,
+                                );
+                                closest_d = d.min(closest_d);
                             }
+
+                            tile[index] = closest_d;
                         }
                         (tile_key, tile)
                     },
@@ -293,9 +504,100 @@ impl GraphNode {
         let _span = span!("drop");
         drop(node_cache);
         drop(edge_cache);
+        drop(edge_segments);
         drop(plant_cache);
     }
 
+    /// Subtracts and intersects shapes of the layer with all materials of `field`, which
+    /// holds the composed grid, graph and plants of that layer
+    pub fn apply_operations(
+        field: &mut Field,
+        cell_size: i32,
+        layer_key: LayerKey,
+        nodes: &SlotMap<GraphNodeKey, GraphNode>,
+        edges: &SlotMap<GraphEdgeKey, GraphEdge>,
+    ) {
+        let _span = span!("GraphNode::apply_operations");
+
+        // subtracted shapes go first, so that intersections can not restore them
+        let mut shapes = Vec::new();
+        for operation in [NodeOperation::Subtract, NodeOperation::Intersect] {
+            for (key, node) in nodes
+                .iter()
+                .filter(|(_, n)| n.layer == layer_key && n.operation == operation)
+            {
+                shapes.push((operation, Shape::Node(key), node.bounds()));
+            }
+            for (key, edge) in edges
+                .iter()
+                .filter(|(_, e)| e.is_in_layer(nodes, layer_key) && e.operation(nodes) == operation)
+            {
+                let Some(bounds) = edge.bounds(nodes) else { continue };
+                shapes.push((operation, Shape::Edge(key), bounds));
+            }
+        }
+        if shapes.is_empty() {
+            return;
+        }
+        let edge_segments: HashMap<GraphEdgeKey, Vec<EdgeSegment>> = shapes
+            .iter()
+            .filter_map(|&(_, shape, _)| match shape {
+                Shape::Edge(key) if !edges[key].control_points.is_empty() => Some(key),
+                _ => None,
+            })
+            .filter_map(|key| Some((key, edges[key].segments(nodes)?)))
+            .collect();
+
+        let cell_size_f = cell_size as f32;
+        let tile_size = field.tile_size;
+        field
+            .materials
+            .par_iter_mut()
+            .skip(1)
+            .flat_map(|tiles| tiles.par_iter_mut())
+            .for_each(|(&tile_key, tile)| {
+                // subtracted shapes change only tiles they touch, intersected ones all of them
+                let tile_shapes: Vec<_> = shapes
+                    .iter()
+                    .filter(|(operation, _, bounds)| {
+                        *operation == NodeOperation::Intersect
+                            || Field::world_to_tile_range(
+                                bounds.inflate(GRAPH_TILE_PADDING),
+                                cell_size,
+                                tile_size,
+                            )
+                            .contains_point(ivec2(tile_key.0, tile_key.1))
+                    })
+                    .collect();
+                if tile_shapes.is_empty() {
+                    return;
+                }
+                for (index, d) in tile.iter_mut().enumerate() {
+                    let x = (index & (tile_size - 1)) as i32 + tile_key.0 * tile_size as i32;
+                    let y = (index / tile_size) as i32 + tile_key.1 * tile_size as i32;
+                    let pos = (ivec2(x, y).as_vec2() + vec2(0.5, 0.5)) * cell_size_f;
+                    for &&(operation, shape, _) in &tile_shapes {
+                        let (shape_d, blend) = match shape {
+                            Shape::Node(key) => (nodes[key].distance(pos), nodes[key].blend as f32),
+                            Shape::Edge(key) => (
+                                edges[key].distance(
+                                    nodes,
+                                    edge_segments.get(&key).map(|v| v.as_slice()),
+                                    pos,
+                                ),
+                                edges[key].blend(nodes),
+                            ),
+                        };
+                        let shape_d = match operation {
+                            NodeOperation::Subtract => -shape_d,
+                            _ => shape_d,
+                        };
+                        *d = smooth_max(*d, shape_d, blend);
+                    }
+                }
+            });
+    }
+
     pub fn split_edge_node(
         nodes: &SlotMap<GraphNodeKey, GraphNode>,
         edges: &SlotMap<GraphEdgeKey, GraphEdge>,
@@ -310,15 +612,14 @@ impl GraphNode {
             if let Some((start, end)) = nodes.get(edge.start).zip(nodes.get(edge.end)) {
                 let mut node = start.clone();
                 node.radius = node.radius.min(end.radius);
-                if end.no_outline == false {
-                    node.no_outline = false;
+                if end.operation == NodeOperation::Union {
+                    node.operation = NodeOperation::Union;
                 }
                 match split_pos {
                     SplitPos::Fraction(f) => {
-                        pos = start
-                            .pos
-                            .as_vec2()
-                            .lerp(end.pos.as_vec2(), f)
+                        pos = edge
+                            .point(nodes, f)
+                            .unwrap_or(start.pos.as_vec2())
                             .floor()
                             .as_ivec2();
                     }
@@ -328,6 +629,7 @@ impl GraphNode {
         }
         GraphNode {
             pos,
+            symmetry_group: None,
             ..default_node.unwrap_or_else(|| GraphNode::new())
         }
     }
@@ -347,9 +649,11 @@ impl GraphNode {
         for key in keys {
             let Some(node ) = nodes.get(key) else { continue };
 
+            // only nodes of the same layer are merged
             let closest_node = other_keys
                 .iter()
                 .cloned()
+                .filter(|&k| nodes[k].layer == node.layer)
                 .map(|k| {
                     (
                         NotNan::new((node.pos - nodes[k].pos).as_vec2().length() as f32).unwrap(),
@@ -367,25 +671,268 @@ impl GraphNode {
         result.sort_unstable();
         result
     }
+
+    /// Older documents carve shapes out with `no_outline` flags, these become subtracting
+    /// operations
+    pub(crate) fn upgrade_no_outline(
+        nodes: &mut SlotMap<GraphNodeKey, GraphNode>,
+        edges: &mut SlotMap<GraphEdgeKey, GraphEdge>,
+    ) {
+        let edge_operations: Vec<(GraphEdgeKey, NodeOperation)> = edges
+            .iter()
+            .map(|(key, edge)| {
+                let no_outline = edge.no_outline.unwrap_or_else(|| {
+                    [edge.start, edge.end]
+                        .iter()
+                        .any(|k| nodes.get(*k).is_some_and(|n| n.no_outline))
+                });
+                let operation = match edge.operation(nodes) {
+                    NodeOperation::Union if no_outline => NodeOperation::Subtract,
+                    operation => operation,
+                };
+                (key, operation)
+            })
+            .collect();
+        for node in nodes.values_mut() {
+            if std::mem::take(&mut node.no_outline) && node.operation == NodeOperation::Union {
+                node.operation = NodeOperation::Subtract;
+            }
+        }
+        for (key, operation) in edge_operations {
+            let edge = &mut edges[key];
+            edge.no_outline = None;
+            if edge.operation(nodes) != operation {
+                edge.operation = Some(operation);
+            }
+        }
+    }
 }
 
 impl GraphEdge {
+    pub fn new(start: GraphNodeKey, end: GraphNodeKey) -> GraphEdge {
+        GraphEdge {
+            start,
+            end,
+            control_points: Vec::new(),
+            material: None,
+            radius: None,
+            operation: None,
+            no_outline: None,
+        }
+    }
+
+    /// Material the edge is drawn with
+    pub fn material(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> u8 {
+        let a = nodes.get(self.start);
+        let b = nodes.get(self.end);
+        self.material
+            .or_else(|| a.map(|a| a.material))
+            .or_else(|| b.map(|b| b.material))
+            .unwrap_or(1)
+    }
+
+    /// Edges between nodes with the same operation share it, other edges are united
+    pub fn operation(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> NodeOperation {
+        if let Some(operation) = self.operation {
+            return operation;
+        }
+        let a = nodes.get(self.start).map(|n| n.operation);
+        let b = nodes.get(self.end).map(|n| n.operation);
+        match (a, b) {
+            (Some(a), Some(b)) if a == b => a,
+            _ => NodeOperation::Union,
+        }
+    }
+
+    /// Edges take the larger outline thickness of their nodes
+    pub fn outline_thickness(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> f32 {
+        [self.start, self.end]
+            .iter()
+            .filter_map(|k| nodes.get(*k))
+            .map(|n| n.outline_thickness())
+            .fold(0.0, f32::max)
+    }
+
+    /// Edges are blended with the larger blend radius of their nodes
+    pub fn blend(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> f32 {
+        [self.start, self.end]
+            .iter()
+            .filter_map(|k| nodes.get(*k))
+            .map(|n| n.blend as f32)
+            .fold(0.0, f32::max)
+    }
+
+    /// Radii at the start and the end of the edge
+    pub fn radii(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> Option<[f32; 2]> {
+        if let Some([start, end]) = self.radius {
+            return Some([start as f32, end as f32]);
+        }
+        Some([
+            nodes.get(self.start)?.radius as f32,
+            nodes.get(self.end)?.radius as f32,
+        ])
+    }
+
+    /// Splits the edge at curve position `t`, new node is expected to be placed at that
+    /// position
     pub(crate) fn split_edge(
         edges: &mut SlotMap<GraphEdgeKey, GraphEdge>,
+        nodes: &SlotMap<GraphNodeKey, GraphNode>,
         key: GraphEdgeKey,
         node_key: GraphNodeKey,
+        t: f32,
     ) -> GraphNodeKey {
         if let Some(edge) = edges.get_mut(key) {
+            let (start_points, end_points) = match edge.curve_points(nodes) {
+                Some(points) if !edge.control_points.is_empty() => {
+                    let (start, end) = split_bezier(&points, t);
+                    let inner = |points: &[Vec2]| {
+                        points[1..points.len() - 1]
+                            .iter()
+                            .map(|p| p.round().as_ivec2())
+                            .collect()
+                    };
+                    (inner(&start), inner(&end))
+                }
+                _ => (Vec::new(), Vec::new()),
+            };
+            let (start_radius, end_radius) = match edge.radius {
+                Some([start, end]) => {
+                    let split = (start as f32 + (end as f32 - start as f32) * t).round() as usize;
+                    (Some([start, split]), Some([split, end]))
+                }
+                None => (None, None),
+            };
             let old_end = edge.end;
-            edge.end = node_key;
-            edges.insert(GraphEdge {
+            let end_edge = GraphEdge {
                 start: node_key,
                 end: old_end,
-            });
+                control_points: end_points,
+                radius: end_radius,
+                ..edge.clone()
+            };
+            edge.end = node_key;
+            edge.control_points = start_points;
+            edge.radius = start_radius;
+            edges.insert(end_edge);
         }
         node_key
     }
 
+    /// Start node, control points and end node positions
+    pub fn curve_points(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> Option<Vec<Vec2>> {
+        let start = nodes.get(self.start)?;
+        let end = nodes.get(self.end)?;
+        let mut points = Vec::with_capacity(self.control_points.len() + 2);
+        points.push(start.pos.as_vec2());
+        points.extend(self.control_points.iter().map(|p| p.as_vec2()));
+        points.push(end.pos.as_vec2());
+        Some(points)
+    }
+
+    pub fn point(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>, t: f32) -> Option<Vec2> {
+        Some(bezier_point(&self.curve_points(nodes)?, t))
+    }
+
+    /// Curve positions of points that approximate the edge, a single segment for straight edges
+    pub fn segment_positions(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> Vec<f32> {
+        let Some(points) = self.curve_points(nodes) else { return Vec::new() };
+        if self.control_points.is_empty() {
+            return vec![0.0, 1.0];
+        }
+        // control polygon is never shorter than the curve
+        let length: f32 = points.windows(2).map(|w| (w[1] - w[0]).length()).sum();
+        let num_segments = ((length / EDGE_SEGMENT_LENGTH).ceil() as usize).clamp(4, 64);
+        (0..=num_segments)
+            .map(|i| i as f32 / num_segments as f32)
+            .collect()
+    }
+
+    /// Radius tapers between radii of the nodes along the length of the curve
+    pub fn segments(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> Option<Vec<EdgeSegment>> {
+        let points = self.curve_points(nodes)?;
+        let [start_radius, end_radius] = self.radii(nodes)?;
+        let positions: Vec<Vec2> = self
+            .segment_positions(nodes)
+            .into_iter()
+            .map(|t| bezier_point(&points, t))
+            .collect();
+        let mut distances = vec![0.0];
+        for w in positions.windows(2) {
+            distances.push(distances.last().unwrap() + (w[1] - w[0]).length());
+        }
+        let length = distances.last().copied().unwrap_or(0.0).max(f32::EPSILON);
+        let radius = |distance: f32| start_radius + (end_radius - start_radius) * distance / length;
+        Some(
+            (0..positions.len().saturating_sub(1))
+                .map(|i| EdgeSegment {
+                    start: positions[i],
+                    end: positions[i + 1],
+                    start_radius: radius(distances[i]),
+                    end_radius: radius(distances[i + 1]),
+                })
+                .collect(),
+        )
+    }
+
+    /// Changes number of control points, keeping the shape of the curve where possible
+    pub fn set_control_point_count(
+        &mut self,
+        nodes: &SlotMap<GraphNodeKey, GraphNode>,
+        count: usize,
+    ) {
+        let Some(points) = self.curve_points(nodes) else { return };
+        let start = points[0];
+        let end = points[points.len() - 1];
+        let control_points = match (self.control_points.len(), count) {
+            (old, new) if old == new => return,
+            (_, 0) => Vec::new(),
+            // quadratic that passes through the middle of the curve
+            (_, 1) => vec![bezier_point(&points, 0.5) * 2.0 - (start + end) * 0.5],
+            // degree elevation of the quadratic is exact
+            (1, _) => vec![
+                start.lerp(points[1], 2.0 / 3.0),
+                end.lerp(points[1], 2.0 / 3.0),
+            ],
+            (_, _) => vec![start.lerp(end, 1.0 / 3.0), start.lerp(end, 2.0 / 3.0)],
+        };
+        self.control_points = control_points
+            .into_iter()
+            .map(|p| p.round().as_ivec2())
+            .collect();
+    }
+
+    /// Curve lies within bounds of its control points, inflated by the largest radius and
+    /// the blend
+    /// Edges belong to the layers of both of their nodes
+    pub fn is_in_layer(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>, layer: LayerKey) -> bool {
+        [self.start, self.end]
+            .iter()
+            .any(|k| nodes.get(*k).map(|n| n.layer) == Some(layer))
+    }
+
+    /// Signed distance from `pos` in world space, `segments` of curved edges can be cached by
+    /// the caller
+    pub fn distance(
+        &self,
+        nodes: &SlotMap<GraphNodeKey, GraphNode>,
+        segments: Option<&[EdgeSegment]>,
+        pos: Vec2,
+    ) -> f32 {
+        if let Some(segments) = segments {
+            return segments
+                .iter()
+                .map(|s| sd_uneven_capsule(pos, s.start, s.end, s.start_radius, s.end_radius))
+                .fold(f32::MAX, f32::min);
+        }
+        let a = nodes.get(self.start).map(|n| n.pos.as_vec2());
+        let b = nodes.get(self.end).map(|n| n.pos.as_vec2());
+        match a.zip(b).zip(self.radii(nodes)) {
+            Some(((a_pos, b_pos), [a_r, b_r])) => sd_trapezoid(pos, a_pos, b_pos, a_r, b_r),
+            None => f32::MAX,
+        }
+    }
+
     pub fn bounds(&self, nodes: &SlotMap<GraphNodeKey, GraphNode>) -> Option<[Vec2; 2]> {
         let mut b = Rect::invalid();
         if let Some(start_bounds) = nodes.get(self.start).map(|n| n.bounds()) {
@@ -394,6 +941,23 @@ impl GraphEdge {
         if let Some(end_bounds) = nodes.get(self.end).map(|n| n.bounds()) {
             b = b.union(end_bounds);
         }
+        let Some(points) = self.curve_points(nodes) else { return b.valid() };
+        let [start_radius, end_radius] = self.radii(nodes).unwrap_or_default();
+        let blend = self.blend(nodes) * 0.25;
+        let (start_radius, end_radius) = (start_radius + blend, end_radius + blend);
+        if self.radius.is_some() {
+            let ends = [
+                (points[0], start_radius),
+                (points[points.len() - 1], end_radius),
+            ];
+            for (point, radius) in ends {
+                b = b.union(<[Vec2; 2]>::from_point(point).inflate(radius));
+            }
+        }
+        let radius = start_radius.max(end_radius);
+        for &point in &points[1..points.len() - 1] {
+            b = b.union(<[Vec2; 2]>::from_point(point).inflate(radius));
+        }
         b.valid()
     }
 }
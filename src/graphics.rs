@@ -12,13 +12,14 @@ use zerocopy::AsBytes;
 use cbmap::{BuiltinMaterial, Material, MaterialSlot};
 
 use crate::app::SDFUniforms;
-use crate::document::{ChangeMask, Document, View};
+use crate::document::{ChangeMask, Document, GridKey, LayerKey, View};
 use crate::field::Field;
-use crate::graph::GraphNode;
+use crate::graph::{GraphNode, NodeOperation, GRAPH_TILE_PADDING};
 use crate::grid::Grid;
 use crate::math::Rect;
-use crate::plant::{PlantSegment, PlantSegmentKey};
+use crate::plant::GrownPlants;
 use crate::profiler::Profiler;
+use crate::rasterizer::SdfRasterizer;
 use crate::some_or;
 use std::collections::{HashMap, HashSet};
 use std::mem::replace;
@@ -29,146 +30,267 @@ pub struct DocumentGraphics {
     pub generated_grid: Grid<u8>,
     pub generated_distances: Field,
     pub distance_textures: Vec<HashMap<(i32, i32), Texture>>,
-    pub plant_segments: SlotMap<PlantSegmentKey, PlantSegment>,
+    pub grown_plants: GrownPlants,
+    /// Bounds of grids at the time of last generation
+    grid_bounds: HashMap<GridKey, [IVec2; 2]>,
+    /// Opacity groups of `generated_distances`
+    pub opacity_groups: Vec<OpacityGroup>,
 
     pub materials: Vec<MaterialSlot>,
     pub resolved_materials: Vec<Material>,
+    /// Outline widths of `resolved_materials`, in world units
+    pub outline_widths: Vec<f32>,
 
     pub reference_texture: Option<Texture>,
 }
 
+/// Layers that are composed together. In the editor view consecutive visible layers with the
+/// same opacity form a group, exported maps consist of a single opaque group. Layer groups of
+/// the document are flattened in order.
+///
+/// Materials of group `N` start at `N * materials.len()` in `generated_distances`.
+#[derive(Clone, PartialEq)]
+pub struct OpacityGroup {
+    pub opacity: f32,
+    pub layers: Vec<LayerKey>,
+}
+
+impl OpacityGroup {
+    pub fn from_document(doc: &Document, is_export: bool) -> Vec<OpacityGroup> {
+        let mut groups: Vec<OpacityGroup> = Vec::new();
+        for layer_key in doc.flat_layers() {
+            let layer = some_or!(doc.layers.get(layer_key), continue);
+            if !is_export && doc.is_layer_hidden(layer_key) {
+                continue;
+            }
+            let opacity = if is_export {
+                1.0
+            } else {
+                layer.opacity.clamp(0.0, 1.0)
+            };
+            match groups.last_mut() {
+                Some(group) if group.opacity == opacity => group.layers.push(layer_key),
+                _ => groups.push(OpacityGroup {
+                    opacity,
+                    layers: vec![layer_key],
+                }),
+            }
+        }
+        groups
+    }
+}
+
 impl DocumentGraphics {
+    pub fn new() -> DocumentGraphics {
+        DocumentGraphics {
+            cell_size: 4,
+            generated_grid: Grid {
+                default_value: 0,
+                bounds: Rect::zero(),
+                cells: vec![],
+            },
+            generated_distances: Field::new(),
+            distance_textures: Default::default(),
+            reference_texture: None,
+            resolved_materials: Vec::new(),
+            outline_widths: Vec::new(),
+            materials: Vec::new(),
+            grown_plants: GrownPlants::default(),
+            grid_bounds: HashMap::new(),
+            opacity_groups: Vec::new(),
+        }
+    }
+
     pub(crate) fn generate(
         &mut self,
         doc: &Document,
-        change_mask: ChangeMask,
+        change_mask: &ChangeMask,
         is_export: bool,
         mut context: Option<&mut Context>,
         profiler: &mut Profiler,
     ) {
         start_noncontinuous_frame!("generate");
+        let tile_range = self.dirty_tile_range(doc, change_mask);
         self.cell_size = doc.cell_size;
         let _span = span!("DocumentGraphics::generate");
-        if change_mask.cell_layers != 0 {
-            self.generate_cells(doc, change_mask.cell_layers, is_export, profiler);
-            self.materials = doc.materials.clone();
-            self.resolved_materials = doc
-                .materials
-                .iter()
-                .map(|m| {
-                    m.to_material().unwrap_or_else(|| Material {
-                        fill_color: [255, 0, 0],
-                        outline_color: [255, 0, 0],
-                        custom_name: String::new(),
-                    })
-                })
-                .collect();
+        if change_mask.has_cell_changes() {
+            self.generate_cells(doc, tile_range, is_export, profiler);
+            self.update_materials(doc);
 
             if let Some(context) = &mut context {
-                while self.distance_textures.len() < self.generated_distances.materials.len() {
-                    self.distance_textures.push(Default::default());
+                // padding of textures is copied from neighbouring tiles
+                let upload_range = tile_range.map(|r| r.inflate(1));
+                self.upload_distance_textures(context, upload_range);
+            }
+        }
+
+        if change_mask.reference_path {
+            self.generate_reference(doc, context)
+        }
+        finish_continuous_frame!("generate");
+    }
+
+    pub(crate) fn update_materials(&mut self, doc: &Document) {
+        self.materials = doc.materials.clone();
+        self.resolved_materials = doc
+            .materials
+            .iter()
+            .map(|m| {
+                m.to_material().unwrap_or_else(|| Material {
+                    fill_color: [255, 0, 0],
+                    outline_color: [255, 0, 0],
+                    custom_name: String::new(),
+                })
+            })
+            .collect();
+        self.outline_widths = (0..doc.materials.len())
+            .map(|index| {
+                doc.outline_widths
+                    .get(index)
+                    .copied()
+                    .flatten()
+                    .unwrap_or(DEFAULT_OUTLINE_WIDTH)
+            })
+            .collect();
+    }
+
+    /// Replaces distance tiles within `tile_range` with tiles generated elsewhere
+    pub(crate) fn replace_tiles(
+        &mut self,
+        context: &mut Context,
+        tile_range: [IVec2; 2],
+        materials: Vec<HashMap<(i32, i32), Vec<f32>>>,
+    ) {
+        let _span = span!("DocumentGraphics::replace_tiles");
+        for tiles in &mut self.generated_distances.materials {
+            tiles.retain(|&(x, y), _| !tile_range.contains_point(ivec2(x, y)));
+        }
+        while self.generated_distances.materials.len() < materials.len() {
+            self.generated_distances.materials.push(Default::default());
+        }
+        for (material, tiles) in materials.into_iter().enumerate() {
+            self.generated_distances.materials[material].extend(tiles);
+        }
+        // padding of textures is copied from neighbouring tiles
+        self.upload_distance_textures(context, Some(tile_range.inflate(1)));
+    }
+
+    /// Removes distance tiles and their textures outside of `tile_range`
+    pub(crate) fn retain_tiles(&mut self, tile_range: [IVec2; 2]) {
+        let in_range = |&(x, y): &(i32, i32)| tile_range.contains_point(ivec2(x, y));
+        for tiles in &mut self.generated_distances.materials {
+            tiles.retain(|key, _| in_range(key));
+        }
+        for textures in &mut self.distance_textures {
+            textures.retain(|key, tex| {
+                if !in_range(key) {
+                    tex.delete();
                 }
-                let tile_size = self.generated_distances.tile_size as i32;
-                for (material, tiles) in self.generated_distances.materials.iter().enumerate() {
-                    let mut unused_tiles = self.distance_textures[material]
-                        .keys()
-                        .copied()
-                        .collect::<HashSet<_>>();
-
-                    for (&tile_key, _tile) in tiles {
-                        unused_tiles.remove(&tile_key);
-
-                        // prepare texture content, add some padding using neighbouring distance tiles to
-                        // aid correct filtering
-                        let padding = DISTANCE_TEXTURE_PADDING as i32;
-                        let w = self.generated_distances.tile_size as u32 + padding as u32 * 2;
-                        let h = self.generated_distances.tile_size as u32 + padding as u32 * 2;
-                        let mut padded_distances = vec![f32::MAX; w as usize * h as usize];
-                        let rect_of_interest = [
-                            ivec2(
-                                tile_key.0 * tile_size - padding,
-                                tile_key.1 * tile_size - padding,
-                            ),
-                            ivec2(
-                                (tile_key.0 + 1) * tile_size + padding,
-                                (tile_key.1 + 1) * tile_size + padding,
-                            ),
-                        ];
-                        for j in (tile_key.1 - 1)..=(tile_key.1 + 1) {
-                            for i in (tile_key.0 - 1)..=(tile_key.0 + 1) {
-                                let key = (i, j);
-                                let tile = some_or!(
-                                    self.generated_distances.materials[material].get(&key),
-                                    continue
-                                );
-                                let copied_rect = [
-                                    ivec2(key.0 * tile_size, key.1 * tile_size),
-                                    ivec2((key.0 + 1) * tile_size, (key.1 + 1) * tile_size),
-                                ]
-                                .intersect(rect_of_interest)
-                                .unwrap();
-
-                                for y in copied_rect[0].y..copied_rect[1].y {
-                                    for x in copied_rect[0].x..copied_rect[1].x {
-                                        let dx = x - rect_of_interest[0].x;
-                                        let dy = y - rect_of_interest[0].y;
-                                        let sx = x & (tile_size - 1);
-                                        let sy = y & (tile_size - 1);
-                                        padded_distances[(dy * w as i32 + dx) as usize] =
-                                            tile[(sy * tile_size + sx) as usize];
-                                    }
-                                }
+                in_range(key)
+            });
+        }
+    }
+
+    /// Updates textures of tiles within `tile_range`, or of all tiles when it is omitted
+    fn upload_distance_textures(&mut self, context: &mut Context, tile_range: Option<[IVec2; 2]>) {
+        let _span = span!("DocumentGraphics::upload_distance_textures");
+        let in_range =
+            |&(x, y): &(i32, i32)| tile_range.is_none_or(|r| r.contains_point(ivec2(x, y)));
+        while self.distance_textures.len() < self.generated_distances.materials.len() {
+            self.distance_textures.push(Default::default());
+        }
+        let tile_size = self.generated_distances.tile_size as i32;
+        for (material, tiles) in self.generated_distances.materials.iter().enumerate() {
+            let mut unused_tiles = self.distance_textures[material]
+                .keys()
+                .copied()
+                .filter(in_range)
+                .collect::<HashSet<_>>();
+
+            for (&tile_key, _tile) in tiles.iter().filter(|(key, _)| in_range(key)) {
+                unused_tiles.remove(&tile_key);
+
+                // prepare texture content, add some padding using neighbouring distance tiles to
+                // aid correct filtering
+                let padding = DISTANCE_TEXTURE_PADDING as i32;
+                let w = self.generated_distances.tile_size as u32 + padding as u32 * 2;
+                let h = self.generated_distances.tile_size as u32 + padding as u32 * 2;
+                let mut padded_distances = vec![f32::MAX; w as usize * h as usize];
+                let rect_of_interest = [
+                    ivec2(
+                        tile_key.0 * tile_size - padding,
+                        tile_key.1 * tile_size - padding,
+                    ),
+                    ivec2(
+                        (tile_key.0 + 1) * tile_size + padding,
+                        (tile_key.1 + 1) * tile_size + padding,
+                    ),
+                ];
+                for j in (tile_key.1 - 1)..=(tile_key.1 + 1) {
+                    for i in (tile_key.0 - 1)..=(tile_key.0 + 1) {
+                        let key = (i, j);
+                        let tile = some_or!(
+                            self.generated_distances.materials[material].get(&key),
+                            continue
+                        );
+                        let copied_rect = [
+                            ivec2(key.0 * tile_size, key.1 * tile_size),
+                            ivec2((key.0 + 1) * tile_size, (key.1 + 1) * tile_size),
+                        ]
+                        .intersect(rect_of_interest)
+                        .unwrap();
+
+                        for y in copied_rect[0].y..copied_rect[1].y {
+                            for x in copied_rect[0].x..copied_rect[1].x {
+                                let dx = x - rect_of_interest[0].x;
+                                let dy = y - rect_of_interest[0].y;
+                                let sx = x & (tile_size - 1);
+                                let sy = y & (tile_size - 1);
+                                padded_distances[(dy * w as i32 + dx) as usize] =
+                                    tile[(sy * tile_size + sx) as usize];
                             }
                         }
-                        let bytes_slice = padded_distances.as_bytes();
-
-                        let texture_params = TextureParams {
-                            format: TextureFormat::Alpha32F,
-                            wrap: TextureWrap::Clamp,
-                            filter: FilterMode::Linear,
-                            width: w,
-                            height: h,
-                            ..Default::default()
-                        };
+                    }
+                }
+                let bytes_slice = padded_distances.as_bytes();
 
-                        let _span = span!("texture update");
+                let texture_params = TextureParams {
+                    format: TextureFormat::Alpha32F,
+                    wrap: TextureWrap::Clamp,
+                    filter: FilterMode::Linear,
+                    width: w,
+                    height: h,
+                    ..Default::default()
+                };
 
-                        while material >= self.distance_textures.len() {
-                            self.distance_textures.push(Default::default())
-                        }
+                let _span = span!("texture update");
 
-                        self.distance_textures[material]
-                            .entry(tile_key)
-                            .and_modify(|tex| {
-                                if tex.width == w && tex.height == h {
-                                    tex.update(context, bytes_slice);
-                                } else {
-                                    tex.delete();
-                                    *tex = Texture::from_data_and_format(
-                                        context,
-                                        bytes_slice,
-                                        texture_params,
-                                    );
-                                }
-                            })
-                            .or_insert_with(|| {
-                                Texture::from_data_and_format(context, bytes_slice, texture_params)
-                            });
-                    }
+                while material >= self.distance_textures.len() {
+                    self.distance_textures.push(Default::default())
+                }
 
-                    for tile_key in unused_tiles {
-                        if let Some(tex) = self.distance_textures[material].remove(&tile_key) {
+                self.distance_textures[material]
+                    .entry(tile_key)
+                    .and_modify(|tex| {
+                        if tex.width == w && tex.height == h {
+                            tex.update(context, bytes_slice);
+                        } else {
                             tex.delete();
+                            *tex =
+                                Texture::from_data_and_format(context, bytes_slice, texture_params);
                         }
-                    }
-                }
+                    })
+                    .or_insert_with(|| {
+                        Texture::from_data_and_format(context, bytes_slice, texture_params)
+                    });
             }
-        }
 
-        if change_mask.reference_path {
-            self.generate_reference(doc, context)
+            for tile_key in unused_tiles {
+                if let Some(tex) = self.distance_textures[material].remove(&tile_key) {
+                    tex.delete();
+                }
+            }
         }
-        finish_continuous_frame!("generate");
     }
 
     fn generate_reference(&mut self, doc: &Document, mut context: Option<&mut Context>) {
@@ -212,74 +334,282 @@ impl DocumentGraphics {
         }
     }
 
-    fn generate_cells(
+    /// Range of distance tiles affected by `change_mask`, `None` when the whole map has to
+    /// be regenerated.
+    pub(crate) fn dirty_tile_range(
+        &self,
+        doc: &Document,
+        change_mask: &ChangeMask,
+    ) -> Option<[IVec2; 2]> {
+        if change_mask.all_layers || self.cell_size != doc.cell_size {
+            return None;
+        }
+        let opacity_groups = OpacityGroup::from_document(doc, false);
+        if self.opacity_groups != opacity_groups
+            || self.generated_distances.materials.len()
+                != doc.materials.len() * opacity_groups.len().max(1)
+        {
+            return None;
+        }
+        // intersected shapes clip the whole layer, so any change within it can reach far
+        let has_intersection = |layer: LayerKey| {
+            doc.nodes
+                .values()
+                .any(|n| n.layer == layer && n.operation == NodeOperation::Intersect)
+                || doc.edges.values().any(|e| {
+                    e.is_in_layer(&doc.nodes, layer)
+                        && e.operation(&doc.nodes) == NodeOperation::Intersect
+                })
+        };
+        if change_mask.cell_layers.iter().any(|&l| has_intersection(l)) {
+            return None;
+        }
+        let field_cell_size = doc.cell_size / 2;
+        let mut cell_rect =
+            Grid::<f32>::world_to_grid_rect(change_mask.cell_rect?, field_cell_size);
+
+        // resized grids get tiles for new cells, or lose tiles of removed cells
+        let mut add_grid_bounds = |bounds: [IVec2; 2]| {
+            if !bounds.is_null() {
+                cell_rect = cell_rect.union([bounds[0] * 2, bounds[1] * 2]);
+            }
+        };
+        for (grid_key, grid) in &doc.grids {
+            let old_bounds = self.grid_bounds.get(&grid_key).copied();
+            if old_bounds != Some(grid.bounds) {
+                add_grid_bounds(grid.bounds);
+                add_grid_bounds(old_bounds.unwrap_or(Rect::zero()));
+            }
+        }
+        for (&grid_key, &old_bounds) in &self.grid_bounds {
+            if !doc.grids.contains_key(grid_key) {
+                add_grid_bounds(old_bounds);
+            }
+        }
+        // distances of a grid depend on all of its cells
+        for layer_key in &change_mask.cell_layers {
+            let Some(layer) = doc.layers.get(*layer_key) else { continue };
+            if let Some(grid) = doc.grids.get(layer.grid) {
+                add_grid_bounds(grid.bounds);
+            }
+        }
+
+        // a changed grid cell affects its EPX neighbours
+        let grid_padding = 2;
+        let graph_padding = (GRAPH_TILE_PADDING / field_cell_size as f32).ceil() as i32;
+        let tile_range = Field::grid_to_tile_range(
+            cell_rect.inflate(grid_padding.max(graph_padding)),
+            self.generated_distances.tile_size,
+        );
+        // plants can grow differently far from the change
+        Some(self.surface_plants_tile_range(doc, tile_range))
+    }
+
+    /// Plants that react to their surroundings read distances around the whole plant, so their
+    /// tiles are regenerated together. Otherwise growth would depend on the generated range.
+    fn surface_plants_tile_range(&self, doc: &Document, tile_range: [IVec2; 2]) -> [IVec2; 2] {
+        let plant_ranges: Vec<[IVec2; 2]> = doc
+            .plants
+            .values()
+            .filter(|p| p.reacts_to_surroundings())
+            .map(|p| {
+                let bounds = p.bounds().inflate(GRAPH_TILE_PADDING);
+                Field::world_to_tile_range(
+                    bounds,
+                    doc.cell_size / 2,
+                    self.generated_distances.tile_size,
+                )
+            })
+            .collect();
+        // expanded range can reach further plants
+        let mut result = tile_range;
+        loop {
+            let expanded = plant_ranges
+                .iter()
+                .filter(|r| r.intersect(result).is_some())
+                .fold(result, |acc, r| acc.union(*r));
+            if expanded == result {
+                return result;
+            }
+            result = expanded;
+        }
+    }
+
+    /// Range of tiles that can be produced by `generate_cells` for the whole map
+    pub(crate) fn document_tile_range(&self, doc: &Document) -> [IVec2; 2] {
+        let field_cell_size = doc.cell_size / 2;
+        let mut cell_rect: [IVec2; 2] = Rect::invalid();
+        for grid in doc.grids.values() {
+            if !grid.bounds.is_null() {
+                cell_rect = cell_rect.union([grid.bounds[0] * 2, grid.bounds[1] * 2]);
+            }
+        }
+
+        let mut bounds: [Vec2; 2] = Rect::invalid();
+        for node in doc.nodes.values() {
+            bounds = bounds.union(node.bounds());
+        }
+        for edge in doc.edges.values() {
+            let Some(edge_bounds) = edge.bounds(&doc.nodes) else { continue };
+            bounds = bounds.union(edge_bounds);
+        }
+        for plant in doc.plants.values() {
+            bounds = bounds.union(plant.bounds());
+        }
+        if bounds.is_valid() {
+            cell_rect = cell_rect.union(Grid::<f32>::world_to_grid_rect(
+                bounds.inflate(GRAPH_TILE_PADDING),
+                field_cell_size,
+            ));
+        }
+
+        if !cell_rect.is_valid() {
+            return Rect::zero();
+        }
+        Field::grid_to_tile_range(cell_rect, self.generated_distances.tile_size)
+    }
+
+    pub(crate) fn clear_cells(&mut self) {
+        self.generated_grid.clear();
+        self.generated_distances.materials.clear();
+    }
+
+    /// Regenerates distance tiles within `tile_range`, or all of them when it is omitted
+    pub(crate) fn generate_cells(
         &mut self,
         doc: &Document,
-        layer_mask: u64,
+        tile_range: Option<[IVec2; 2]>,
         is_export: bool,
         profiler: &mut Profiler,
     ) {
         let _span = span!("DocumentGraphics::generate_cells");
         profiler.open_block("generate_cells");
 
+        if tile_range.is_none() {
+            self.clear_cells();
+        }
+
         let cell_size = doc.cell_size;
-        let mut generated_bitmap = replace(&mut self.generated_grid, Grid::new(0));
+        let tile_range = tile_range.map(|r| self.surface_plants_tile_range(doc, r));
+        let generated_bitmap = replace(&mut self.generated_grid, Grid::new(0));
         let mut generated_distances = replace(&mut self.generated_distances, Field::new());
 
-        if layer_mask == u64::MAX {
-            generated_bitmap.clear();
-
+        if let Some(tile_range) = tile_range {
             for grid in &mut generated_distances.materials {
-                grid.clear();
-            }
-        } else {
-            generated_bitmap.cells.fill(0);
-
-            for grid in &mut generated_distances.materials {
-                grid.clear();
+                grid.retain(|&(x, y), _| !tile_range.contains_point(ivec2(x, y)));
             }
         }
 
-        while generated_distances.materials.len() < doc.materials.len() {
-            generated_distances.materials.push(Default::default());
-        }
+        let opacity_groups = OpacityGroup::from_document(doc, is_export);
+        let num_materials = doc.materials.len();
+        generated_distances.materials.resize_with(
+            num_materials * opacity_groups.len().max(1),
+            Default::default,
+        );
 
-        for &layer_key in doc.layer_order.iter() {
-            let layer = &doc.layers[layer_key];
-            if layer.hidden && !is_export {
-                continue;
-            }
-            profiler.open_block("Layer");
+        for (group_index, group) in opacity_groups.iter().enumerate() {
+            // tiles do not depend on each other, so regenerated tiles are composed separately
+            let mut composed = Field::new();
+            for &layer_key in &group.layers {
+                let layer = &doc.layers[layer_key];
+                profiler.open_block("Layer");
+
+                // operations of the layer apply to its grid, graph and plants together
+                let mut layer_field = Field::new();
+                layer_field
+                    .materials
+                    .resize_with(num_materials, Default::default);
+                if let Some(grid) = doc.grids.get(layer.grid) {
+                    let _span = span!("Grid");
+                    let field = Field::from_grid(grid, num_materials, cell_size, tile_range);
+                    layer_field.compose(&field);
+                }
 
-            if let Some(grid) = doc.grids.get(layer.grid) {
-                let _span = span!("Grid");
-                let field = Field::from_grid(grid, doc.materials.len(), cell_size);
-                generated_distances.compose(&field);
-            }
+                let reacting_plants = doc
+                    .plants
+                    .values()
+                    .any(|p| p.layer == layer_key && p.reacts_to_surroundings());
+                // plants see the rest of their layer, but not each other
+                let surroundings = reacting_plants.then(|| {
+                    let _span = span!("Plant Surroundings");
+                    let mut f = Field::new();
+                    f.materials.resize_with(num_materials, Default::default);
+                    GraphNode::render_distances(
+                        &mut f,
+                        cell_size / 2,
+                        layer_key,
+                        &doc.nodes,
+                        &doc.edges,
+                        &SlotMap::with_key(),
+                        &SlotMap::with_key(),
+                        tile_range,
+                    );
+                    let mut layer_surroundings = layer_field.clone();
+                    layer_surroundings.compose(&f);
+                    GraphNode::apply_operations(
+                        &mut layer_surroundings,
+                        cell_size / 2,
+                        layer_key,
+                        &doc.nodes,
+                        &doc.edges,
+                    );
+                    let mut surroundings = composed.clone();
+                    surroundings.compose(&layer_surroundings);
+                    surroundings
+                });
+                self.grown_plants.grow_layer(
+                    surroundings.as_ref().unwrap_or(&composed),
+                    cell_size / 2,
+                    layer_key,
+                    &doc.plants,
+                );
 
-            {
-                let _span = span!("Graph");
-                let mut f = Field::new();
-                for _i in 0..doc.materials.len() {
-                    f.materials.push(Default::default());
+                {
+                    let _span = span!("Graph");
+                    let mut f = Field::new();
+                    for _i in 0..num_materials {
+                        f.materials.push(Default::default());
+                    }
+                    GraphNode::render_distances(
+                        &mut f,
+                        cell_size / 2,
+                        layer_key,
+                        &doc.nodes,
+                        &doc.edges,
+                        &doc.plants,
+                        &self.grown_plants.segments[&layer_key],
+                        tile_range,
+                    );
+                    layer_field.compose(&f);
                 }
-                GraphNode::render_distances(
-                    &mut f,
+                GraphNode::apply_operations(
+                    &mut layer_field,
                     cell_size / 2,
                     layer_key,
                     &doc.nodes,
                     &doc.edges,
-                    &doc.plants,
-                    &mut self.plant_segments,
                 );
-                generated_distances.compose(&f);
+                composed.compose(&layer_field);
+
+                profiler.close_block();
             }
 
-            profiler.close_block();
+            // materials that are missing in the document can not be drawn
+            composed.materials.truncate(num_materials);
+            let first_material = group_index * num_materials;
+            for (material, tiles) in composed.materials.into_iter().enumerate() {
+                generated_distances.materials[first_material + material].extend(tiles);
+            }
         }
 
         self.generated_grid = generated_bitmap;
         self.generated_distances = generated_distances;
+        self.opacity_groups = opacity_groups;
+        self.grid_bounds = doc
+            .grids
+            .iter()
+            .map(|(key, grid)| (key, grid.bounds))
+            .collect();
 
         profiler.close_block();
     }
@@ -310,6 +640,7 @@ impl DocumentGraphics {
         context.apply_uniforms(&SDFUniforms {
             fill_color: [0.5, 0.5, 0.5, 1.0],
             outline_color: [0.75, 0.75, 0.75, 1.0],
+            outline_width: DEFAULT_OUTLINE_WIDTH,
             world_to_screen_xy,
             world_to_screen_pos,
             screen_size: window_size.into(),
@@ -318,7 +649,14 @@ impl DocumentGraphics {
         let t_inv = view.screen_to_world();
         let tile_size = self.generated_distances.tile_size;
         let cell_size = self.cell_size / 2;
-        for material in 0..self.distance_textures.len() {
+        let num_materials = self.materials.len().max(1);
+        for texture_index in 0..self.distance_textures.len() {
+            let material = texture_index % num_materials;
+            let opacity = self
+                .opacity_groups
+                .get(texture_index / num_materials)
+                .map_or(1.0, |group| group.opacity);
+            let vertex_color = [255, 255, 255, (opacity * 255.0).round() as u8];
             let world_min = t_inv.transform_point2(vec2(0.0, 0.0));
             let world_max = t_inv.transform_point2(window_size.into());
             let tile_range =
@@ -328,7 +666,10 @@ impl DocumentGraphics {
                 for x in tile_range[0].x..tile_range[1].x {
                     let tile_key = (x, y);
 
-                    let tex = some_or!(self.distance_textures[material].get(&tile_key), continue);
+                    let tex = some_or!(
+                        self.distance_textures[texture_index].get(&tile_key),
+                        continue
+                    );
                     batch.set_image(*tex);
 
                     let a = ivec2(x, y).as_vec2() * cell_size as f32 * tile_size as f32;
@@ -340,7 +681,7 @@ impl DocumentGraphics {
                     batch.geometry.fill_rect_uv(
                         rect,
                         [padding, padding, 1.0 - padding, 1.0 - padding],
-                        [255, 255, 255, 255],
+                        vertex_color,
                     );
                 }
             }
@@ -377,6 +718,11 @@ impl DocumentGraphics {
                     outline_color[2] as f32 / 255.0,
                     1.0,
                 ],
+                outline_width: self
+                    .outline_widths
+                    .get(material)
+                    .copied()
+                    .unwrap_or(DEFAULT_OUTLINE_WIDTH),
                 screen_size: window_size.into(),
                 pixel_size: 1.0 / view.zoom,
                 world_to_screen_pos,
@@ -388,16 +734,8 @@ impl DocumentGraphics {
         batch.flush(Some(window_size.into()), context);
     }
 
-    pub fn render_map_image(
-        &self,
-        doc: &Document,
-        white_texture: Texture,
-        finish_texture: Texture,
-        sdf_pipeline: Pipeline,
-        context: &mut Context,
-    ) -> (Vec<u8>, [IVec2; 2]) {
-        let _span = span!("DocumentGraphics::render_map_image");
-
+    /// Pixel rectangle of the map image, `main.png` is written with these bounds
+    pub fn map_image_bounds(&self, doc: &Document) -> [IVec2; 2] {
         let bounds = self.generated_grid.bounds;
         let margin = 2;
         let mut pixel_bounds = if bounds.is_valid() {
@@ -424,7 +762,40 @@ impl DocumentGraphics {
         if !pixel_bounds.is_valid() {
             pixel_bounds = [ivec2(0, 0), ivec2(1, 1)];
         }
+        pixel_bounds
+    }
+
+    /// Renders map image without GPU context, output matches `render_map_image`.
+    pub fn render_map_image_cpu(&self, doc: &Document) -> (Vec<u8>, [IVec2; 2]) {
+        let _span = span!("DocumentGraphics::render_map_image_cpu");
+
+        let pixel_bounds = self.map_image_bounds(doc);
+        let size = pixel_bounds[1] - pixel_bounds[0];
+        let center = (0.5 * (pixel_bounds[1].as_vec2() + pixel_bounds[0].as_vec2())).floor();
+        // same mapping as `View::world_to_screen` with zoom of 1, sampled at pixel centers
+        let origin = center - (size.as_vec2() * 0.5).floor() + vec2(1.0, 1.0);
+
+        let rasterizer = SdfRasterizer {
+            field: &self.generated_distances,
+            cell_size: (doc.cell_size / 2) as f32,
+            materials: &self.materials,
+            resolved_materials: &self.resolved_materials,
+            outline_widths: &self.outline_widths,
+        };
+        (rasterizer.render(origin, size), pixel_bounds)
+    }
 
+    pub fn render_map_image(
+        &self,
+        doc: &Document,
+        white_texture: Texture,
+        finish_texture: Texture,
+        sdf_pipeline: Pipeline,
+        context: &mut Context,
+    ) -> (Vec<u8>, [IVec2; 2]) {
+        let _span = span!("DocumentGraphics::render_map_image");
+
+        let pixel_bounds = self.map_image_bounds(doc);
         let map_width = (pixel_bounds[1].x - pixel_bounds[0].x) as usize;
         let map_height = (pixel_bounds[1].y - pixel_bounds[0].y) as usize;
 
@@ -599,27 +970,8 @@ pub fn create_pipeline(ctx: &mut Context) -> Pipeline {
     pipeline
 }
 
-pub fn create_pipeline_sdf(ctx: &mut Context) -> Pipeline {
-    let vertex_shader = r#"#version 100
-            attribute vec2 pos;
-            attribute vec2 uv;
-            attribute vec4 color;
-            uniform vec4 world_to_screen_xy;
-            uniform vec2 world_to_screen_pos;            
-            uniform vec2 screen_size;
-            varying lowp vec2 v_uv;
-            varying lowp vec4 v_color;
-            varying lowp vec2 v_pos;
-            void main() {
-                vec2 world_to_screen_x = world_to_screen_xy.xy;
-                vec2 world_to_screen_y = world_to_screen_xy.zw;
-                vec2 screen_pos = (vec2(dot(pos, world_to_screen_x), dot(pos, world_to_screen_y)) + world_to_screen_pos);
-                gl_Position = vec4((screen_pos / screen_size * 2.0 - 1.0) * vec2(1.0, -1.0), 0, 1);
-                v_uv = uv;
-                v_pos = pos;
-                v_color = color / 255.0;
-            }"#;
-    let fragment_shader = r#"#version 100
+/// Also implemented on CPU by `SdfRasterizer`, keep both in sync
+pub(crate) const SDF_FRAGMENT_SHADER: &str = r#"#version 100
             precision lowp float;    
             varying lowp vec2 v_uv;
             varying lowp vec4 v_color;
@@ -628,6 +980,7 @@ pub fn create_pipeline_sdf(ctx: &mut Context) -> Pipeline {
             uniform sampler2D tex;
             uniform vec4 fill_color;
             uniform vec4 outline_color;
+            uniform float outline_width;
             uniform float pixel_size;
             float outline_mask(float d, float width) {
                 float alpha1 = clamp(d + 0.5 + width * 0.5, 0.0, 1.0);
@@ -647,15 +1000,36 @@ pub fn create_pipeline_sdf(ctx: &mut Context) -> Pipeline {
                 vec4 color = vec4(0.0);
                 //float v = (0.8 + 0.2*cos(d)) * (1.0 - exp(-0.02 * d));
                 //color = vec4(v, v, v, 1.0);
-                color = alpha_over(color, pma(v_color * outline_color * tex_color * vec4(vec3(1.0), 1.0 - clamp((d - 1.41) / pixel_size, 0.0, 1.0))));
+                color = alpha_over(color, pma(v_color * outline_color * tex_color * vec4(vec3(1.0), 1.0 - clamp((d - outline_width) / pixel_size, 0.0, 1.0))));
                 color = alpha_over(color, pma(v_color * fill_color * tex_color * vec4(vec3(1.0), 1.0 - clamp((d) / pixel_size, 0.0, 1.0))));
                 
                 gl_FragColor = color;
             }"#;
+
+pub fn create_pipeline_sdf(ctx: &mut Context) -> Pipeline {
+    let vertex_shader = r#"#version 100
+            attribute vec2 pos;
+            attribute vec2 uv;
+            attribute vec4 color;
+            uniform vec4 world_to_screen_xy;
+            uniform vec2 world_to_screen_pos;            
+            uniform vec2 screen_size;
+            varying lowp vec2 v_uv;
+            varying lowp vec4 v_color;
+            varying lowp vec2 v_pos;
+            void main() {
+                vec2 world_to_screen_x = world_to_screen_xy.xy;
+                vec2 world_to_screen_y = world_to_screen_xy.zw;
+                vec2 screen_pos = (vec2(dot(pos, world_to_screen_x), dot(pos, world_to_screen_y)) + world_to_screen_pos);
+                gl_Position = vec4((screen_pos / screen_size * 2.0 - 1.0) * vec2(1.0, -1.0), 0, 1);
+                v_uv = uv;
+                v_pos = pos;
+                v_color = color / 255.0;
+            }"#;
     let shader = Shader::new(
         ctx,
         vertex_shader,
-        fragment_shader,
+        SDF_FRAGMENT_SHADER,
         ShaderMeta {
             images: vec!["sdf_tex".to_owned(), "tex".to_owned()],
             uniforms: UniformBlockLayout {
@@ -663,6 +1037,7 @@ pub fn create_pipeline_sdf(ctx: &mut Context) -> Pipeline {
                 uniforms: vec![
                     UniformDesc::new("fill_color", UniformType::Float4),
                     UniformDesc::new("outline_color", UniformType::Float4),
+                    UniformDesc::new("outline_width", UniformType::Float1),
                     UniformDesc::new("world_to_screen_xy", UniformType::Float4),
                     UniformDesc::new("world_to_screen_pos", UniformType::Float2),
                     UniformDesc::new("screen_size", UniformType::Float2),
@@ -714,3 +1089,15 @@ fn isopoint(d: [f32; 2], p: [Vec2; 2], _thickness_half: f32) -> Vec2 {
 }
 
 pub const DISTANCE_TEXTURE_PADDING: u32 = 4;
+
+/// Width of the outline that is drawn around the fill, in world units
+pub const DEFAULT_OUTLINE_WIDTH: f32 = 1.41;
+
+/// 4x4 RGBA texture of the finish material, tiled every 32 world units
+#[rustfmt::skip]
+pub const FINISH_TEXTURE_PIXELS: [u32; 4 * 4] = [
+    0xff736556, 0xff736556, 0xff000000, 0xff000000,
+    0xff736556, 0xff736556, 0xff000000, 0xff000000,
+    0xff000000, 0xff000000, 0xff736556, 0xff736556,
+    0xff000000, 0xff000000, 0xff736556, 0xff736556,
+];
@@ -1,20 +1,29 @@
-use glam::{vec2, IVec2, Vec2};
+use anyhow::Context as _;
+use glam::{vec2, Affine2, IVec2, Mat2, Vec2};
 use rimui::{KeyCode, UIEvent};
+use std::f32::consts::TAU;
 
 use crate::app::{App, MODIFIER_ALT, MODIFIER_CONTROL, MODIFIER_SHIFT};
-use crate::document::{Document, LayerKey, SelectRef, Vec2Ord};
-use crate::graph::{GraphEdge, GraphNode, GraphNodeKey, SplitPos};
+use crate::clipboard::ClipboardContent;
+use crate::document::{ChangeMask, Document, LayerKey, SelectRef, Vec2Ord};
+use crate::gizmo::{GizmoHandle, GizmoPivot};
+use crate::graph::{GraphEdge, GraphEdgeKey, GraphNode, GraphNodeKey, GraphNodeShape, SplitPos};
 use crate::grid::Grid;
 use crate::grid_segment_iterator::GridSegmentIterator;
-use crate::math::Rect;
+use crate::math::{rotate_vec2, Rect};
 use crate::mouse_operation::MouseOperation;
 use crate::plant::{Plant, PlantKey};
+use crate::symmetry::{transform_pos, transform_zone};
 use crate::tool::Tool;
 use crate::zone::{AnyZone, EditorTranslate, ZoneRef};
 use core::iter::once;
 use miniquad::Context;
 use std::collections::BTreeSet;
 use std::mem::replace;
+use std::sync::Arc;
+
+/// Dragged nodes are welded onto nodes whose centers are closer than this, in screen pixels
+const WELD_DISTANCE: f32 = 16.0;
 
 impl App {
     pub(crate) fn screen_to_document(&self, screen_pos: Vec2) -> Vec2 {
@@ -61,6 +70,7 @@ impl App {
             UIEvent::MouseDown { button, pos, .. } => {
                 let pos = IVec2::from(pos);
                 let mouse_world = self.view.screen_to_world().transform_point2(pos.as_vec2());
+                let layer_locked = self.doc.is_layer_locked(self.doc.current_layer);
                 // start new operations
                 match self.tool {
                     Tool::Pan => {
@@ -68,7 +78,7 @@ impl App {
                         self.operation.start(op, button, context)
                     }
                     Tool::Paint => {
-                        if button == 1 || button == 2 {
+                        if (button == 1 || button == 2) && !layer_locked {
                             let op = operation_stroke(
                                 self,
                                 if button == 1 { self.active_material } else { 0 },
@@ -77,7 +87,7 @@ impl App {
                         }
                     }
                     Tool::Fill => {
-                        if button == 1 || button == 2 {
+                        if (button == 1 || button == 2) && !layer_locked {
                             action_flood_fill(
                                 self,
                                 pos,
@@ -86,7 +96,7 @@ impl App {
                         }
                     }
                     Tool::Rectangle => {
-                        if button == 1 || button == 2 {
+                        if (button == 1 || button == 2) && !layer_locked {
                             let op = operation_rectangle(
                                 self,
                                 pos,
@@ -96,7 +106,19 @@ impl App {
                         }
                     }
                     Tool::Zone => {
-                        if button == 1 {
+                        let over_zone =
+                            !AnyZone::hit_test_zone(&self.doc.markup, pos.as_vec2(), &self.view)
+                                .is_empty();
+                        let include_pivot = !over_zone || self.modifier_down[MODIFIER_ALT];
+                        if button == 1
+                            && !self.start_gizmo_operation(
+                                button,
+                                pos,
+                                mouse_world,
+                                include_pivot,
+                                context,
+                            )
+                        {
                             let hit_result = AnyZone::hit_test_zone_corner(
                                 &self.doc.markup,
                                 pos.as_vec2(),
@@ -112,6 +134,7 @@ impl App {
                                     let op = operation_move_zone_corner(
                                         start_rect,
                                         reference,
+                                        self.doc.symmetric_zones(reference),
                                         corner,
                                         mouse_world,
                                     );
@@ -132,6 +155,7 @@ impl App {
                                         let op = operation_move_zone(
                                             start_value,
                                             selection,
+                                            self.doc.symmetric_zones(selection),
                                             mouse_world,
                                         );
                                         self.operation.start(op, button, context);
@@ -145,7 +169,7 @@ impl App {
                     }
                 }
             }
-            UIEvent::KeyDown { key, .. } => {
+            UIEvent::KeyDown { key, control, .. } => {
                 let material_index = match key {
                     KeyCode::Key1 => Some(1),
                     KeyCode::Key2 => Some(2),
@@ -171,6 +195,15 @@ impl App {
                         }
                         _ => {}
                     },
+                    KeyCode::C if control && matches!(self.tool, Tool::Select) => {
+                        action_copy_selection(self);
+                    }
+                    KeyCode::X if control && matches!(self.tool, Tool::Select) => {
+                        action_cut_selection(self);
+                    }
+                    KeyCode::V if control && matches!(self.tool, Tool::Select) => {
+                        action_paste(self);
+                    }
                     _ => {}
                 }
             }
@@ -211,6 +244,27 @@ impl App {
         return false;
     }
 
+    /// Starts rotating, scaling or moving pivot of the selection when a gizmo handle is hit
+    fn start_gizmo_operation(
+        &mut self,
+        button: i32,
+        pos: IVec2,
+        mouse_world: Vec2,
+        include_pivot: bool,
+        context: &mut Context,
+    ) -> bool {
+        let Some(gizmo) = self.gizmo() else { return false };
+        let Some((handle, _)) = gizmo.hit_test(pos.as_vec2(), include_pivot) else { return false };
+        if handle == GizmoHandle::Pivot {
+            let op = operation_move_gizmo_pivot();
+            self.operation.start(op, button, context);
+        } else {
+            let op = operation_transform_selection(self, handle, mouse_world);
+            self.operation.start(op, button, context);
+        }
+        true
+    }
+
     fn handle_select_mouse_down(
         &mut self,
         button: i32,
@@ -223,10 +277,26 @@ impl App {
             return;
         }
 
-        let default_node = match self.doc.selected.last() {
-            Some(SelectRef::NodeRadius(key) | SelectRef::Node(key)) => {
-                self.doc.nodes.get(*key).map(|n| n.clone())
+        // pivot is picked over elements only with Alt, as it starts in the middle of selection
+        let over_element = self.doc.hit_test(pos.as_vec2(), &self.view).is_some();
+        let include_pivot = !over_element || self.modifier_down[MODIFIER_ALT];
+        if self.start_gizmo_operation(button, pos, mouse_world, include_pivot, context) {
+            return;
+        }
+
+        if self.doc.symmetry.is_enabled() {
+            let pivot = self.document_to_screen(self.doc.symmetry.pivot.as_vec2());
+            if pivot.distance(pos.as_vec2()) < 8.0 {
+                let op = operation_move_symmetry_pivot();
+                self.operation.start(op, button, context);
+                return;
             }
+        }
+
+        let default_node = match self.doc.selected.last() {
+            Some(
+                SelectRef::NodeRadius(key) | SelectRef::Node(key) | SelectRef::NodeVertex(key, _),
+            ) => self.doc.nodes.get(*key).map(|n| n.clone()),
             _ => None,
         };
         let mut hover = self.doc.hit_test(pos.as_vec2(), &self.view);
@@ -235,9 +305,10 @@ impl App {
 
         match hover {
             None => {
-                if self.modifier_down[MODIFIER_CONTROL] {
+                let current_layer = self.doc.current_layer;
+                if self.modifier_down[MODIFIER_CONTROL] && !self.doc.is_layer_locked(current_layer)
+                {
                     push_undo = false;
-                    let current_layer = self.doc.current_layer;
                     hover = Some(SelectRef::Node(action_add_graph_node(
                         self,
                         current_layer,
@@ -263,11 +334,13 @@ impl App {
                         }
                     }
                 }
-                SelectRef::NodeRadius(node_key) => {
+                SelectRef::NodeRadius(node_key) | SelectRef::NodeVertex(node_key, _) => {
                     if self.doc.selected.iter().all(|s| match *s {
-                        SelectRef::Node(node) | SelectRef::NodeRadius(node) => node != node_key,
+                        SelectRef::Node(node)
+                        | SelectRef::NodeRadius(node)
+                        | SelectRef::NodeVertex(node, _) => node != node_key,
                         SelectRef::Edge(_) => true,
-                        SelectRef::EdgePoint(_, _) => true,
+                        SelectRef::EdgePoint(_, _) | SelectRef::EdgeControl(_, _) => true,
                         SelectRef::Plant(_) | SelectRef::PlantDirection(_) => true,
                         SelectRef::Point(_) => true,
                     }) {
@@ -304,13 +377,39 @@ impl App {
                 let op = operation_move_graph_node_radius(self, key);
                 self.operation.start(op, button, context);
             }
+            Some(SelectRef::NodeVertex(key, index)) => {
+                let op = operation_move_graph_node_vertex(self, key, index);
+                self.operation.start(op, button, context);
+            }
             Some(SelectRef::PlantDirection(key)) => {
                 let op = operation_move_plant_direction(self, key);
                 self.operation.start(op, button, context);
             }
-            Some(hover @ SelectRef::EdgePoint { .. }) => {
+            Some(hover @ SelectRef::EdgeControl(key, index)) => {
                 self.doc.selected = once(hover).collect();
-                let op = operation_move_selection(self, mouse_world, true, |_| {});
+                let op = operation_move_edge_control_point(self, key, index);
+                self.operation.start(op, button, context);
+            }
+            Some(SelectRef::Edge(key)) if self.modifier_down[MODIFIER_CONTROL] => {
+                // split the edge with a node that is dragged from the clicked point
+                if let Some(position) = self.doc.edge_position(key, mouse_world) {
+                    self.doc.selected = once(SelectRef::EdgePoint(key, position)).collect();
+                    let op = operation_move_selection(self, mouse_world, true, |_| {});
+                    self.operation.start(op, button, context);
+                }
+            }
+            Some(hover @ SelectRef::Edge(_)) if self.modifier_down[MODIFIER_SHIFT] => {
+                if !self.doc.selected.contains(&hover) {
+                    self.doc.selected.push(hover);
+                } else {
+                    self.doc.selected.retain(|e| *e != hover);
+                }
+            }
+            Some(hover @ SelectRef::Edge(_)) => {
+                if !self.doc.selected.contains(&hover) {
+                    self.doc.selected = once(hover).collect();
+                }
+                let op = operation_move_selection(self, mouse_world, true, select_hovered);
                 self.operation.start(op, button, context);
             }
             _ => {
@@ -373,12 +472,13 @@ pub(crate) fn operation_stroke(app: &mut App, value: u8) -> impl FnMut(&mut App,
             }
 
             // Drawing outside of the grid? Resize it.
-            let Some(grid) = app.doc.grids.get_mut(grid_key) else { return };
+            let Some(grid) = app.doc.grids.get_mut(grid_key).map(Arc::make_mut) else { return };
             grid.resize_to_include_amortized(Rect::from_point(grid_pos_outside));
             assert!(grid.bounds.contains_point(grid_pos_outside));
         }
 
         let doc = &app.doc;
+        let symmetry = doc.symmetry;
         let cell_index = if let Some(grid) = doc.grids.get(grid_key) {
             let pos = grid.world_to_grid_pos(document_pos, cell_size).unwrap();
             let w = grid.size().x;
@@ -396,7 +496,7 @@ pub(crate) fn operation_stroke(app: &mut App, value: u8) -> impl FnMut(&mut App,
                 undo_pushed = true;
             }
             let doc = &mut app.doc;
-            if let Some(layer) = doc.grids.get_mut(grid_key) {
+            if let Some(layer) = doc.grids.get_mut(grid_key).map(Arc::make_mut) {
                 for pos in GridSegmentIterator::new(
                     last_document_pos,
                     document_pos,
@@ -405,10 +505,11 @@ pub(crate) fn operation_stroke(app: &mut App, value: u8) -> impl FnMut(&mut App,
                     1024,
                 ) {
                     if layer.bounds.contains_point(pos) {
-                        let cell_index = layer.grid_pos_index(pos.x, pos.y);
-                        if layer.cells[cell_index] != value {
-                            layer.cells[cell_index] = value;
-                            app.dirty_mask.mark_dirty_layer(current_layer)
+                        // also paints mirrored cells
+                        let changed = symmetry.paint_cell(layer, pos, value, cell_size);
+                        if changed.is_valid() {
+                            app.dirty_mask
+                                .mark_dirty_cells(current_layer, changed, cell_size);
                         }
                     }
                 }
@@ -434,6 +535,7 @@ pub(crate) fn operation_rectangle(
         &mut app.doc.grids,
     );
     let (grid_pos, serialized_layer) = if let Some(grid) = app.doc.grids.get_mut(grid_key) {
+        let grid = Arc::make_mut(grid);
         let grid_pos = grid
             .world_to_grid_pos(start_pos, cell_size)
             .unwrap_or_else(|e| e);
@@ -444,7 +546,9 @@ pub(crate) fn operation_rectangle(
     };
 
     let start_pos: [IVec2; 2] = Rect::from_point(grid_pos);
+    let symmetry = app.doc.symmetry;
     let mut last_pos = grid_pos;
+    let mut last_rect = start_pos;
 
     move |app, event| {
         let pos = match event {
@@ -463,11 +567,17 @@ pub(crate) fn operation_rectangle(
             if grid_pos == last_pos {
                 return;
             }
-            *grid = bincode::deserialize(&serialized_layer).unwrap();
+            // restored grid is not shared, so it is not copied on write
+            *grid = Arc::new(bincode::deserialize(&serialized_layer).unwrap());
+            let grid = Arc::make_mut(grid);
             grid.resize_to_include_amortized(Rect::from_point(grid_pos));
-            grid.rectangle_outline(start_pos.union(Rect::from_point(grid_pos)), value);
-            app.dirty_mask.mark_dirty_layer(current_layer);
+            let rect = start_pos.union(Rect::from_point(grid_pos));
+            grid.rectangle_outline(rect, value);
+            let painted = rect.union(symmetry.rectangle_outline(grid, rect, value, cell_size));
+            app.dirty_mask
+                .mark_dirty_cells(current_layer, painted.union(last_rect), cell_size);
             last_pos = grid_pos;
+            last_rect = painted;
         }
     }
 }
@@ -481,10 +591,16 @@ pub(crate) fn action_flood_fill(app: &mut App, mouse_pos: IVec2, value: u8) {
     let cell_size = doc.cell_size;
     let grid_key =
         Document::get_or_add_layer_grid(&mut doc.layers, doc.current_layer, &mut doc.grids);
-    if let Some(grid) = doc.grids.get_mut(grid_key) {
+    if let Some(grid) = doc.grids.get_mut(grid_key).map(Arc::make_mut) {
         if let Ok(pos) = grid.world_to_grid_pos(world_pos, cell_size) {
             Grid::flood_fill(&mut grid.cells, grid.bounds, pos, value, 0);
-            app.dirty_mask.mark_dirty_layer(current_layer);
+            for pos in doc.symmetry.cell_images(pos, cell_size) {
+                if grid.bounds.contains_point(pos) {
+                    Grid::flood_fill(&mut grid.cells, grid.bounds, pos, value, 0);
+                }
+            }
+            app.dirty_mask
+                .mark_dirty_cells(current_layer, grid.bounds, cell_size);
         }
     }
 }
@@ -492,6 +608,7 @@ pub(crate) fn action_flood_fill(app: &mut App, mouse_pos: IVec2, value: u8) {
 fn operation_move_zone_corner(
     start_rect: AnyZone,
     reference: ZoneRef,
+    counterparts: Vec<(Affine2, ZoneRef)>,
     corner: u8,
     start_mouse_world: Vec2,
 ) -> impl FnMut(&mut App, &UIEvent) {
@@ -520,6 +637,9 @@ fn operation_move_zone_corner(
             app.push_undo("Move Zone Corner");
             first_change = false;
         }
+        for (t, counterpart) in &counterparts {
+            counterpart.update(&mut app.doc.markup, transform_zone(t, &new_value));
+        }
         reference.update(&mut app.doc.markup, new_value);
     }
 }
@@ -527,6 +647,7 @@ fn operation_move_zone_corner(
 fn operation_move_zone(
     start_value: AnyZone,
     reference: ZoneRef,
+    counterparts: Vec<(Affine2, ZoneRef)>,
     start_mouse_world: Vec2,
 ) -> impl FnMut(&mut App, &UIEvent) {
     let mut first_move = true;
@@ -542,6 +663,9 @@ fn operation_move_zone(
             first_move = false;
         }
         new_value.translate([delta.x as i32, delta.y as i32]);
+        for (t, counterpart) in &counterparts {
+            counterpart.update(&mut app.doc.markup, transform_zone(t, &new_value));
+        }
         reference.update(&mut app.doc.markup, new_value);
     }
 }
@@ -556,14 +680,18 @@ pub fn action_add_graph_node(
     let cell_size = app.doc.cell_size as f32;
 
     let doc = &mut app.doc;
+    let old_bounds = doc.selected_bounds();
 
     let prev_node = match doc.selected.last().cloned() {
-        Some(SelectRef::Node(key) | SelectRef::NodeRadius(key)) => Some(key),
+        Some(SelectRef::Node(key) | SelectRef::NodeRadius(key) | SelectRef::NodeVertex(key, _)) => {
+            Some(key)
+        }
         Some(SelectRef::EdgePoint(key, pos)) => {
             let split_node =
                 GraphNode::split_edge_node(&doc.nodes, &doc.edges, key, SplitPos::Fraction(*pos));
             let node_key = doc.nodes.insert(split_node);
-            let split_node_key = GraphEdge::split_edge(&mut doc.edges, key, node_key);
+            let split_node_key =
+                GraphEdge::split_edge(&mut doc.edges, &doc.nodes, key, node_key, *pos);
             default_node = Some(doc.nodes[split_node_key].clone());
             Some(split_node_key)
         }
@@ -573,19 +701,21 @@ pub fn action_add_graph_node(
     let key = doc.nodes.insert(GraphNode {
         pos,
         layer: layer_key,
+        symmetry_group: None,
         ..default_node.unwrap_or(GraphNode::new())
     });
 
     if let Some(prev_node) = prev_node {
         // connect with previously selection node
-        doc.edges.insert(GraphEdge {
-            start: prev_node,
-            end: key,
-        });
+        doc.edges.insert(GraphEdge::new(prev_node, key));
     }
+    let mirrored_bounds = doc.add_symmetric_nodes(&[key]);
     doc.selected = vec![SelectRef::Node(key)];
 
-    app.dirty_mask.mark_dirty_layer(layer_key);
+    let bounds = old_bounds
+        .union(doc.selected_bounds())
+        .union(mirrored_bounds);
+    app.dirty_mask.mark_dirty_rect(layer_key, bounds);
     key
 }
 
@@ -595,15 +725,20 @@ pub fn action_add_plant(app: &mut App, layer_key: LayerKey, world_pos: Vec2) ->
 
     let doc = &mut app.doc;
     let pos = ((world_pos / cell_size).floor() * cell_size).as_ivec2();
+    // neighbouring plants should not grow identically
+    let seed =
+        ((pos.x as u32).wrapping_mul(0x9e3779b1) ^ (pos.y as u32).wrapping_mul(0x85ebca6b)) >> 16;
     let key = doc.plants.insert(Plant {
         pos,
         layer: layer_key,
+        seed,
         ..Plant::new()
     });
 
     doc.selected = vec![SelectRef::Plant(key)];
 
-    app.dirty_mask.mark_dirty_layer(layer_key);
+    let bounds = doc.selected_bounds();
+    app.dirty_mask.mark_dirty_rect(layer_key, bounds);
     key
 }
 
@@ -611,6 +746,7 @@ fn action_delete_selection(app: &mut App) {
     let can_delete = {
         app.doc.selected.iter().any(|n| match n {
             SelectRef::Node { .. } | SelectRef::NodeRadius { .. } => true,
+            SelectRef::Edge { .. } | SelectRef::EdgePoint { .. } => true,
             SelectRef::Plant { .. } => true,
             _ => false,
         })
@@ -618,61 +754,604 @@ fn action_delete_selection(app: &mut App) {
 
     if can_delete {
         app.push_undo("Delete Selection");
-        let mut removed_nodes = Vec::new();
-        let mut removed_edges = Vec::new();
-        let mut removed_plants = Vec::new();
-        for selection in &app.doc.selected {
-            match *selection {
-                SelectRef::Node(key) => {
-                    removed_nodes.push(key);
-                }
-                SelectRef::Edge(key) => {
-                    removed_edges.push(key);
-                }
-                SelectRef::Plant(key) => {
-                    removed_plants.push(key);
+        // counterparts are removed along
+        let doc = &app.doc;
+        let groups = doc.symmetry_groups();
+        let mut mirrored = Vec::new();
+        for t in doc.symmetry.transforms() {
+            for selection in &doc.selected {
+                let counterpart = match *selection {
+                    SelectRef::Node(key) => {
+                        doc.counterpart_node(&groups, key, &t).map(SelectRef::Node)
+                    }
+                    SelectRef::Edge(key) | SelectRef::EdgePoint(key, _) => {
+                        doc.counterpart_edge(&groups, key, &t).map(SelectRef::Edge)
+                    }
+                    _ => None,
+                };
+                let Some(counterpart) = counterpart else { continue };
+                if !doc.selected.contains(&counterpart) && !mirrored.contains(&counterpart) {
+                    mirrored.push(counterpart);
                 }
-                _ => {}
             }
         }
+        app.doc.selected.extend(mirrored);
+        remove_selection(&mut app.doc, &mut app.dirty_mask);
+    }
+}
 
-        // mark edges of removed nodes
-        for (key, edge) in &app.doc.edges {
-            if removed_nodes.contains(&edge.start) || removed_nodes.contains(&edge.end) {
+fn remove_selection(doc: &mut Document, dirty_mask: &mut ChangeMask) {
+    let mut removed_nodes = Vec::new();
+    let mut removed_edges = Vec::new();
+    let mut removed_plants = Vec::new();
+    for selection in &doc.selected {
+        match *selection {
+            SelectRef::Node(key) | SelectRef::NodeRadius(key) => {
+                removed_nodes.push(key);
+            }
+            SelectRef::Edge(key) | SelectRef::EdgePoint(key, _) => {
                 removed_edges.push(key);
             }
+            SelectRef::Plant(key) => {
+                removed_plants.push(key);
+            }
+            _ => {}
+        }
+    }
+
+    // mark edges of removed nodes
+    for (key, edge) in &doc.edges {
+        if removed_nodes.contains(&edge.start) || removed_nodes.contains(&edge.end) {
+            removed_edges.push(key);
         }
+    }
 
-        let mut affected_layers = BTreeSet::new();
-        for &key in &removed_nodes {
-            let Some(node) = app.doc.nodes.get(key) else { continue };
+    let mut affected_layers = BTreeSet::new();
+    for &key in &removed_nodes {
+        let Some(node) = doc.nodes.get(key) else { continue };
+        affected_layers.insert(node.layer);
+    }
+    for &key in &removed_edges {
+        let Some(edge) = doc.edges.get(key) else { continue };
+        for node_key in [edge.start, edge.end] {
+            let Some(node) = doc.nodes.get(node_key) else { continue };
             affected_layers.insert(node.layer);
         }
-        for &key in &removed_plants {
-            let Some(plant) = app.doc.plants.get(key) else { continue };
-            affected_layers.insert(plant.layer);
+    }
+    for &key in &removed_plants {
+        let Some(plant) = doc.plants.get(key) else { continue };
+        affected_layers.insert(plant.layer);
+    }
+
+    let bounds = doc.selected_bounds();
+
+    doc.selected.retain(|s| match s {
+        SelectRef::NodeRadius(key) | SelectRef::Node(key) | SelectRef::NodeVertex(key, _) => {
+            !removed_nodes.contains(&key)
+        }
+        SelectRef::Edge(key) | SelectRef::EdgePoint(key, _) | SelectRef::EdgeControl(key, _) => {
+            !removed_edges.contains(key)
         }
+        SelectRef::Plant(key) | SelectRef::PlantDirection(key) => !removed_plants.contains(key),
+        SelectRef::Point(_) => false,
+    });
+    if !removed_edges.is_empty() {
+        doc.edges.retain(|key, _| !removed_edges.contains(&key));
+    }
+    if !removed_nodes.is_empty() {
+        doc.nodes.retain(|key, _| !removed_nodes.contains(&key))
+    }
+    if !removed_plants.is_empty() {
+        doc.plants.retain(|key, _| !removed_plants.contains(&key))
+    }
+    for layer in affected_layers {
+        dirty_mask.mark_dirty_rect(layer, bounds);
+    }
+}
 
-        app.doc.selected.retain(|s| match s {
-            SelectRef::NodeRadius(key) | SelectRef::Node(key) => !removed_nodes.contains(&key),
-            SelectRef::Edge(key) | SelectRef::EdgePoint(key, _) => !removed_edges.contains(key),
-            SelectRef::Plant(key) | SelectRef::PlantDirection(key) => !removed_plants.contains(key),
-            SelectRef::Point(_) => false,
-        });
-        if !removed_edges.is_empty() {
-            app.doc.edges.retain(|key, _| !removed_edges.contains(&key));
+fn selected_node_keys(doc: &Document) -> Vec<GraphNodeKey> {
+    let mut keys = Vec::new();
+    for selection in &doc.selected {
+        let (SelectRef::Node(key) | SelectRef::NodeRadius(key) | SelectRef::NodeVertex(key, _)) =
+            *selection
+        else {
+            continue;
+        };
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    keys
+}
+
+fn selected_edge_keys(doc: &Document) -> Vec<GraphEdgeKey> {
+    let mut keys = Vec::new();
+    for selection in &doc.selected {
+        let (SelectRef::Edge(key) | SelectRef::EdgePoint(key, _) | SelectRef::EdgeControl(key, _)) =
+            *selection
+        else {
+            continue;
+        };
+        if !keys.contains(&key) {
+            keys.push(key);
         }
-        if !removed_nodes.is_empty() {
-            app.doc.nodes.retain(|key, _| !removed_nodes.contains(&key))
+    }
+    keys
+}
+
+fn mark_edges_dirty(app: &mut App, edges: &[GraphEdge]) {
+    for edge in edges {
+        let Some(bounds) = edge.bounds(&app.doc.nodes) else { continue };
+        for node_key in [edge.start, edge.end] {
+            let Some(node) = app.doc.nodes.get(node_key) else { continue };
+            app.dirty_mask.mark_dirty_rect(node.layer, bounds);
         }
-        if !removed_plants.is_empty() {
-            app.doc
+    }
+}
+
+/// Connects selected nodes in the order they were selected.
+pub fn action_connect_nodes(app: &mut App) {
+    let keys = selected_node_keys(&app.doc);
+    let doc = &app.doc;
+    let pairs: Vec<_> = keys
+        .windows(2)
+        .map(|w| (w[0], w[1]))
+        .filter(|&(a, b)| {
+            doc.nodes
+                .get(a)
+                .zip(doc.nodes.get(b))
+                .is_some_and(|(a, b)| a.layer == b.layer)
+        })
+        .filter(|&(a, b)| {
+            !doc.edges
+                .values()
+                .any(|e| (e.start == a && e.end == b) || (e.start == b && e.end == a))
+        })
+        .collect();
+    if pairs.is_empty() {
+        return;
+    }
+    app.push_undo("Connect Nodes");
+    let mut added = Vec::new();
+    for (a, b) in pairs {
+        let edge = GraphEdge::new(a, b);
+        app.doc.edges.insert(edge.clone());
+        added.push(edge);
+    }
+    mark_edges_dirty(app, &added);
+}
+
+/// Removes selected edges and edges between selected nodes.
+pub fn action_disconnect_nodes(app: &mut App) {
+    let nodes = selected_node_keys(&app.doc);
+    let mut removed = selected_edge_keys(&app.doc);
+    for (key, edge) in &app.doc.edges {
+        if nodes.contains(&edge.start) && nodes.contains(&edge.end) && !removed.contains(&key) {
+            removed.push(key);
+        }
+    }
+    let removed_edges: Vec<GraphEdge> = removed
+        .iter()
+        .filter_map(|&key| app.doc.edges.get(key).cloned())
+        .collect();
+    if removed_edges.is_empty() {
+        return;
+    }
+    app.push_undo("Disconnect Nodes");
+    mark_edges_dirty(app, &removed_edges);
+    app.doc.edges.retain(|key, _| !removed.contains(&key));
+    app.doc.selected.retain(|s| match *s {
+        SelectRef::Edge(key) | SelectRef::EdgePoint(key, _) | SelectRef::EdgeControl(key, _) => {
+            !removed.contains(&key)
+        }
+        _ => true,
+    });
+}
+
+/// Removes selected nodes and their mirrored counterparts. A node with exactly two
+/// neighbours is replaced by an edge between them, other nodes are removed with their
+/// edges.
+pub fn action_dissolve_nodes(app: &mut App) {
+    let mut keys = selected_node_keys(&app.doc);
+    if keys.is_empty() {
+        return;
+    }
+    // counterparts are dissolved along
+    let doc = &app.doc;
+    let groups = doc.symmetry_groups();
+    for t in doc.symmetry.transforms() {
+        for i in 0..keys.len() {
+            let Some(counterpart) = doc.counterpart_node(&groups, keys[i], &t) else { continue };
+            if !keys.contains(&counterpart) {
+                keys.push(counterpart);
+            }
+        }
+    }
+    app.push_undo("Dissolve Nodes");
+    for key in keys {
+        let Some(node) = app.doc.nodes.get(key).cloned() else { continue };
+        let doc = &mut app.doc;
+        let node_edges: Vec<(GraphEdgeKey, GraphEdge)> = doc
+            .edges
+            .iter()
+            .filter(|(_, e)| e.start == key || e.end == key)
+            .map(|(k, e)| (k, e.clone()))
+            .collect();
+        let mut neighbours: Vec<GraphNodeKey> = Vec::new();
+        for (_, edge) in &node_edges {
+            let other = if edge.start == key {
+                edge.end
+            } else {
+                edge.start
+            };
+            if other != key && doc.nodes.contains_key(other) && !neighbours.contains(&other) {
+                neighbours.push(other);
+            }
+        }
+        // only a node in the middle of a chain is bridged, joining more neighbours would
+        // add connections that did not exist
+        let pairs: Vec<(GraphNodeKey, GraphNodeKey)> = match neighbours[..] {
+            [a, b] => vec![(a, b)],
+            _ => Vec::new(),
+        };
+
+        let removed_edges: Vec<GraphEdge> = node_edges.iter().map(|(_, e)| e.clone()).collect();
+        mark_edges_dirty(app, &removed_edges);
+        app.dirty_mask.mark_dirty_rect(node.layer, node.bounds());
+        let doc = &mut app.doc;
+        doc.edges.retain(|_, e| e.start != key && e.end != key);
+        doc.nodes.remove(key);
+        let mut added = Vec::new();
+        for (a, b) in pairs {
+            let connected = doc
+                .edges
+                .values()
+                .any(|e| (e.start == a && e.end == b) || (e.start == b && e.end == a));
+            if connected {
+                continue;
+            }
+            // joined edge keeps overrides of the first removed edge
+            let edge = match node_edges.first() {
+                Some((_, first)) => GraphEdge {
+                    start: a,
+                    end: b,
+                    control_points: Vec::new(),
+                    radius: None,
+                    ..first.clone()
+                },
+                None => GraphEdge::new(a, b),
+            };
+            doc.edges.insert(edge.clone());
+            added.push(edge);
+        }
+        mark_edges_dirty(app, &added);
+    }
+    let doc = &mut app.doc;
+    let (nodes, edges) = (&doc.nodes, &doc.edges);
+    doc.selected.retain(|s| match *s {
+        SelectRef::Node(key) | SelectRef::NodeRadius(key) | SelectRef::NodeVertex(key, _) => {
+            nodes.contains_key(key)
+        }
+        SelectRef::Edge(key) | SelectRef::EdgePoint(key, _) | SelectRef::EdgeControl(key, _) => {
+            edges.contains_key(key)
+        }
+        _ => true,
+    });
+}
+
+/// Inserts `count` nodes into each selected edge, evenly spaced along the curve parameter.
+pub fn action_subdivide_edges(app: &mut App, count: usize) {
+    let keys = selected_edge_keys(&app.doc);
+    if keys.is_empty() || count == 0 {
+        return;
+    }
+    app.push_undo("Subdivide Edges");
+    let mut selection = Vec::new();
+    for key in keys {
+        let Some(edge) = app.doc.edges.get(key).cloned() else { continue };
+        mark_edges_dirty(app, &[edge]);
+        let doc = &mut app.doc;
+        let mut new_nodes = Vec::new();
+        // split from the end, so that the original edge always keeps the first part
+        for i in (1..=count).rev() {
+            let t = i as f32 / (i + 1) as f32;
+            let split_node =
+                GraphNode::split_edge_node(&doc.nodes, &doc.edges, key, SplitPos::Fraction(t));
+            let node_key = doc.nodes.insert(split_node);
+            GraphEdge::split_edge(&mut doc.edges, &doc.nodes, key, node_key, t);
+            new_nodes.push(node_key);
+        }
+        selection.extend(new_nodes.into_iter().rev().map(SelectRef::Node));
+    }
+    app.doc.selected = selection;
+    let bounds = app.doc.selected_bounds();
+    let layers: BTreeSet<LayerKey> = app
+        .doc
+        .selected
+        .iter()
+        .filter_map(|s| match *s {
+            SelectRef::Node(key) => app.doc.nodes.get(key).map(|n| n.layer),
+            _ => None,
+        })
+        .collect();
+    for layer in layers {
+        app.dirty_mask.mark_dirty_rect(layer, bounds);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum Alignment {
+    Left,
+    CenterX,
+    Right,
+    Top,
+    CenterY,
+    Bottom,
+}
+
+#[derive(Clone, Copy)]
+pub enum Distribute {
+    Horizontal,
+    Vertical,
+    /// Along the line going through the elements, in the order of their connections or selection
+    Path,
+}
+
+/// Selected nodes and plants, in selection order
+fn layout_items(doc: &Document) -> Vec<(SelectRef, IVec2, [Vec2; 2])> {
+    doc.selected
+        .iter()
+        .filter_map(|&s| match s {
+            SelectRef::Node(key) => doc.nodes.get(key).map(|n| (s, n.pos, n.bounds())),
+            SelectRef::Plant(key) => doc
                 .plants
-                .retain(|key, _| !removed_plants.contains(&key))
+                .get(key)
+                .map(|p| (s, p.pos, Rect::from_point(p.pos.as_vec2()))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Moves layout items as a single undo step
+fn apply_layout(app: &mut App, text: &str, moves: Vec<(SelectRef, IVec2)>) {
+    if moves.iter().all(|&(_, delta)| delta == IVec2::ZERO) {
+        return;
+    }
+    app.push_undo(text);
+    let old_bounds = app.doc.selected_bounds();
+    let mut layers = BTreeSet::new();
+    let doc = &mut app.doc;
+    for (item, delta) in moves {
+        match item {
+            SelectRef::Node(key) => {
+                let Some(node) = doc.nodes.get_mut(key) else { continue };
+                node.pos += delta;
+                layers.insert(node.layer);
+            }
+            SelectRef::Plant(key) => {
+                let Some(plant) = doc.plants.get_mut(key) else { continue };
+                plant.pos += delta;
+                layers.insert(plant.layer);
+            }
+            _ => {}
+        }
+    }
+    let bounds = old_bounds.union(app.doc.selected_bounds());
+    for layer in layers {
+        app.dirty_mask.mark_dirty_rect(layer, bounds);
+    }
+}
+
+/// Aligns bounds of selected nodes and plants with the bounds of the whole selection
+pub fn action_align_selection(app: &mut App, align: Alignment) {
+    let items = layout_items(&app.doc);
+    if items.len() < 2 {
+        return;
+    }
+    let total = items
+        .iter()
+        .fold(Rect::invalid(), |b: [Vec2; 2], &(_, _, r)| b.union(r));
+    let total_center = (total[0] + total[1]) * 0.5;
+    let moves = items
+        .into_iter()
+        .map(|(item, _, [min, max])| {
+            let center = (min + max) * 0.5;
+            let delta = match align {
+                Alignment::Left => vec2(total[0].x - min.x, 0.0),
+                Alignment::CenterX => vec2(total_center.x - center.x, 0.0),
+                Alignment::Right => vec2(total[1].x - max.x, 0.0),
+                Alignment::Top => vec2(0.0, total[0].y - min.y),
+                Alignment::CenterY => vec2(0.0, total_center.y - center.y),
+                Alignment::Bottom => vec2(0.0, total[1].y - max.y),
+            };
+            (item, delta.round().as_ivec2())
+        })
+        .collect();
+    apply_layout(app, "Align", moves);
+}
+
+/// Order of nodes when they form a single chain of edges
+fn chain_order(doc: &Document, keys: &[GraphNodeKey]) -> Option<Vec<GraphNodeKey>> {
+    let neighbours = |key| -> Vec<GraphNodeKey> {
+        doc.edges
+            .values()
+            .filter_map(|e| match (e.start == key, e.end == key) {
+                (true, false) if keys.contains(&e.end) => Some(e.end),
+                (false, true) if keys.contains(&e.start) => Some(e.start),
+                _ => None,
+            })
+            .collect()
+    };
+    if keys.iter().any(|&k| neighbours(k).len() > 2) {
+        return None;
+    }
+    // selection order decides which end goes first
+    let start = *keys.iter().find(|&&k| neighbours(k).len() == 1)?;
+    let mut order = vec![start];
+    while let Some(next) = neighbours(*order.last().unwrap())
+        .into_iter()
+        .find(|k| !order.contains(k))
+    {
+        order.push(next);
+    }
+    (order.len() == keys.len()).then_some(order)
+}
+
+/// Spaces positions of selected nodes and plants evenly, first and last ones stay in place
+pub fn action_distribute_selection(app: &mut App, distribute: Distribute) {
+    let mut items: Vec<(SelectRef, Vec2)> = layout_items(&app.doc)
+        .into_iter()
+        .map(|(item, pos, _)| (item, pos.as_vec2()))
+        .collect();
+    if items.len() < 3 {
+        return;
+    }
+    let count = items.len();
+    let targets: Vec<Vec2> = match distribute {
+        Distribute::Horizontal | Distribute::Vertical => {
+            let axis = match distribute {
+                Distribute::Horizontal => Vec2::X,
+                _ => Vec2::Y,
+            };
+            items.sort_by(|a, b| a.1.dot(axis).total_cmp(&b.1.dot(axis)));
+            let first = items[0].1.dot(axis);
+            let last = items[count - 1].1.dot(axis);
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, &(_, pos))| {
+                    let along = first + (last - first) * i as f32 / (count - 1) as f32;
+                    pos + axis * (along - pos.dot(axis))
+                })
+                .collect()
         }
-        for layer in affected_layers {
-            app.dirty_mask.mark_dirty_layer(layer);
+        Distribute::Path => {
+            let keys: Vec<GraphNodeKey> = items
+                .iter()
+                .filter_map(|(item, _)| match *item {
+                    SelectRef::Node(key) => Some(key),
+                    _ => None,
+                })
+                .collect();
+            if keys.len() == count {
+                if let Some(order) = chain_order(&app.doc, &keys) {
+                    items.sort_by_key(|(item, _)| match *item {
+                        SelectRef::Node(key) => order.iter().position(|&k| k == key),
+                        _ => None,
+                    });
+                }
+            }
+            let lengths: Vec<f32> = items.windows(2).map(|w| w[0].1.distance(w[1].1)).collect();
+            let total: f32 = lengths.iter().sum();
+            (0..count)
+                .map(|i| {
+                    let mut along = total * i as f32 / (count - 1) as f32;
+                    for (segment, &length) in lengths.iter().enumerate() {
+                        if along <= length || segment + 1 == lengths.len() {
+                            let t = if length > 0.0 { along / length } else { 0.0 };
+                            return items[segment].1.lerp(items[segment + 1].1, t.min(1.0));
+                        }
+                        along -= length;
+                    }
+                    items[i].1
+                })
+                .collect()
         }
+    };
+    let moves = items
+        .iter()
+        .zip(targets)
+        .map(|(&(item, pos), target)| (item, target.round().as_ivec2() - pos.as_ivec2()))
+        .collect();
+    apply_layout(app, "Distribute", moves);
+}
+
+/// Gives selected nodes radius of the first selected one
+pub fn action_equalize_radii(app: &mut App) {
+    let keys = selected_node_keys(&app.doc);
+    let Some(radius) = keys
+        .first()
+        .and_then(|&k| app.doc.nodes.get(k))
+        .map(|n| n.radius)
+    else {
+        return;
+    };
+    if keys
+        .iter()
+        .all(|&k| app.doc.nodes.get(k).is_none_or(|n| n.radius == radius))
+    {
+        return;
+    }
+    app.push_undo("Equalize Radii");
+    let old_bounds = app.doc.nodes_bounds(&keys);
+    let mut layers = BTreeSet::new();
+    for &key in &keys {
+        let Some(node) = app.doc.nodes.get_mut(key) else { continue };
+        node.radius = radius;
+        layers.insert(node.layer);
+    }
+    let bounds = old_bounds.union(app.doc.nodes_bounds(&keys));
+    for layer in layers {
+        app.dirty_mask.mark_dirty_rect(layer, bounds);
+    }
+}
+
+fn action_copy_selection(app: &mut App) {
+    let content = ClipboardContent::from_selection(&app.doc);
+    if content.is_empty() {
+        return;
+    }
+    let result = content
+        .to_text()
+        .and_then(|text| app.clipboard.set_text(text).context("Copying"));
+    app.report_error(result);
+}
+
+fn action_cut_selection(app: &mut App) {
+    let content = ClipboardContent::from_selection(&app.doc);
+    if content.is_empty() {
+        return;
+    }
+    let result = content
+        .to_text()
+        .and_then(|text| app.clipboard.set_text(text).context("Copying"));
+    if app.report_error(result).is_none() {
+        return;
+    }
+    app.push_undo("Cut");
+    remove_selection(&mut app.doc, &mut app.dirty_mask);
+    if !app.doc.is_layer_locked(app.doc.current_layer) && app.doc.clear_selected_cells() {
+        app.dirty_mask.mark_dirty_cells(
+            app.doc.current_layer,
+            app.doc.selection.bounds,
+            app.doc.cell_size,
+        );
+    }
+}
+
+fn action_paste(app: &mut App) {
+    if app.doc.is_layer_locked(app.doc.current_layer) {
+        return;
+    }
+    let text = app.clipboard.get_text().context("Pasting");
+    let Some(text) = app.report_error(text) else { return };
+    let content = ClipboardContent::from_text(&text);
+    let Some(Some(content)) = app.report_error(content) else { return };
+    if content.is_empty() {
+        return;
+    }
+
+    app.push_undo("Paste");
+    let mouse_world = app.screen_to_document(app.last_mouse_pos);
+    let delta = Document::snap_to_grid(mouse_world - content.origin, app.doc.cell_size).as_ivec2();
+    app.doc.selected = content.paste(&mut app.doc, delta);
+    let current_layer = app.doc.current_layer;
+    app.dirty_mask
+        .mark_dirty_rect(current_layer, app.doc.selected_bounds());
+    if content.cells.is_some() {
+        let cell_size = app.doc.cell_size;
+        app.dirty_mask
+            .mark_dirty_cells(current_layer, app.doc.selection.bounds, cell_size);
     }
 }
 
@@ -693,6 +1372,11 @@ fn operation_move_selection(
     for &s in &doc.selected {
         match s {
             SelectRef::Node(key) => selected_nodes.push(key),
+            // edges are moved with their nodes
+            SelectRef::Edge(key) => {
+                let Some(edge) = doc.edges.get(key) else { continue };
+                selected_nodes.extend([edge.start, edge.end]);
+            }
             SelectRef::Plant(key) => {
                 if let Some(plant) = doc.plants.get(key) {
                     selected_plants.push((key, plant.clone()));
@@ -702,9 +1386,35 @@ fn operation_move_selection(
         }
     }
 
+    selected_nodes.sort_unstable();
+    selected_nodes.dedup();
+
+    // counterparts follow moved nodes: (transform, moved node, counterpart)
+    let transforms = doc.symmetry.transforms();
+    let groups = doc.symmetry_groups();
+    let mut mirrored_nodes = Vec::new();
+    // nodes on the axis are their own counterparts, they get copies once moved off it
+    let mut axis_nodes = Vec::new();
+    for (index, t) in transforms.iter().enumerate() {
+        for &key in &selected_nodes {
+            let Some(counterpart) = doc.counterpart_node(&groups, key, t) else { continue };
+            if counterpart == key && !axis_nodes.contains(&key) {
+                axis_nodes.push(key);
+            }
+            if selected_nodes.contains(&counterpart)
+                || mirrored_nodes.iter().any(|&(_, _, c)| c == counterpart)
+            {
+                continue;
+            }
+            mirrored_nodes.push((index, key, counterpart));
+        }
+    }
+    let mirrored_keys: Vec<GraphNodeKey> = mirrored_nodes.iter().map(|&(_, _, c)| c).collect();
+
     drop(doc);
     let mut changed = false;
     let mut last_delta = IVec2::ZERO;
+    let mut last_axis_copies_bounds: [Vec2; 2] = Rect::invalid();
     move |app, event| {
         if start_nodes.is_empty() {
             click_action(app);
@@ -728,6 +1438,12 @@ fn operation_move_selection(
         let current_layer = doc.current_layer;
         let cell_size = doc.cell_size;
         drop(doc);
+        // nodes dropped onto other nodes are welded, unless Alt is held
+        let weld = !app.modifier_down[MODIFIER_ALT];
+        let weld_distance = WELD_DISTANCE / app.view.zoom;
+        let released = matches!(event, UIEvent::MouseUp { .. });
+        let mut welded = false;
+        let mut weld_targets = Vec::new();
 
         let delta = Document::snap_to_grid(pos_world - start_pos_world, cell_size).as_ivec2();
         //let delta = (pos_world - start_pos_world).as_ivec2();
@@ -737,12 +1453,20 @@ fn operation_move_selection(
                 if push_undo {
                     app.push_undo("Move Graph Node");
                 }
+                // moved pivot would stay behind
+                app.gizmo_pivot = None;
             }
             changed = true;
         } else {
             return;
         }
 
+        let old_bounds = app
+            .doc
+            .selected_bounds()
+            .union(app.doc.nodes_bounds(&mirrored_keys))
+            .union(last_axis_copies_bounds);
+        let mut axis_copies_bounds = Rect::invalid();
         let doc = &mut app.doc;
         {
             // insert nodes if we are trying to move edge points
@@ -760,7 +1484,13 @@ fn operation_move_selection(
                     );
                     node.pos = Document::snap_to_grid(node.pos.as_vec2(), doc.cell_size).as_ivec2();
                     let node_key = doc.nodes.insert(node);
-                    *sel = SelectRef::Node(GraphEdge::split_edge(&mut doc.edges, key, node_key));
+                    *sel = SelectRef::Node(GraphEdge::split_edge(
+                        &mut doc.edges,
+                        &doc.nodes,
+                        key,
+                        node_key,
+                        *pos,
+                    ));
                     changed = true;
                 }
             }
@@ -773,15 +1503,57 @@ fn operation_move_selection(
                     node.pos += delta;
                 }
 
+                for &(index, key, counterpart) in &mirrored_nodes {
+                    let Some(pos) = doc.nodes.get(key).map(|n| n.pos) else { continue };
+                    let Some(node) = doc.nodes.get_mut(counterpart) else { continue };
+                    node.pos = transform_pos(&transforms[index], pos);
+                }
+                axis_copies_bounds = doc.separate_axis_nodes(&axis_nodes);
+
+                // curves are moved along when both of their ends are
+                let mirror_index = |key| {
+                    mirrored_nodes
+                        .iter()
+                        .find(|&&(_, _, c)| c == key)
+                        .map(|&(index, _, _)| index)
+                };
+                for edge in doc.edges.values_mut() {
+                    if selected_nodes.contains(&edge.start) && selected_nodes.contains(&edge.end) {
+                        for point in &mut edge.control_points {
+                            *point += delta;
+                        }
+                    }
+                    let (Some(start), Some(end)) =
+                        (mirror_index(edge.start), mirror_index(edge.end))
+                    else {
+                        continue;
+                    };
+                    if start == end {
+                        let t = &transforms[start];
+                        let mirrored_delta =
+                            t.transform_vector2(delta.as_vec2()).round().as_ivec2();
+                        for point in &mut edge.control_points {
+                            *point += mirrored_delta;
+                        }
+                    }
+                }
+
                 for (key, old_plant) in selected_plants.iter().cloned() {
                     let Some(plant) = doc.plants.get_mut(key) else { continue };
                     *plant = old_plant;
                     plant.pos += delta;
                 }
 
-                // Merge graph nodes that are moved together
-                let merged_pairs =
-                    GraphNode::merge_nodes(&selected_nodes, &doc.nodes, cell_size as f32);
+                // Merge graph nodes that are dropped onto other nodes
+                let mut merged_pairs =
+                    GraphNode::merge_nodes(&selected_nodes, &doc.nodes, weld_distance);
+                if !weld {
+                    merged_pairs.clear();
+                }
+                if !released {
+                    weld_targets = merged_pairs.iter().map(|&(_, to)| to).collect();
+                    merged_pairs.clear();
+                }
 
                 let replace_node = |key: GraphNodeKey| -> Option<GraphNodeKey> {
                     if let Ok(i) = merged_pairs.binary_search_by_key(&key, |(f, _t)| *f) {
@@ -793,12 +1565,15 @@ fn operation_move_selection(
 
                 for &(from, to) in &merged_pairs {
                     if let Some([from, to]) = doc.nodes.get_disjoint_mut([from, to]) {
-                        if from.radius < to.radius {
-                            *from = to.clone();
-                        } else {
-                            *to = from.clone();
+                        // larger shape is kept, the target keeps its layer and symmetry links
+                        if from.radius >= to.radius {
+                            to.pos = from.pos;
+                            to.radius = from.radius;
+                            to.shape = from.shape.clone();
+                            to.rotation = from.rotation;
                         }
                         changed = true;
+                        welded = true;
                     }
                 }
                 doc.nodes.retain(|k, _node| {
@@ -814,15 +1589,32 @@ fn operation_move_selection(
                     }
                     edge.start != edge.end
                 });
+
+                // re-pointed edges may duplicate existing connections of the surviving node
+                let mut connections = BTreeSet::new();
+                doc.edges.retain(|_k, edge| {
+                    if !merged_pairs
+                        .iter()
+                        .any(|&(_, to)| to == edge.start || to == edge.end)
+                    {
+                        return true;
+                    }
+                    connections.insert((edge.start.min(edge.end), edge.start.max(edge.end)))
+                });
+
                 // update selected nodes
                 for sel in &mut doc.selected {
                     match sel {
-                        SelectRef::Node(ref mut key) | SelectRef::NodeRadius(ref mut key) => {
+                        SelectRef::Node(ref mut key)
+                        | SelectRef::NodeRadius(ref mut key)
+                        | SelectRef::NodeVertex(ref mut key, _) => {
                             if let Some(new_key) = replace_node(*key) {
                                 *key = new_key;
                             }
                         }
-                        SelectRef::Edge { .. } | SelectRef::EdgePoint { .. } => {}
+                        SelectRef::Edge { .. }
+                        | SelectRef::EdgePoint { .. }
+                        | SelectRef::EdgeControl { .. } => {}
                         SelectRef::Plant { .. } | SelectRef::PlantDirection { .. } => {}
                         SelectRef::Point { .. } => {}
                     }
@@ -830,9 +1622,9 @@ fn operation_move_selection(
 
                 // update selected edges
                 doc.selected.retain(|sel| match *sel {
-                    SelectRef::Edge(key) | SelectRef::EdgePoint(key, _) => {
-                        doc.edges.contains_key(key)
-                    }
+                    SelectRef::Edge(key)
+                    | SelectRef::EdgePoint(key, _)
+                    | SelectRef::EdgeControl(key, _) => doc.edges.contains_key(key),
                     _ => true,
                 });
 
@@ -842,10 +1634,182 @@ fn operation_move_selection(
             }
         }
         drop(doc);
-        if delta != last_delta {
-            app.dirty_mask.mark_dirty_layer(current_layer);
+        if delta != last_delta || welded {
+            let bounds = old_bounds
+                .union(app.doc.selected_bounds())
+                .union(app.doc.nodes_bounds(&mirrored_keys))
+                .union(axis_copies_bounds);
+            app.dirty_mask.mark_dirty_rect(current_layer, bounds);
             last_delta = delta;
+            last_axis_copies_bounds = axis_copies_bounds;
+        }
+
+        if !weld_targets.is_empty() {
+            app.operation_batch.set_image(app.white_texture);
+            let world_to_screen = app.view.world_to_screen();
+            for key in weld_targets {
+                let Some(node) = app.doc.nodes.get(key) else { continue };
+                let pos = world_to_screen.transform_point2(node.pos.as_vec2());
+                app.operation_batch.geometry.stroke_circle_aa(
+                    pos,
+                    20.0,
+                    2.0,
+                    24,
+                    [255, 128, 0, 255],
+                );
+            }
+        }
+    }
+}
+
+fn operation_move_gizmo_pivot() -> impl FnMut(&mut App, &UIEvent) {
+    move |app, _event| {
+        let mut pos = app.screen_to_document(app.last_mouse_pos);
+        if app.modifier_down[MODIFIER_SHIFT] {
+            pos = Document::snap_to_grid(pos, app.doc.cell_size);
         }
+        app.gizmo_pivot = Some(GizmoPivot {
+            selected: app.doc.selected.clone(),
+            zone: app.doc.zone_selection,
+            pos,
+        });
+    }
+}
+
+/// Rotates or scales selected nodes, plants or zone around the pivot of the gizmo
+fn operation_transform_selection(
+    app: &App,
+    handle: GizmoHandle,
+    start_mouse_world: Vec2,
+) -> impl FnMut(&mut App, &UIEvent) {
+    let doc = &app.doc;
+    let pivot = app.gizmo_pivot().unwrap_or(start_mouse_world);
+    let zone = match app.tool {
+        Tool::Zone => doc.zone_selection.filter(|z| z.is_valid(&doc.markup)),
+        _ => None,
+    };
+    let start_zone = zone.map(|z| (z, z.fetch(&doc.markup)));
+
+    let mut start_nodes = Vec::new();
+    let mut start_plants = Vec::new();
+    if matches!(app.tool, Tool::Select) {
+        for selection in &doc.selected {
+            match *selection {
+                SelectRef::Node(key) => {
+                    let Some(node) = doc.nodes.get(key) else { continue };
+                    start_nodes.push((key, node.clone()));
+                }
+                SelectRef::Plant(key) => {
+                    let Some(plant) = doc.plants.get(key) else { continue };
+                    start_plants.push((key, plant.clone()));
+                }
+                _ => {}
+            }
+        }
+    }
+    let is_selected = |key| start_nodes.iter().any(|&(k, _)| k == key);
+    // curves are transformed when both of their ends are
+    let start_curves: Vec<(GraphEdgeKey, Vec<IVec2>)> = doc
+        .edges
+        .iter()
+        .filter(|(_, e)| !e.control_points.is_empty() && is_selected(e.start) && is_selected(e.end))
+        .map(|(k, e)| (k, e.control_points.clone()))
+        .collect();
+    let layers: BTreeSet<LayerKey> = start_nodes
+        .iter()
+        .map(|(_, n)| n.layer)
+        .chain(start_plants.iter().map(|(_, p)| p.layer))
+        .collect();
+
+    let mut push_undo = true;
+    move |app, _event| {
+        let mouse_world = app.screen_to_document(app.last_mouse_pos);
+        let snap = app.modifier_down[MODIFIER_SHIFT];
+        let from = start_mouse_world - pivot;
+        let to = mouse_world - pivot;
+        let (linear, angle, radius_scale) = match handle {
+            GizmoHandle::Rotate => {
+                let mut angle = to.y.atan2(to.x) - from.y.atan2(from.x);
+                if snap {
+                    let step = 15.0f32.to_radians();
+                    angle = (angle / step).round() * step;
+                }
+                (Mat2::from_angle(angle), angle, 1.0)
+            }
+            GizmoHandle::Scale { x, y } => {
+                let mut scale = if x && y {
+                    Vec2::splat(to.length() / from.length().max(1.0))
+                } else {
+                    let axis = |enabled: bool, to: f32, from: f32| {
+                        if enabled && from.abs() >= 1.0 {
+                            to / from
+                        } else {
+                            1.0
+                        }
+                    };
+                    vec2(axis(x, to.x, from.x), axis(y, to.y, from.y))
+                };
+                if snap {
+                    scale = (scale * 4.0).round() / 4.0;
+                }
+                let scale = scale.max(Vec2::splat(0.05));
+                (Mat2::from_diagonal(scale), 0.0, (scale.x * scale.y).sqrt())
+            }
+            GizmoHandle::Pivot => return,
+        };
+        let t = Affine2::from_translation(pivot)
+            * Affine2::from_mat2(linear)
+            * Affine2::from_translation(-pivot);
+
+        if push_undo {
+            if linear.abs_diff_eq(&Mat2::IDENTITY, 1e-4) {
+                return;
+            }
+            app.push_undo("Transform Selection");
+            push_undo = false;
+        }
+
+        let old_bounds = app.doc.selected_bounds();
+        let doc = &mut app.doc;
+        for (key, start) in &start_nodes {
+            let Some(node) = doc.nodes.get_mut(*key) else { continue };
+            node.pos = transform_pos(&t, start.pos);
+            node.radius = ((start.radius as f32 * radius_scale).round() as usize).max(1);
+            node.rotation = (start.rotation + angle).rem_euclid(TAU);
+        }
+        for (key, points) in &start_curves {
+            let Some(edge) = doc.edges.get_mut(*key) else { continue };
+            edge.control_points = points.iter().map(|&p| transform_pos(&t, p)).collect();
+        }
+        for (key, start) in &start_plants {
+            let Some(plant) = doc.plants.get_mut(*key) else { continue };
+            plant.pos = transform_pos(&t, start.pos);
+            plant.dir = (linear * start.dir).try_normalize().unwrap_or(start.dir);
+        }
+        if let Some((zone, start)) = &start_zone {
+            zone.update(&mut doc.markup, transform_zone(&t, start));
+        }
+
+        let bounds = old_bounds.union(app.doc.selected_bounds());
+        for &layer in &layers {
+            app.dirty_mask.mark_dirty_rect(layer, bounds);
+        }
+    }
+}
+
+fn operation_move_symmetry_pivot() -> impl FnMut(&mut App, &UIEvent) {
+    let mut push_undo = true;
+    move |app, _event| {
+        let pos_world = app.screen_to_document(app.last_mouse_pos);
+        let pivot = Document::snap_to_grid(pos_world, app.doc.cell_size).as_ivec2();
+        if pivot == app.doc.symmetry.pivot {
+            return;
+        }
+        if push_undo {
+            app.push_undo("Move Symmetry Pivot");
+            push_undo = false;
+        }
+        app.doc.symmetry.pivot = pivot;
     }
 }
 
@@ -873,6 +1837,7 @@ fn operation_move_graph_node_radius(
             Some(n) => n.pos,
             _ => return,
         };
+        let old_bounds = doc.selected_bounds();
         for selection in &doc.selected {
             match *selection {
                 SelectRef::Node(key) | SelectRef::NodeRadius(key) => {
@@ -888,8 +1853,42 @@ fn operation_move_graph_node_radius(
             }
         }
 
+        let bounds = old_bounds.union(doc.selected_bounds());
         drop(doc);
-        app.dirty_mask.mark_dirty_layer(current_layer);
+        app.dirty_mask.mark_dirty_rect(current_layer, bounds);
+    }
+}
+
+fn operation_move_graph_node_vertex(
+    app: &mut App,
+    edited_key: GraphNodeKey,
+    index: usize,
+) -> impl FnMut(&mut App, &UIEvent) {
+    let mut push_undo = true;
+    app.locked_hover = Some(SelectRef::NodeVertex(edited_key, index));
+    move |app, _event| {
+        let pos_world = app
+            .view
+            .screen_to_world()
+            .transform_point2(app.last_mouse_pos);
+
+        if push_undo {
+            app.push_undo("Move Polygon Vertex");
+            push_undo = false;
+        }
+
+        let doc = &mut app.doc;
+        let pos = Document::snap_to_grid(pos_world, doc.cell_size);
+        let Some(node) = doc.nodes.get_mut(edited_key) else { return };
+        let old_bounds = node.bounds();
+        let local =
+            rotate_vec2(pos - node.pos.as_vec2(), -node.rotation) / node.radius.max(1) as f32;
+        let GraphNodeShape::Polygon { points } = &mut node.shape else { return };
+        let Some(point) = points.get_mut(index) else { return };
+        *point = local;
+        let bounds = old_bounds.union(node.bounds());
+        let layer = node.layer;
+        app.dirty_mask.mark_dirty_rect(layer, bounds);
     }
 }
 
@@ -912,7 +1911,10 @@ fn operation_move_plant_direction(
 
         let doc = &mut app.doc;
         let current_layer = doc.current_layer;
-        let Some(edited_pos) = doc.plants.get(edited_key).map(|p| p.pos) else { return };
+        let Some(edited_plant) = doc.plants.get(edited_key) else { return };
+        let edited_pos = edited_plant.pos;
+        // direction does not change the area that plants can occupy
+        let bounds = doc.selected_bounds().union(edited_plant.bounds());
         for &selection in doc
             .selected
             .iter()
@@ -930,7 +1932,46 @@ fn operation_move_plant_direction(
         }
 
         drop(doc);
-        app.dirty_mask.mark_dirty_layer(current_layer);
+        app.dirty_mask.mark_dirty_rect(current_layer, bounds);
+    }
+}
+
+fn operation_move_edge_control_point(
+    app: &mut App,
+    edited_key: GraphEdgeKey,
+    index: usize,
+) -> impl FnMut(&mut App, &UIEvent) {
+    let mut push_undo = true;
+    app.locked_hover = Some(SelectRef::EdgeControl(edited_key, index));
+    move |app, _event| {
+        let pos_world = app
+            .view
+            .screen_to_world()
+            .transform_point2(app.last_mouse_pos);
+
+        if push_undo {
+            app.push_undo("Move Edge Control Point");
+            push_undo = false;
+        }
+
+        let doc = &mut app.doc;
+        let Some(edge) = doc.edges.get(edited_key) else { return };
+        let Some(old_bounds) = edge.bounds(&doc.nodes) else { return };
+        let layers: Vec<LayerKey> = [edge.start, edge.end]
+            .iter()
+            .filter_map(|key| doc.nodes.get(*key).map(|n| n.layer))
+            .collect();
+        let pos = Document::snap_to_grid(pos_world, doc.cell_size).as_ivec2();
+        let edge = &mut doc.edges[edited_key];
+        let Some(point) = edge.control_points.get_mut(index) else { return };
+        *point = pos;
+        let bounds = edge
+            .bounds(&doc.nodes)
+            .map_or(old_bounds, |b| b.union(old_bounds));
+
+        for layer in layers {
+            app.dirty_mask.mark_dirty_rect(layer, bounds);
+        }
     }
 }
 
@@ -950,6 +1991,10 @@ fn operation_rectangle_selection(
         SelectOperation::Replace => vec![],
         SelectOperation::Extend | SelectOperation::Substract => app.doc.selected.clone(),
     };
+    let start_cell_selection = match operation {
+        SelectOperation::Replace => Grid::new(0),
+        SelectOperation::Extend | SelectOperation::Substract => app.doc.selection.clone(),
+    };
 
     let mut changed = false;
     move |app, event| match event {
@@ -974,6 +2019,9 @@ fn operation_rectangle_selection(
                 }
             };
             for (node_key, node) in &app.doc.nodes {
+                if app.doc.is_layer_locked(node.layer) {
+                    continue;
+                }
                 let [min, max] = node.bounds();
                 let bounds = [
                     app.view.world_to_screen().transform_point2(min),
@@ -984,6 +2032,9 @@ fn operation_rectangle_selection(
             }
 
             for (plant_key, plant) in &app.doc.plants {
+                if app.doc.is_layer_locked(plant.layer) {
+                    continue;
+                }
                 let pos_screen = app
                     .view
                     .world_to_screen()
@@ -998,6 +2049,45 @@ fn operation_rectangle_selection(
                 app.doc.selected = new_selection;
             }
 
+            // cells are selected in the grid of the current layer
+            if !app.doc.is_layer_locked(app.doc.current_layer) {
+                let screen_to_world = app.view.screen_to_world();
+                let grid_rect = Grid::<u8>::world_to_grid_rect(
+                    [
+                        screen_to_world.transform_point2(rect[0]),
+                        screen_to_world.transform_point2(rect[1]),
+                    ],
+                    app.doc.cell_size,
+                );
+                let mut cell_selection = start_cell_selection.clone();
+                match operation {
+                    SelectOperation::Substract => {
+                        if let Some(common) = cell_selection.bounds.intersect(grid_rect) {
+                            for y in common[0].y..common[1].y {
+                                for x in common[0].x..common[1].x {
+                                    let index = cell_selection.grid_pos_index(x, y);
+                                    cell_selection.cells[index] = 0;
+                                }
+                            }
+                        }
+                    }
+                    SelectOperation::Extend | SelectOperation::Replace => {
+                        if cell_selection.bounds.is_null() {
+                            cell_selection.resize(grid_rect);
+                        } else {
+                            cell_selection.resize(cell_selection.bounds.union(grid_rect));
+                        }
+                        for y in grid_rect[0].y..grid_rect[1].y {
+                            for x in grid_rect[0].x..grid_rect[1].x {
+                                let index = cell_selection.grid_pos_index(x, y);
+                                cell_selection.cells[index] = 1;
+                            }
+                        }
+                    }
+                }
+                app.doc.selection = cell_selection;
+            }
+
             app.operation_batch.set_image(app.white_texture);
 
             app.operation_batch
@@ -1022,6 +2112,7 @@ fn operation_rectangle_selection(
                         }
                     }
                     SelectOperation::Replace => {
+                        app.doc.selection.clear();
                         app.doc.selected.clear();
                         if !app.doc.selected.contains(&sel_ref) {
                             app.doc.selected.push(sel_ref);
@@ -1060,6 +2151,9 @@ fn operation_paint_selection(
             }
         };
         for (node_key, node) in &app.doc.nodes {
+            if app.doc.is_layer_locked(node.layer) {
+                continue;
+            }
             let [min, max] = node.bounds();
             let bounds = [
                 app.view.world_to_screen().transform_point2(min),
@@ -1069,6 +2163,9 @@ fn operation_paint_selection(
             test_and_add(bounds, SelectRef::Node(node_key));
         }
         for (plant_key, plant) in &app.doc.plants {
+            if app.doc.is_layer_locked(plant.layer) {
+                continue;
+            }
             let pos_screen = app
                 .view
                 .world_to_screen()
@@ -1083,3 +2180,46 @@ fn operation_paint_selection(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::ivec2;
+
+    use super::*;
+
+    #[test]
+    fn cut_round_trip_with_radius_selection() {
+        let mut doc = Document::new();
+        let layer = doc.current_layer;
+        let a = doc.nodes.insert(GraphNode {
+            pos: ivec2(0, 0),
+            radius: 24,
+            layer,
+            ..GraphNode::new()
+        });
+        let b = doc.nodes.insert(GraphNode {
+            pos: ivec2(128, 64),
+            radius: 32,
+            layer,
+            ..GraphNode::new()
+        });
+        doc.edges.insert(GraphEdge::new(a, b));
+        // radius handle of `a` is grabbed, as after resizing it
+        doc.selected = vec![SelectRef::NodeRadius(a), SelectRef::Node(b)];
+
+        let text = ClipboardContent::from_selection(&doc).to_text().unwrap();
+        remove_selection(&mut doc, &mut ChangeMask::default());
+        assert!(doc.nodes.is_empty());
+        assert!(doc.edges.is_empty());
+        assert!(doc.selected.is_empty());
+
+        let content = ClipboardContent::from_text(&text).unwrap().unwrap();
+        let selection = content.paste(&mut doc, IVec2::ZERO);
+        assert_eq!(selection.len(), 2);
+        let mut nodes: Vec<(IVec2, usize)> =
+            doc.nodes.values().map(|n| (n.pos, n.radius)).collect();
+        nodes.sort_by_key(|&(pos, _)| (pos.x, pos.y));
+        assert_eq!(nodes, vec![(ivec2(0, 0), 24), (ivec2(128, 64), 32)]);
+        assert_eq!(doc.edges.len(), 1);
+    }
+}
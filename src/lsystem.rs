@@ -0,0 +1,280 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+/// Longest expanded string, larger grammars would stall generation
+const MAX_SYMBOLS: usize = 100_000;
+pub const MAX_ITERATIONS: u32 = 10;
+
+/// Lindenmayer system interpreted with turtle graphics:
+///
+/// - `F` and `G` move forward and draw a segment
+/// - `f` moves forward without drawing
+/// - `+` and `-` turn by `angle`, `|` turns around
+/// - `[` and `]` start and end a branch
+///
+/// Other symbols are only used by rules.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LSystem {
+    pub axiom: String,
+    /// Production rules written as `symbol=replacement`, empty ones are skipped
+    pub rules: Vec<String>,
+    /// Turn of `+` and `-`, in radians
+    pub angle: f32,
+    pub step_length: f32,
+    pub iterations: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Turtle {
+    Draw,
+    Move,
+    Turn(f32),
+    Push,
+    Pop,
+}
+
+impl LSystem {
+    pub fn presets() -> [(&'static str, LSystem); 3] {
+        let preset = |axiom: &str, rules: &[&str], degrees: f32, step_length, iterations| LSystem {
+            axiom: axiom.to_owned(),
+            rules: rules.iter().map(|r| r.to_string()).collect(),
+            angle: degrees.to_radians(),
+            step_length,
+            iterations,
+        };
+        [
+            (
+                "Vine",
+                preset("X", &["X=F-[[X]+X]+F[+FX]-X", "F=FF"], 22.5, 3.0, 4),
+            ),
+            (
+                "Tree",
+                preset("F", &["F=FF+[+F-F-F]-[-F+F+F]"], 22.5, 6.0, 3),
+            ),
+            (
+                "Coral",
+                preset("X", &["X=F[+X][-X]FX", "F=FF"], 35.0, 4.0, 4),
+            ),
+        ]
+    }
+
+    /// Parsed rules, as symbol and replacement
+    fn parse_rules(&self) -> Result<Vec<(char, &str)>> {
+        let mut rules: Vec<(char, &str)> = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            let number = index + 1;
+            let rule = rule.trim();
+            if rule.is_empty() {
+                continue;
+            }
+            let (symbol, replacement) = rule
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Rule {}: expected \"symbol=replacement\"", number))?;
+            let mut symbol_chars = symbol.trim().chars();
+            let (Some(symbol), None) = (symbol_chars.next(), symbol_chars.next()) else {
+                bail!(
+                    "Rule {}: \"{}\" should be a single symbol",
+                    number,
+                    symbol.trim()
+                );
+            };
+            if symbol == '[' || symbol == ']' {
+                bail!(
+                    "Rule {}: branch symbol '{}' can not be replaced",
+                    number,
+                    symbol
+                );
+            }
+            if rules.iter().any(|&(s, _)| s == symbol) {
+                bail!(
+                    "Rule {}: '{}' is already replaced by another rule",
+                    number,
+                    symbol
+                );
+            }
+            check_branches(replacement.trim()).map_err(|e| anyhow!("Rule {}: {}", number, e))?;
+            rules.push((symbol, replacement.trim()));
+        }
+        Ok(rules)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.expand().map(|_| ())
+    }
+
+    /// Axiom with rules applied `iterations` times
+    pub fn expand(&self) -> Result<String> {
+        if self.axiom.trim().is_empty() {
+            bail!("Axiom is empty");
+        }
+        check_branches(self.axiom.trim()).map_err(|e| anyhow!("Axiom: {}", e))?;
+        if self.iterations > MAX_ITERATIONS {
+            bail!("Iterations can not exceed {}", MAX_ITERATIONS);
+        }
+        if !self.step_length.is_finite() || self.step_length <= 0.0 {
+            bail!("Step length should be positive");
+        }
+        if !self.angle.is_finite() {
+            bail!("Angle is not a number");
+        }
+        let rules = self.parse_rules()?;
+
+        let mut current = self.axiom.trim().to_owned();
+        for iteration in 0..self.iterations {
+            let mut next = String::with_capacity(current.len() * 2);
+            for symbol in current.chars() {
+                match rules.iter().find(|&&(s, _)| s == symbol) {
+                    Some((_, replacement)) => next.push_str(replacement),
+                    None => next.push(symbol),
+                }
+                if next.len() > MAX_SYMBOLS {
+                    bail!(
+                        "Iteration {} grows past {} symbols, use fewer iterations",
+                        iteration + 1,
+                        MAX_SYMBOLS
+                    );
+                }
+            }
+            current = next;
+        }
+        Ok(current)
+    }
+
+    /// Turtle commands of the expanded grammar
+    pub fn commands(&self) -> Result<Vec<Turtle>> {
+        Ok(self
+            .expand()?
+            .chars()
+            .filter_map(|symbol| match symbol {
+                'F' | 'G' => Some(Turtle::Draw),
+                'f' => Some(Turtle::Move),
+                '+' => Some(Turtle::Turn(self.angle)),
+                '-' => Some(Turtle::Turn(-self.angle)),
+                '|' => Some(Turtle::Turn(PI)),
+                '[' => Some(Turtle::Push),
+                ']' => Some(Turtle::Pop),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+fn check_branches(text: &str) -> Result<()> {
+    let mut open = 0;
+    for (index, symbol) in text.chars().enumerate() {
+        match symbol {
+            '[' => open += 1,
+            ']' if open == 0 => bail!("']' at position {} closes no branch", index + 1),
+            ']' => open -= 1,
+            _ => {}
+        }
+    }
+    if open > 0 {
+        bail!("{} '[' not closed with ']'", open);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lsystem(axiom: &str, rules: &[&str], iterations: u32) -> LSystem {
+        LSystem {
+            axiom: axiom.to_owned(),
+            rules: rules.iter().map(|r| r.to_string()).collect(),
+            angle: 0.5,
+            step_length: 4.0,
+            iterations,
+        }
+    }
+
+    fn error(lsystem: &LSystem) -> String {
+        format!("{:#}", lsystem.expand().unwrap_err())
+    }
+
+    #[test]
+    fn expands_rules_in_parallel() {
+        let coral = lsystem("X", &["X=F[+X][-X]FX", "F=FF"], 2);
+        assert_eq!(
+            coral.expand().unwrap(),
+            "FF[+F[+X][-X]FX][-F[+X][-X]FX]FFF[+X][-X]FX"
+        );
+    }
+
+    #[test]
+    fn expands_presets() {
+        for (name, preset) in LSystem::presets() {
+            let commands = preset.commands().unwrap();
+            assert!(commands.contains(&Turtle::Draw), "{} draws nothing", name);
+        }
+    }
+
+    #[test]
+    fn skips_empty_rules() {
+        let lsystem = lsystem("F", &["", "  ", "F=F+F"], 1);
+        assert_eq!(lsystem.expand().unwrap(), "F+F");
+    }
+
+    #[test]
+    fn rejects_empty_axiom() {
+        assert_eq!(error(&lsystem(" ", &[], 1)), "Axiom is empty");
+    }
+
+    #[test]
+    fn rejects_rule_without_equals_sign() {
+        let lsystem = lsystem("F", &["F=FF", "FF"], 1);
+        assert_eq!(error(&lsystem), "Rule 2: expected \"symbol=replacement\"");
+    }
+
+    #[test]
+    fn rejects_multiple_character_symbol() {
+        let lsystem = lsystem("F", &["FG=F"], 1);
+        assert_eq!(error(&lsystem), "Rule 1: \"FG\" should be a single symbol");
+    }
+
+    #[test]
+    fn rejects_duplicate_rule() {
+        let lsystem = lsystem("F", &["F=FF", "F=F+F"], 1);
+        assert_eq!(
+            error(&lsystem),
+            "Rule 2: 'F' is already replaced by another rule"
+        );
+    }
+
+    #[test]
+    fn rejects_unbalanced_brackets() {
+        assert_eq!(
+            error(&lsystem("F[+F", &[], 1)),
+            "Axiom: 1 '[' not closed with ']'"
+        );
+        assert_eq!(
+            error(&lsystem("F", &["F=F]+F"], 1)),
+            "Rule 1: ']' at position 2 closes no branch"
+        );
+    }
+
+    #[test]
+    fn rejects_expansion_past_max_symbols() {
+        let lsystem = lsystem("F", &["F=FFFFFFFF"], 6);
+        assert!(error(&lsystem).starts_with("Iteration 6 grows past"));
+        assert!(LSystem {
+            iterations: 5,
+            ..lsystem
+        }
+        .validate()
+        .is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_step_length() {
+        for step_length in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let lsystem = LSystem {
+                step_length,
+                ..lsystem("F", &[], 1)
+            };
+            assert_eq!(error(&lsystem), "Step length should be positive");
+        }
+    }
+}
@@ -1,19 +1,28 @@
 #![windows_subsystem = "windows"]
 mod app;
+mod clipboard;
 mod document;
+mod export;
 mod field;
+mod generation;
+mod gizmo;
+#[cfg(test)]
+mod golden_tests;
 mod graph;
 mod graphics;
 mod grid;
 mod grid_segment_iterator;
 mod interaction;
+mod lsystem;
 mod math;
 mod mouse_operation;
 mod net_client_connection;
 mod plant;
 mod profiler;
+mod rasterizer;
 mod sdf;
 mod some_or;
+mod symmetry;
 mod tool;
 mod ui;
 mod undo_stack;
@@ -33,6 +42,7 @@ use glam::vec2;
 use log::{error, info};
 use miniquad::{conf, EventHandler, KeyMods, PassAction, UserData};
 use rimui::*;
+use std::mem::take;
 use std::path::PathBuf;
 use tool::Tool;
 use tracy_client::span;
@@ -63,16 +73,21 @@ impl EventHandler for App {
         self.ui(context, time, dt);
 
         if self.dirty_mask != ChangeMask::default() {
-            self.generation_profiler.begin_frame();
-            self.graphics.borrow_mut().generate(
+            self.undo.borrow_mut().mark_changes(&self.dirty_mask);
+            self.generation.start(
                 &self.doc,
-                self.dirty_mask,
-                false,
-                Some(context),
+                take(&mut self.dirty_mask),
+                self.view.target,
+                &mut self.graphics.borrow_mut(),
+                context,
                 &mut self.generation_profiler,
             );
-            self.dirty_mask = ChangeMask::default();
         }
+        self.generation.receive(
+            &mut self.graphics.borrow_mut(),
+            context,
+            &mut self.generation_profiler,
+        );
 
         self.last_time = time;
         tracy_client::finish_continuous_frame!("update");
@@ -165,6 +180,12 @@ impl EventHandler for App {
             );
         }
 
+        if self.doc.symmetry.is_enabled() {
+            self.doc
+                .symmetry
+                .draw(&mut self.batch, &self.view, self.window_size.into());
+        }
+
         match self.tool {
             Tool::Select => {
                 self.doc.draw_selectable(
@@ -186,6 +207,13 @@ impl EventHandler for App {
             _ => {}
         }
 
+        if let Some(gizmo) = self.gizmo() {
+            let hover = gizmo
+                .hit_test(self.last_mouse_pos, true)
+                .map(|(handle, _)| handle);
+            gizmo.draw(&mut self.batch, hover);
+        }
+
         self.batch.flush(None, context);
 
         self.operation_batch.draw(context, None);
@@ -393,14 +421,6 @@ fn find_resources_path() -> Option<PathBuf> {
 }
 
 fn main() {
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        // change current directory to res/ if we are being run from target/..
-        if let Some(resources_path) = find_resources_path() {
-            std::env::set_current_dir(&resources_path).expect("failed to set current directory");
-        }
-    }
-
     #[cfg(not(target_arch = "wasm32"))]
     simple_logger::SimpleLogger::new()
         .with_module_level("ws", log::LevelFilter::Warn)
@@ -409,6 +429,24 @@ fn main() {
         .init()
         .unwrap();
 
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        // headless subcommands, paths are relative to the invocation directory
+        let args: Vec<String> = std::env::args().collect();
+        if args.get(1).map(|a| a.as_str()) == Some("export") {
+            if let Err(err) = export::run_export(&args[2..]) {
+                error!("{:#}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        // change current directory to res/ if we are being run from target/..
+        if let Some(resources_path) = find_resources_path() {
+            std::env::set_current_dir(&resources_path).expect("failed to set current directory");
+        }
+    }
+
     miniquad::start(
         conf::Conf {
             window_title: "CBA Editor".to_owned(),
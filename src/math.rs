@@ -32,6 +32,42 @@ pub fn closest_point_on_segment(start: Vec2, end: Vec2, p: Vec2) -> (Vec2, f32)
     (start + fraction * delta, fraction)
 }
 
+/// Rotates `v` counter-clockwise by `angle` radians
+pub fn rotate_vec2(v: Vec2, angle: f32) -> Vec2 {
+    if angle == 0.0 {
+        return v;
+    }
+    let (sin, cos) = angle.sin_cos();
+    Vec2::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos)
+}
+
+/// Point of a Bézier curve of any degree at `t`
+pub fn bezier_point(points: &[Vec2], t: f32) -> Vec2 {
+    let mut points = points.to_vec();
+    for n in (1..points.len()).rev() {
+        for i in 0..n {
+            points[i] = points[i].lerp(points[i + 1], t);
+        }
+    }
+    points.first().copied().unwrap_or(Vec2::ZERO)
+}
+
+/// Splits a Bézier curve at `t` into two curves of the same degree (de Casteljau)
+pub fn split_bezier(points: &[Vec2], t: f32) -> (Vec<Vec2>, Vec<Vec2>) {
+    let mut first = Vec::with_capacity(points.len());
+    let mut second = Vec::with_capacity(points.len());
+    let mut points = points.to_vec();
+    for n in (0..points.len()).rev() {
+        first.push(points[0]);
+        second.push(points[n]);
+        for i in 0..n {
+            points[i] = points[i].lerp(points[i + 1], t);
+        }
+    }
+    second.reverse();
+    (first, second)
+}
+
 pub trait Rect {
     type Scalar;
     type Point;
@@ -1,8 +1,14 @@
 use crate::math::Rect;
+use crate::span;
 use glam::{ivec2, vec2, IVec2, Vec2};
 use serde::{Deserialize, Serialize};
 use slotmap::{new_key_type, SlotMap};
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::hash::Hasher;
+use twox_hash::XxHash64;
 
+use crate::lsystem::{LSystem, Turtle};
 use crate::{document::LayerKey, field::Field};
 
 new_key_type! {
@@ -10,7 +16,7 @@ new_key_type! {
     pub struct PlantSegmentKey;
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Plant {
     pub pos: IVec2,
     pub dir: Vec2,
@@ -22,6 +28,51 @@ pub struct Plant {
     pub segment_length: f32,
     pub branch_period: f32,
     pub max_length: f32,
+
+    // variation
+    /// Same seed grows the same plant
+    #[serde(default)]
+    pub seed: u32,
+    /// Angle that direction turns by after every segment, in radians
+    #[serde(default = "Plant::default_bend")]
+    pub bend: f32,
+    #[serde(default)]
+    pub bend_jitter: f32,
+    /// Angle between a parent branch and its children, in radians
+    #[serde(default = "Plant::default_branch_angle")]
+    pub branch_angle: f32,
+    #[serde(default)]
+    pub branch_angle_jitter: f32,
+    /// Fraction of the thickness lost from root to the tip at `max_length`
+    #[serde(default)]
+    pub taper: f32,
+    /// Share of the remaining parent length that child branch can grow
+    #[serde(default = "Plant::default_branch_decay")]
+    pub branch_decay: f32,
+    #[serde(default = "Plant::default_max_depth")]
+    pub max_depth: u32,
+
+    // surroundings
+    #[serde(default)]
+    pub surface: SurfaceMode,
+    /// Material that branches turn away from and stop at
+    #[serde(default)]
+    pub avoid_material: Option<u8>,
+
+    /// Grows from a grammar instead of periodic branching
+    #[serde(default)]
+    pub lsystem: Option<LSystem>,
+}
+
+/// How growth reacts to distances of the layer, including layers below it
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum SurfaceMode {
+    #[default]
+    Ignore,
+    /// Branches turn along nearby surfaces and keep touching them
+    Cling,
+    /// Branches end where they would run into a surface
+    StopAtCollision,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -37,6 +88,45 @@ struct Branch {
     pos: Vec2,
     dir: Vec2,
     start_length: f32,
+    end_length: f32,
+    depth: u32,
+}
+
+#[derive(Clone, Copy)]
+struct TurtleState {
+    pos: Vec2,
+    dir: Vec2,
+    /// Distance from the root along the branch
+    length: f32,
+    /// Ran into a surface, nothing grows until the branch ends
+    blocked: bool,
+}
+
+/// Small deterministic generator (SplitMix64), so that growth does not depend on platform or
+/// crate versions
+struct PlantRng(u64);
+
+impl PlantRng {
+    fn new(seed: u32) -> Self {
+        Self(seed as u64)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `[-range, range]`
+    fn jitter(&mut self, range: f32) -> f32 {
+        if range == 0.0 {
+            return 0.0;
+        }
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        (unit * 2.0 - 1.0) * range
+    }
 }
 
 impl Plant {
@@ -50,65 +140,350 @@ impl Plant {
             material: 3,
             layer: LayerKey::default(),
             max_length: 1024.0,
+            seed: 0,
+            bend: Self::default_bend(),
+            bend_jitter: 0.01 * PI,
+            branch_angle: Self::default_branch_angle(),
+            branch_angle_jitter: 0.05 * PI,
+            taper: 0.75,
+            branch_decay: 0.75,
+            max_depth: 8,
+            surface: SurfaceMode::Ignore,
+            avoid_material: None,
+            lsystem: None,
         }
     }
 
-    pub fn grow_plants(
-        _field: &Field,
-        plants: &SlotMap<PlantKey, Plant>,
-        segments: &mut SlotMap<PlantSegmentKey, PlantSegment>,
-    ) {
-        segments.clear();
-        println!("Growing plants...");
-
-        for (plant_k, plant) in plants {
-            let mut branch_stack = Vec::new();
-            branch_stack.push(Branch {
-                pos: plant.pos.as_vec2(),
-                dir: plant.dir,
-                start_length: 0.0,
-            });
-            while let Some(Branch {
-                mut pos,
-                mut dir,
-                start_length: mut length,
-            }) = branch_stack.pop()
-            {
-                let last_branch_length = length;
-                let mut last_pos = pos;
-                let mut dir = dir.normalize();
-                while length < plant.max_length {
-                    let next_branch = last_branch_length + plant.branch_period;
-                    let last_length = length;
-                    length += plant.segment_length;
-                    pos += dir * plant.segment_length;
-                    dir = bend_dir(dir, 0.0025 * std::f32::consts::PI);
-                    println!("l {length} {dir}");
-                    if last_length < next_branch && length >= next_branch {
+    fn default_bend() -> f32 {
+        0.0025 * PI
+    }
+
+    fn default_branch_angle() -> f32 {
+        0.1 * PI
+    }
+
+    fn default_branch_decay() -> f32 {
+        1.0
+    }
+
+    /// Plants saved before the depth limit existed keep growing without it
+    fn default_max_depth() -> u32 {
+        u32::MAX
+    }
+
+    /// Growth of such plants depends on the distance field around them
+    pub(crate) fn reacts_to_surroundings(&self) -> bool {
+        self.surface != SurfaceMode::Ignore || self.avoid_material.is_some()
+    }
+
+    fn thickness_at(&self, length: f32) -> f32 {
+        let t = (length / self.max_length).clamp(0.0, 1.0);
+        self.thickness * (1.0 - self.taper.clamp(0.0, 1.0) * t)
+    }
+
+    /// Area that grown segments of the plant can occupy
+    pub(crate) fn bounds(&self) -> [Vec2; 2] {
+        let reach = self.max_length + self.segment_length + self.thickness;
+        [
+            self.pos.as_vec2() - Vec2::splat(reach),
+            self.pos.as_vec2() + Vec2::splat(reach),
+        ]
+    }
+
+    /// Segments of the plant, grown within `surroundings` when the plant reacts to them
+    fn grow(&self, key: PlantKey, surroundings: &Surroundings) -> Vec<PlantSegment> {
+        if let Some(lsystem) = &self.lsystem {
+            return self.grow_lsystem(lsystem, key, surroundings);
+        }
+        let mut segments = Vec::new();
+        if self.segment_length <= 0.0 {
+            return segments;
+        }
+        let mut rng = PlantRng::new(self.seed);
+        let mut branch_stack = Vec::new();
+        branch_stack.push(Branch {
+            pos: self.pos.as_vec2(),
+            dir: self.dir,
+            start_length: 0.0,
+            end_length: self.max_length,
+            depth: 0,
+        });
+        while let Some(Branch {
+            mut pos,
+            dir,
+            start_length: mut length,
+            end_length,
+            depth,
+        }) = branch_stack.pop()
+        {
+            let last_branch_length = length;
+            let mut last_pos = pos;
+            let mut dir = dir.normalize();
+            while length < end_length {
+                let next_branch = last_branch_length + self.branch_period;
+                let last_length = length;
+                length += self.segment_length;
+                pos += dir * self.segment_length;
+                let thickness = self.thickness_at(length);
+                if self.reacts_to_surroundings()
+                    && surroundings.blocks(self, last_pos, pos, thickness)
+                {
+                    break;
+                }
+                dir = bend_dir(dir, self.bend + rng.jitter(self.bend_jitter));
+                if self.reacts_to_surroundings() {
+                    dir = surroundings.steer(self, pos, dir, thickness);
+                }
+                if last_length < next_branch && length >= next_branch && depth < self.max_depth {
+                    let child_end_length =
+                        length + (end_length - length) * self.branch_decay.clamp(0.0, 1.0);
+                    for side in [1.0, -1.0] {
+                        let angle = self.branch_angle + rng.jitter(self.branch_angle_jitter);
                         branch_stack.push(Branch {
                             pos,
-                            dir: bend_dir(dir, 0.1 * std::f32::consts::PI),
+                            dir: bend_dir(dir, side * angle),
                             start_length: length,
+                            end_length: child_end_length,
+                            depth: depth + 1,
                         });
-                        branch_stack.push(Branch {
-                            pos,
-                            dir: bend_dir(dir, -0.1 * std::f32::consts::PI),
-                            start_length: length,
+                    }
+                }
+                segments.push(PlantSegment {
+                    start: last_pos,
+                    end: pos,
+                    start_thickness: self.thickness_at(last_length),
+                    end_thickness: thickness,
+                    plant: key,
+                });
+                last_pos = pos;
+            }
+        }
+        segments
+    }
+
+    /// Follows turtle commands of the grammar. Invalid grammars grow nothing, steps that would
+    /// go further than `max_length` from the root are skipped to keep the plant within bounds.
+    fn grow_lsystem(
+        &self,
+        lsystem: &LSystem,
+        key: PlantKey,
+        surroundings: &Surroundings,
+    ) -> Vec<PlantSegment> {
+        let mut segments = Vec::new();
+        let Ok(commands) = lsystem.commands() else { return segments };
+        let mut rng = PlantRng::new(self.seed);
+        let mut turtle = TurtleState {
+            pos: self.pos.as_vec2(),
+            dir: self.dir.normalize(),
+            length: 0.0,
+            blocked: false,
+        };
+        let mut stack = Vec::new();
+        for command in commands {
+            match command {
+                Turtle::Draw | Turtle::Move => {
+                    let step = lsystem.step_length;
+                    if turtle.blocked || turtle.length + step > self.max_length {
+                        continue;
+                    }
+                    let last_pos = turtle.pos;
+                    let last_length = turtle.length;
+                    turtle.length += step;
+                    turtle.pos += turtle.dir * step;
+                    let thickness = self.thickness_at(turtle.length);
+                    if self.reacts_to_surroundings()
+                        && surroundings.blocks(self, last_pos, turtle.pos, thickness)
+                    {
+                        // rest of the branch is dropped
+                        turtle.blocked = true;
+                        continue;
+                    }
+                    if command == Turtle::Draw {
+                        segments.push(PlantSegment {
+                            start: last_pos,
+                            end: turtle.pos,
+                            start_thickness: self.thickness_at(last_length),
+                            end_thickness: thickness,
+                            plant: key,
                         });
                     }
-                    segments.insert(PlantSegment {
-                        start: last_pos,
-                        end: pos,
-                        start_thickness: plant.thickness,
-                        end_thickness: plant.thickness,
-                        plant: plant_k,
-                    });
-                    last_pos = pos;
+                    turtle.dir = bend_dir(turtle.dir, rng.jitter(self.bend_jitter));
+                    if self.reacts_to_surroundings() {
+                        turtle.dir = surroundings.steer(self, turtle.pos, turtle.dir, thickness);
+                    }
+                }
+                Turtle::Turn(angle) => {
+                    let angle = angle + rng.jitter(self.branch_angle_jitter);
+                    turtle.dir = bend_dir(turtle.dir, angle);
+                }
+                Turtle::Push => stack.push(turtle),
+                Turtle::Pop => turtle = stack.pop().unwrap_or(turtle),
+            }
+        }
+        segments
+    }
+
+    /// Changes whenever growth of the plant would change, apart from its surroundings
+    fn growth_hash(&self) -> Option<u64> {
+        let bytes = bincode::serialize(self).ok()?;
+        let mut hasher = XxHash64::with_seed(0);
+        hasher.write(&bytes);
+        Some(hasher.finish())
+    }
+}
+
+/// Segments of grown plants, kept between generations so that only changed plants regrow
+#[derive(Default)]
+pub struct GrownPlants {
+    /// Segments of plants, by layer of the plant
+    pub segments: HashMap<LayerKey, SlotMap<PlantSegmentKey, PlantSegment>>,
+    plants: HashMap<PlantKey, GrownPlant>,
+}
+
+struct GrownPlant {
+    hash: Option<u64>,
+    layer: LayerKey,
+    segments: Vec<PlantSegmentKey>,
+}
+
+impl GrownPlants {
+    /// Regrows plants of the layer that changed since they were grown. Plants that react to
+    /// their surroundings are always regrown, as `field` could have changed. It holds composed
+    /// distances of the layer and the layers below it, with a cell size of `cell_size`.
+    pub fn grow_layer(
+        &mut self,
+        field: &Field,
+        cell_size: i32,
+        layer: LayerKey,
+        plants: &SlotMap<PlantKey, Plant>,
+    ) {
+        let _span = span!("GrownPlants::grow_layer");
+        let Self {
+            segments,
+            plants: grown_plants,
+        } = self;
+
+        // removed plants and plants that moved to other layers
+        grown_plants.retain(|&key, grown| {
+            let keep = plants.get(key).is_some_and(|p| p.layer == grown.layer);
+            if !keep {
+                let layer_segments = segments.entry(grown.layer).or_default();
+                for &segment in &grown.segments {
+                    layer_segments.remove(segment);
                 }
             }
+            keep
+        });
+
+        let surroundings = Surroundings { field, cell_size };
+        let layer_segments = segments.entry(layer).or_insert_with(SlotMap::with_key);
+        for (key, plant) in plants.iter().filter(|(_, p)| p.layer == layer) {
+            let hash = plant.growth_hash();
+            if let Some(grown) = grown_plants.get(&key) {
+                if hash.is_some() && grown.hash == hash && !plant.reacts_to_surroundings() {
+                    continue;
+                }
+                for &segment in &grown.segments {
+                    layer_segments.remove(segment);
+                }
+            }
+            let new_segments = plant
+                .grow(key, &surroundings)
+                .into_iter()
+                .map(|segment| layer_segments.insert(segment))
+                .collect();
+            grown_plants.insert(
+                key,
+                GrownPlant {
+                    hash,
+                    layer,
+                    segments: new_segments,
+                },
+            );
+        }
+    }
+}
+
+struct Surroundings<'a> {
+    field: &'a Field,
+    cell_size: i32,
+}
+
+impl Surroundings<'_> {
+    /// Distance to the closest material
+    fn surface_distance(&self, pos: Vec2) -> f32 {
+        (1..self.field.materials.len())
+            .map(|material| self.field.distance_at(material, pos, self.cell_size))
+            .fold(f32::MAX, f32::min)
+    }
+
+    fn material_distance(&self, material: u8, pos: Vec2) -> f32 {
+        self.field
+            .distance_at(material as usize, pos, self.cell_size)
+    }
+
+    /// Direction in which `distance` grows, zero when it is flat
+    fn gradient(&self, pos: Vec2, distance: impl Fn(Vec2) -> f32) -> Vec2 {
+        let h = self.cell_size as f32;
+        let dx = distance(pos + vec2(h, 0.0)) - distance(pos - vec2(h, 0.0));
+        let dy = distance(pos + vec2(0.0, h)) - distance(pos - vec2(0.0, h));
+        vec2(dx, dy).normalize_or_zero()
+    }
+
+    /// Segments may leave a surface they start in, but not enter one
+    fn blocks(&self, plant: &Plant, from: Vec2, to: Vec2, thickness: f32) -> bool {
+        let limit = match plant.surface {
+            SurfaceMode::Ignore => None,
+            SurfaceMode::Cling => Some(0.0),
+            SurfaceMode::StopAtCollision => Some(thickness),
+        };
+        if let Some(limit) = limit {
+            let d = self.surface_distance(to);
+            if d < limit && d < self.surface_distance(from) {
+                return true;
+            }
+        }
+        if let Some(material) = plant.avoid_material {
+            let d = self.material_distance(material, to);
+            if d < thickness && d < self.material_distance(material, from) {
+                return true;
+            }
         }
+        false
+    }
 
-        println!("Grown {} segments", segments.len());
+    fn steer(&self, plant: &Plant, pos: Vec2, dir: Vec2, thickness: f32) -> Vec2 {
+        let reach = plant.segment_length * 4.0 + thickness;
+        let mut dir = dir;
+        if plant.surface == SurfaceMode::Cling {
+            let d = self.surface_distance(pos);
+            let normal = self.gradient(pos, |p| self.surface_distance(p));
+            if d < reach && normal != Vec2::ZERO {
+                let tangent = if normal.perp().dot(dir) < 0.0 {
+                    -normal.perp()
+                } else {
+                    normal.perp()
+                };
+                // keeps the branch at its own thickness from the surface
+                let pull = ((thickness - d) / reach).clamp(-1.0, 1.0);
+                let cling_dir = (tangent + normal * pull).normalize_or_zero();
+                let weight = (1.0 - d / reach).clamp(0.0, 1.0);
+                dir = dir.lerp(cling_dir, weight).try_normalize().unwrap_or(dir);
+            }
+        }
+        if let Some(material) = plant.avoid_material {
+            let d = self.material_distance(material, pos);
+            let away = self.gradient(pos, |p| self.material_distance(material, p));
+            // only the approaching part of the direction is removed, so branches slide past
+            let approach = dir.dot(away);
+            if d < reach && approach < 0.0 {
+                let weight = (1.0 - d / reach).clamp(0.0, 1.0);
+                dir = (dir - away * approach * weight)
+                    .try_normalize()
+                    .unwrap_or_else(|| away.perp());
+            }
+        }
+        dir
     }
 }
 
@@ -130,3 +505,53 @@ fn bend_dir(d: Vec2, angle: f32) -> Vec2 {
     let my = mx.perp();
     vec2(mx.dot(d), my.dot(d)).normalize()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grow(plant: &Plant) -> Vec<(Vec2, Vec2, f32, f32)> {
+        let field = Field::new();
+        let surroundings = Surroundings {
+            field: &field,
+            cell_size: 4,
+        };
+        plant
+            .grow(PlantKey::default(), &surroundings)
+            .into_iter()
+            .map(|s| (s.start, s.end, s.start_thickness, s.end_thickness))
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_grows_same_segments() {
+        let plant = Plant {
+            seed: 42,
+            ..Plant::new()
+        };
+        let segments = grow(&plant);
+        assert!(!segments.is_empty());
+        assert_eq!(segments, grow(&plant.clone()));
+    }
+
+    #[test]
+    fn different_seed_grows_different_segments() {
+        let plant = Plant {
+            seed: 42,
+            ..Plant::new()
+        };
+        let other = Plant {
+            seed: 43,
+            ..plant.clone()
+        };
+        assert_ne!(grow(&plant), grow(&other));
+    }
+
+    #[test]
+    fn older_plants_have_unlimited_depth() {
+        let mut value = serde_json::to_value(Plant::new()).unwrap();
+        value.as_object_mut().unwrap().remove("max_depth");
+        let plant: Plant = serde_json::from_value(value).unwrap();
+        assert_eq!(plant.max_depth, u32::MAX);
+    }
+}
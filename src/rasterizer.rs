@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+
+use glam::{vec2, IVec2, Vec2};
+
+use cbmap::{BuiltinMaterial, Material, MaterialSlot};
+
+use crate::field::Field;
+use crate::graphics::{DEFAULT_OUTLINE_WIDTH, FINISH_TEXTURE_PIXELS};
+use crate::some_or;
+
+/// Software implementation of the SDF pipeline used by `DocumentGraphics::draw_map`.
+///
+/// Mirrors what GPU does when a map is drawn at zoom 1:
+/// - distance tiles are sampled bilinearly, like padded `Alpha32F` textures with
+///   `FilterMode::Linear`;
+/// - color texture is white or the 4x4 finish checker, repeated every 32 world units;
+/// - fragment shader output is blended with `SourceAlpha, OneMinusSourceAlpha` into
+///   an RGBA8 target, one material after another;
+/// - the result is converted from premultiplied alpha the same way as
+///   `render_map_image` does after reading pixels back.
+pub struct SdfRasterizer<'a> {
+    pub field: &'a Field,
+    /// World size of a single distance cell
+    pub cell_size: f32,
+    pub materials: &'a [MaterialSlot],
+    pub resolved_materials: &'a [Material],
+    pub outline_widths: &'a [f32],
+}
+
+impl<'a> SdfRasterizer<'a> {
+    /// Renders `size` pixels, where the center of pixel `(0, 0)` is located at `origin` in
+    /// world coordinates. Returns straight-alpha RGBA8 pixels.
+    pub fn render(&self, origin: Vec2, size: IVec2) -> Vec<u8> {
+        let width = size.x.max(0) as usize;
+        let height = size.y.max(0) as usize;
+        let tile_size = self.field.tile_size as i32;
+        let tile_world_size = self.cell_size * tile_size as f32;
+
+        let mut pixels = vec![0u8; width * height * 4];
+        for (material, tiles) in self.field.materials.iter().enumerate() {
+            if tiles.is_empty() {
+                continue;
+            }
+            let resolved_material = some_or!(self.resolved_materials.get(material), continue);
+            let fill_color = resolved_material.fill_color.map(|c| c as f32 / 255.0);
+            let outline_color = resolved_material.outline_color.map(|c| c as f32 / 255.0);
+            // documents can refer to materials that have no width set
+            let outline_width = self
+                .outline_widths
+                .get(material)
+                .copied()
+                .unwrap_or(DEFAULT_OUTLINE_WIDTH);
+            let checker = matches!(
+                self.materials.get(material),
+                Some(MaterialSlot::BuiltIn(BuiltinMaterial::Finish))
+            );
+
+            for y in 0..height {
+                for x in 0..width {
+                    let world_pos = origin + vec2(x as f32, y as f32);
+
+                    // each tile is drawn as a separate quad, pixels outside of generated
+                    // tiles are not touched even if neighbours would bleed into them
+                    let quad = (world_pos / tile_world_size).floor().as_ivec2();
+                    if !tiles.contains_key(&(quad.x, quad.y)) {
+                        continue;
+                    }
+
+                    let d = sample_distance(tiles, tile_size, world_pos / self.cell_size);
+                    let tex_color = if checker {
+                        finish_texel(world_pos)
+                    } else {
+                        [1.0; 3]
+                    };
+                    let src =
+                        sdf_fragment(d, 1.0, outline_color, outline_width, fill_color, tex_color);
+
+                    let pixel = &mut pixels[(y * width + x) * 4..][..4];
+                    let src_alpha = src[3];
+                    for c in 0..4 {
+                        let dst = pixel[c] as f32 / 255.0;
+                        let blended = src[c] * src_alpha + dst * (1.0 - src_alpha);
+                        pixel[c] = (blended.clamp(0.0, 1.0) * 255.0).round() as u8;
+                    }
+                }
+            }
+        }
+
+        // divide by alpha to convert from premultiplied format
+        for pixel in pixels.chunks_exact_mut(4) {
+            let a = pixel[3] as i32;
+            for c in &mut pixel[..3] {
+                *c = if a != 0 {
+                    ((*c as i32 * 255 + a / 2) / a).min(255) as u8
+                } else {
+                    0
+                };
+            }
+        }
+        pixels
+    }
+}
+
+/// Bilinear sample of a tiled distance field at fractional cell coordinates,
+/// missing tiles read as `f32::MAX`, same as texture padding.
+pub fn sample_distance(
+    tiles: &HashMap<(i32, i32), Vec<f32>>,
+    tile_size: i32,
+    cell_pos: Vec2,
+) -> f32 {
+    let pos = cell_pos - Vec2::splat(0.5);
+    let base = pos.floor();
+    let t = pos - base;
+    let base = base.as_ivec2();
+    let fetch = |x: i32, y: i32| -> f32 {
+        let key = (x.div_euclid(tile_size), y.div_euclid(tile_size));
+        let tile = some_or!(tiles.get(&key), return f32::MAX);
+        tile[(y.rem_euclid(tile_size) * tile_size + x.rem_euclid(tile_size)) as usize]
+    };
+    let lerp = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
+    let top = lerp(fetch(base.x, base.y), fetch(base.x + 1, base.y), t.x);
+    let bottom = lerp(
+        fetch(base.x, base.y + 1),
+        fetch(base.x + 1, base.y + 1),
+        t.x,
+    );
+    lerp(top, bottom, t.y)
+}
+
+/// Finish material texel for a world position: `texture2D(tex, v_pos / 32.0)` with
+/// 4x4 texture, `TextureWrap::Repeat` and `FilterMode::Nearest`.
+fn finish_texel(world_pos: Vec2) -> [f32; 3] {
+    let texel = (world_pos / 8.0).floor().as_ivec2();
+    let index = texel.y.rem_euclid(4) * 4 + texel.x.rem_euclid(4);
+    let pixel = FINISH_TEXTURE_PIXELS[index as usize];
+    [
+        (pixel & 0xff) as f32 / 255.0,
+        ((pixel >> 8) & 0xff) as f32 / 255.0,
+        ((pixel >> 16) & 0xff) as f32 / 255.0,
+    ]
+}
+
+/// Fragment shader of `create_pipeline_sdf`, returns premultiplied color
+fn sdf_fragment(
+    d: f32,
+    pixel_size: f32,
+    outline_color: [f32; 3],
+    outline_width: f32,
+    fill_color: [f32; 3],
+    tex_color: [f32; 3],
+) -> [f32; 4] {
+    let outline_alpha = 1.0 - ((d - outline_width) / pixel_size).clamp(0.0, 1.0);
+    let fill_alpha = 1.0 - (d / pixel_size).clamp(0.0, 1.0);
+    let mut color = [0.0; 4];
+    for (layer_color, alpha) in [(outline_color, outline_alpha), (fill_color, fill_alpha)] {
+        for c in 0..3 {
+            let above = layer_color[c] * tex_color[c] * alpha;
+            color[c] = (above + color[c] * (1.0 - alpha)).clamp(0.0, 1.0);
+        }
+        color[3] = (alpha + color[3] * (1.0 - alpha)).clamp(0.0, 1.0);
+    }
+    color
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::graphics::SDF_FRAGMENT_SHADER;
+
+    /// Statements of `SDF_FRAGMENT_SHADER` that `sdf_fragment` implements, in order
+    const SHADER_STATEMENTS: [&str; 3] = [
+        "return clamp((above + below * (1.0 - above.a)), vec4(0.0), vec4(1.0));",
+        "color = alpha_over(color, pma(v_color * outline_color * tex_color * vec4(vec3(1.0), 1.0 - clamp((d - outline_width) / pixel_size, 0.0, 1.0))));",
+        "color = alpha_over(color, pma(v_color * fill_color * tex_color * vec4(vec3(1.0), 1.0 - clamp((d) / pixel_size, 0.0, 1.0))));",
+    ];
+
+    /// Statements above evaluated literally, with white vertex color and opaque uniforms
+    fn shader_color(
+        d: f32,
+        pixel_size: f32,
+        outline_color: [f32; 3],
+        outline_width: f32,
+        fill_color: [f32; 3],
+        tex_color: [f32; 3],
+    ) -> [f32; 4] {
+        let pma = |c: [f32; 4]| [c[0] * c[3], c[1] * c[3], c[2] * c[3], c[3]];
+        let alpha_over = |below: [f32; 4], above: [f32; 4]| {
+            let mut result = [0.0; 4];
+            for i in 0..4 {
+                result[i] = (above[i] + below[i] * (1.0 - above[3])).clamp(0.0, 1.0);
+            }
+            result
+        };
+        let layer = |color: [f32; 3], alpha: f32| {
+            pma([
+                color[0] * tex_color[0],
+                color[1] * tex_color[1],
+                color[2] * tex_color[2],
+                alpha,
+            ])
+        };
+        let mut color = [0.0; 4];
+        color = alpha_over(
+            color,
+            layer(
+                outline_color,
+                1.0 - ((d - outline_width) / pixel_size).clamp(0.0, 1.0),
+            ),
+        );
+        color = alpha_over(
+            color,
+            layer(fill_color, 1.0 - (d / pixel_size).clamp(0.0, 1.0)),
+        );
+        color
+    }
+
+    #[test]
+    fn shader_is_unchanged() {
+        let mut last_position = 0;
+        for statement in SHADER_STATEMENTS {
+            let position = SDF_FRAGMENT_SHADER[last_position..]
+                .find(statement)
+                .unwrap_or_else(|| {
+                    panic!("SDF shader has changed, update SdfRasterizer: {statement}")
+                });
+            last_position += position + statement.len();
+        }
+    }
+
+    #[test]
+    fn fragment_matches_shader() {
+        let outline_color = [0.1, 0.2, 0.3];
+        let fill_color = [0.9, 0.6, 0.4];
+        for tex_color in [[1.0; 3], [0.5, 0.75, 1.0]] {
+            for pixel_size in [0.5, 1.0, 2.0] {
+                for outline_width in [0.0, DEFAULT_OUTLINE_WIDTH, 4.0] {
+                    for step in -16..32 {
+                        let d = step as f32 * 0.25;
+                        let expected = shader_color(
+                            d,
+                            pixel_size,
+                            outline_color,
+                            outline_width,
+                            fill_color,
+                            tex_color,
+                        );
+                        let actual = sdf_fragment(
+                            d,
+                            pixel_size,
+                            outline_color,
+                            outline_width,
+                            fill_color,
+                            tex_color,
+                        );
+                        for (a, e) in actual.iter().zip(expected) {
+                            assert!((a - e).abs() < 1e-6, "d {d}: {actual:?} != {expected:?}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn render_matches_shader() {
+        let material = Material {
+            fill_color: [200, 150, 100],
+            outline_color: [20, 40, 60],
+            custom_name: String::new(),
+        };
+        let to_f32 = |c: [u8; 3]| c.map(|c| c as f32 / 255.0);
+        let tile_size = 4;
+        for step in -8..16 {
+            let d = step as f32 * 0.25;
+            // material 1 has no outline width set, default width is used
+            let tile = vec![d; tile_size * tile_size];
+            let field = Field {
+                tile_size,
+                materials: vec![HashMap::new(), HashMap::from([((0, 0), tile)])],
+            };
+            let rasterizer = SdfRasterizer {
+                field: &field,
+                cell_size: 1.0,
+                materials: &[MaterialSlot::None, MaterialSlot::Custom(material.clone())],
+                resolved_materials: &[material.clone(), material.clone()],
+                outline_widths: &[DEFAULT_OUTLINE_WIDTH],
+            };
+            let pixel = rasterizer.render(vec2(1.5, 1.5), IVec2::ONE);
+
+            let color = shader_color(
+                d,
+                1.0,
+                to_f32(material.outline_color),
+                DEFAULT_OUTLINE_WIDTH,
+                to_f32(material.fill_color),
+                [1.0; 3],
+            );
+            // blended over an empty target, then read back as straight alpha
+            let blended = color.map(|c| ((c * color[3]).clamp(0.0, 1.0) * 255.0).round() as i32);
+            let alpha = blended[3];
+            for c in 0..3 {
+                let expected = if alpha != 0 {
+                    ((blended[c] * 255 + alpha / 2) / alpha).min(255)
+                } else {
+                    0
+                };
+                assert_eq!(pixel[c] as i32, expected, "d {d}: {pixel:?}");
+            }
+            assert_eq!(pixel[3] as i32, alpha, "d {d}: {pixel:?}");
+        }
+    }
+}
@@ -21,6 +21,32 @@ pub fn sd_trapezoid(p: Vec2, a: Vec2, b: Vec2, ra: f32, rb: f32) -> f32 {
             .sqrt()
 }
 
+// Inigo Quilez, MIT License
+// Exact for any radii, as long as the circles do not contain each other.
+#[inline]
+pub fn sd_uneven_capsule(p: Vec2, a: Vec2, b: Vec2, ra: f32, rb: f32) -> f32 {
+    let p = p - a;
+    let pb = b - a;
+    let h = pb.dot(pb);
+    let rba = ra - rb;
+    if h <= rba * rba {
+        return (p.length() - ra).min((p - pb).length() - rb);
+    }
+    let mut q = vec2(p.dot(vec2(pb.y, -pb.x)), p.dot(pb)) / h;
+    q.x = q.x.abs();
+    let c = vec2((h - rba * rba).sqrt(), rba);
+    let k = c.perp_dot(q);
+    let m = c.dot(q);
+    let n = q.dot(q);
+    if k < 0.0 {
+        (h * n).sqrt() - ra
+    } else if k > c.x {
+        (h * (n + 1.0 - 2.0 * q.y)).sqrt() - rb
+    } else {
+        m - ra
+    }
+}
+
 // Inigo Quilez, MIT License
 pub fn sd_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
     let pa = p - a;
@@ -52,6 +78,126 @@ pub fn sd_box(p: Vec2, b: Vec2) -> f32 {
     return d.max(Vec2::ZERO).length() + d.x.max(d.y).min(0.0);
 }
 
+// Inigo Quilez, MIT License
+#[inline]
+pub fn sd_rounded_box(p: Vec2, b: Vec2, r: f32) -> f32 {
+    let q = p.abs() - b + Vec2::splat(r);
+    q.x.max(q.y).min(0.0) + q.max(Vec2::ZERO).length() - r
+}
+
+// Inigo Quilez, MIT License
+// `r` is the distance to the flat sides, which are at the top and the bottom.
+#[inline]
+pub fn sd_hexagon(mut p: Vec2, r: f32) -> f32 {
+    let k = [-0.8660254, 0.5, 0.57735026];
+    p = p.abs();
+    p -= 2.0 * vec2(k[0], k[1]).dot(p).min(0.0) * vec2(k[0], k[1]);
+    p -= vec2(p.x.clamp(-k[2] * r, k[2] * r), r);
+    p.length() * p.y.signum()
+}
+
+// Inigo Quilez, MIT License
+// `r` is the distance to the corners, one of which lies on the positive Y axis.
+pub fn sd_regular_polygon(p: Vec2, r: f32, sides: u32) -> f32 {
+    let an = std::f32::consts::PI / sides as f32;
+    let acs = vec2(an.cos(), an.sin());
+    // reduce to the first sector
+    let bn = p.x.atan2(p.y).rem_euclid(2.0 * an) - an;
+    let mut p = p.length() * vec2(bn.cos(), bn.sin().abs());
+    p -= r * acs;
+    p.y += (-p.y).clamp(0.0, r * acs.y);
+    p.length() * p.x.signum()
+}
+
+// Inigo Quilez, MIT License
+// Works for any simple polygon, vertices can go in either direction.
+pub fn sd_polygon(p: Vec2, v: &[Vec2]) -> f32 {
+    let Some(&first) = v.first() else { return f32::MAX };
+    let mut d = (p - first).length_squared();
+    let mut s = 1.0;
+    let mut j = v.len() - 1;
+    for i in 0..v.len() {
+        let e = v[j] - v[i];
+        let w = p - v[i];
+        let b = w - e * (w.dot(e) / e.dot(e)).clamp(0.0, 1.0);
+        d = d.min(b.dot(b));
+        let c = [p.y >= v[i].y, p.y < v[j].y, e.x * w.y > e.y * w.x];
+        if c.iter().all(|&c| c) || c.iter().all(|&c| !c) {
+            s = -s;
+        }
+        j = i;
+    }
+    s * d.sqrt()
+}
+
+// Inigo Quilez, MIT License
+// `ab` are the half-axes of the ellipse.
+pub fn sd_ellipse(p: Vec2, ab: Vec2) -> f32 {
+    let mut p = p.abs();
+    let mut ab = ab;
+    if (ab.x - ab.y).abs() < 1e-3 {
+        return p.length() - ab.x;
+    }
+    if p.x > p.y {
+        p = vec2(p.y, p.x);
+        ab = vec2(ab.y, ab.x);
+    }
+    let l = ab.y * ab.y - ab.x * ab.x;
+    let m = ab.x * p.x / l;
+    let m2 = m * m;
+    let n = ab.y * p.y / l;
+    let n2 = n * n;
+    let c = (m2 + n2 - 1.0) / 3.0;
+    let c3 = c * c * c;
+    let q = c3 + m2 * n2 * 2.0;
+    let d = c3 + m2 * n2;
+    let g = m + m * n2;
+    let co = if d < 0.0 {
+        let h = (q / c3).acos() / 3.0;
+        let s = h.cos();
+        let t = h.sin() * 3.0f32.sqrt();
+        let rx = (-c * (s + t + 2.0) + m2).sqrt();
+        let ry = (-c * (s - t + 2.0) + m2).sqrt();
+        (ry + l.signum() * rx + g.abs() / (rx * ry) - m) / 2.0
+    } else {
+        let h = 2.0 * m * n * d.sqrt();
+        let s = (q + h).signum() * (q + h).abs().powf(1.0 / 3.0);
+        let u = (q - h).signum() * (q - h).abs().powf(1.0 / 3.0);
+        let rx = -s - u - c * 4.0 + 2.0 * m2;
+        let ry = (s - u) * 3.0f32.sqrt();
+        let rm = (rx * rx + ry * ry).sqrt();
+        (ry / (rm - rx).sqrt() + 2.0 * g / rm - m) / 2.0
+    };
+    let co = co.clamp(0.0, 1.0);
+    let r = ab * vec2(co, (1.0 - co * co).sqrt());
+    (r - p).length() * (p.y - r.y).signum()
+}
+
+// Polynomial smooth minimum by Inigo Quilez, MIT License
+// Blends distances within `k` of each other, `k` of zero is the regular minimum.
+#[inline]
+pub fn smooth_min(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (k - (a - b).abs()).max(0.0) / k;
+    a.min(b) - h * h * k * 0.25
+}
+
+#[inline]
+pub fn smooth_max(a: f32, b: f32, k: f32) -> f32 {
+    -smooth_min(-a, -b, k)
+}
+
+/// Share of `b` in `smooth_min(a, b, k)`, from 0 to 1. Used to blend properties of the shapes.
+#[inline]
+pub fn smooth_min_weight(a: f32, b: f32, k: f32) -> f32 {
+    if k <= 0.0 {
+        return if b < a { 1.0 } else { 0.0 };
+    }
+    (0.5 + 0.5 * (a - b) / k).clamp(0.0, 1.0)
+}
+
 pub fn sd_outline(d: f32, half_thickness: f32) -> f32 {
     d.abs() - half_thickness
 }
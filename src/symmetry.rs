@@ -0,0 +1,556 @@
+use std::collections::HashMap;
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+use cbmap::{MapMarkup, MarkupRectKind};
+use glam::{vec2, Affine2, IVec2, Mat2, Vec2};
+use realtime_drawing::{MiniquadBatch, VertexPos3UvColor};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::document::{Document, View};
+use crate::graph::{GraphEdge, GraphEdgeKey, GraphNode, GraphNodeKey, GraphNodeShape};
+use crate::grid::Grid;
+use crate::grid_segment_iterator::GridSegmentIterator;
+use crate::math::Rect;
+use crate::zone::{AnyZone, ZoneRef};
+
+/// Edits are copied onto mirrored counterparts while symmetry is enabled.
+///
+/// Graph nodes are linked with their copies through `GraphNode::symmetry_group`, other
+/// counterparts are found by their mirrored position.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Symmetry {
+    /// Mirrors X coordinate, across the vertical axis going through `pivot`
+    pub mirror_x: bool,
+    /// Mirrors Y coordinate, across the horizontal axis going through `pivot`
+    pub mirror_y: bool,
+    /// Number of rotated copies around `pivot`, values below 2 disable radial symmetry
+    pub radial: u8,
+    pub pivot: IVec2,
+}
+
+impl Symmetry {
+    pub fn is_enabled(&self) -> bool {
+        self.mirror_x || self.mirror_y || self.radial > 1
+    }
+
+    /// Transforms into every counterpart, identity is not included
+    pub fn transforms(&self) -> Vec<Affine2> {
+        let pivot = self.pivot.as_vec2();
+        let count = self.radial.max(1);
+        let mut matrices: Vec<Mat2> = Vec::new();
+        for i in 0..count {
+            let rotation = Mat2::from_angle(i as f32 * TAU / count as f32);
+            for (flip_x, flip_y) in [(false, false), (true, false), (false, true), (true, true)] {
+                if flip_x && !self.mirror_x || flip_y && !self.mirror_y {
+                    continue;
+                }
+                let flip = Mat2::from_diagonal(vec2(
+                    if flip_x { -1.0 } else { 1.0 },
+                    if flip_y { -1.0 } else { 1.0 },
+                ));
+                let matrix = rotation * flip;
+                // e.g. two mirrors are the same as half a turn
+                if !matrices.iter().any(|m| m.abs_diff_eq(&matrix, 1e-4)) {
+                    matrices.push(matrix);
+                }
+            }
+        }
+        matrices
+            .into_iter()
+            .skip(1)
+            .map(|m| {
+                Affine2::from_translation(pivot)
+                    * Affine2::from_mat2(m)
+                    * Affine2::from_translation(-pivot)
+            })
+            .collect()
+    }
+
+    /// Cells that mirror `cell`, found through cell centers
+    pub fn cell_images(&self, cell: IVec2, cell_size: i32) -> Vec<IVec2> {
+        let cell_size = cell_size as f32;
+        let center = (cell.as_vec2() + Vec2::splat(0.5)) * cell_size;
+        self.transforms()
+            .iter()
+            .map(|t| (t.transform_point2(center) / cell_size).floor().as_ivec2())
+            .collect()
+    }
+
+    /// Paints `value` into the cell and its counterparts, growing the grid as needed.
+    /// Returns changed cells.
+    pub fn paint_cell(
+        &self,
+        grid: &mut Grid<u8>,
+        pos: IVec2,
+        value: u8,
+        cell_size: i32,
+    ) -> [IVec2; 2] {
+        let mut changed: [IVec2; 2] = Rect::invalid();
+        for pos in std::iter::once(pos).chain(self.cell_images(pos, cell_size)) {
+            grid.resize_to_include_amortized(Rect::from_point(pos));
+            let index = grid.grid_pos_index(pos.x, pos.y);
+            if grid.cells[index] != value {
+                grid.cells[index] = value;
+                changed = changed.union(Rect::from_point(pos));
+            }
+        }
+        changed
+    }
+
+    /// Paints counterparts of `Grid::rectangle_outline`. Returns bounds of painted cells.
+    pub fn rectangle_outline(
+        &self,
+        grid: &mut Grid<u8>,
+        [min, max]: [IVec2; 2],
+        value: u8,
+        cell_size: i32,
+    ) -> [IVec2; 2] {
+        let mut painted: [IVec2; 2] = Rect::invalid();
+        if max.x <= min.x || max.y <= min.y {
+            return painted;
+        }
+        let cell_size = cell_size as f32;
+        // sides go through cell centers, so that rotated outlines stay connected
+        let corners = [
+            min,
+            IVec2::new(max.x - 1, min.y),
+            max - IVec2::ONE,
+            IVec2::new(min.x, max.y - 1),
+        ]
+        .map(|c| (c.as_vec2() + Vec2::splat(0.5)) * cell_size);
+        for t in self.transforms() {
+            for i in 0..corners.len() {
+                let start = t.transform_point2(corners[i]);
+                let end = t.transform_point2(corners[(i + 1) % corners.len()]);
+                for pos in
+                    GridSegmentIterator::new(start, end, Vec2::ZERO, Vec2::splat(cell_size), 1024)
+                {
+                    grid.resize_to_include_amortized(Rect::from_point(pos));
+                    let index = grid.grid_pos_index(pos.x, pos.y);
+                    grid.cells[index] = value;
+                    painted = painted.union(Rect::from_point(pos));
+                }
+            }
+        }
+        painted
+    }
+
+    pub fn draw(
+        &self,
+        batch: &mut MiniquadBatch<VertexPos3UvColor>,
+        view: &View,
+        window_size: Vec2,
+    ) {
+        let color = [255, 128, 255, 160];
+        let pivot = view
+            .world_to_screen()
+            .transform_point2(self.pivot.as_vec2());
+        if self.mirror_x {
+            batch.geometry.stroke_line_aa(
+                vec2(pivot.x, 0.0),
+                vec2(pivot.x, window_size.y),
+                1.0,
+                color,
+            );
+        }
+        if self.mirror_y {
+            batch.geometry.stroke_line_aa(
+                vec2(0.0, pivot.y),
+                vec2(window_size.x, pivot.y),
+                1.0,
+                color,
+            );
+        }
+        if self.radial > 1 {
+            let reach = window_size.length();
+            for i in 0..self.radial {
+                let angle = i as f32 * TAU / self.radial as f32;
+                let dir = vec2(angle.cos(), angle.sin());
+                batch
+                    .geometry
+                    .stroke_line_aa(pivot, pivot + dir * reach, 1.0, color);
+            }
+        }
+        batch.geometry.stroke_circle_aa(pivot, 8.0, 2.0, 16, color);
+    }
+}
+
+pub fn transform_pos(t: &Affine2, pos: IVec2) -> IVec2 {
+    t.transform_point2(pos.as_vec2()).round().as_ivec2()
+}
+
+pub fn transform_node(t: &Affine2, node: &GraphNode) -> GraphNode {
+    let m = t.matrix2;
+    let mirrored = m.determinant() < 0.0;
+    let mut node = GraphNode {
+        pos: transform_pos(t, node.pos),
+        ..node.clone()
+    };
+    // shapes are symmetric along local X, so a mirror is a flip of X followed by a rotation
+    if mirrored {
+        let angle = (-m.x_axis.y).atan2(-m.x_axis.x);
+        node.rotation = angle - node.rotation;
+        if let GraphNodeShape::Polygon { points } = &mut node.shape {
+            for point in points {
+                point.x = -point.x;
+            }
+        }
+    } else {
+        let angle = m.x_axis.y.atan2(m.x_axis.x);
+        node.rotation += angle;
+    }
+    node.rotation = node.rotation.rem_euclid(TAU);
+    node
+}
+
+pub fn transform_zone(t: &Affine2, zone: &AnyZone) -> AnyZone {
+    let transform = |p: [i32; 2]| transform_pos(t, IVec2::from(p)).into();
+    match *zone {
+        AnyZone::Point(mut p) => {
+            p.pos = transform(p.pos);
+            AnyZone::Point(p)
+        }
+        AnyZone::Rect(mut r) => {
+            let [start, end] = [IVec2::from(r.start), IVec2::from(r.end)];
+            let corners = [
+                start,
+                IVec2::new(end.x, start.y),
+                end,
+                IVec2::new(start.x, end.y),
+            ];
+            let corners = corners.map(|c| transform_pos(t, c));
+            let min = corners
+                .iter()
+                .fold(IVec2::splat(i32::MAX), |a, &c| a.min(c));
+            let max = corners
+                .iter()
+                .fold(IVec2::splat(i32::MIN), |a, &c| a.max(c));
+            r.start = min.into();
+            r.end = max.into();
+            AnyZone::Rect(r)
+        }
+        AnyZone::Segment(mut s) => {
+            s.start = transform(s.start);
+            s.end = transform(s.end);
+            AnyZone::Segment(s)
+        }
+    }
+}
+
+fn zone_equals(a: &AnyZone, b: &AnyZone) -> bool {
+    match (a, b) {
+        (AnyZone::Point(a), AnyZone::Point(b)) => a == b,
+        (AnyZone::Rect(a), AnyZone::Rect(b)) => a == b,
+        (AnyZone::Segment(a), AnyZone::Segment(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn zone_refs(markup: &MapMarkup) -> impl Iterator<Item = ZoneRef> + '_ {
+    (0..markup.points.len())
+        .map(ZoneRef::Point)
+        .chain((0..markup.rects.len()).map(ZoneRef::Rect))
+        .chain((0..markup.segments.len()).map(ZoneRef::Segment))
+}
+
+/// Nodes of each symmetry group, so that counterparts are found without scanning all nodes.
+/// Built once per edit with `Document::symmetry_groups`.
+#[derive(Default)]
+pub struct SymmetryGroups(HashMap<GraphNodeKey, Vec<GraphNodeKey>>);
+
+impl SymmetryGroups {
+    fn insert(&mut self, group: GraphNodeKey, key: GraphNodeKey) {
+        let members = self.0.entry(group).or_default();
+        if !members.contains(&key) {
+            members.push(key);
+        }
+    }
+
+    fn members(&self, group: GraphNodeKey) -> &[GraphNodeKey] {
+        self.0.get(&group).map(Vec::as_slice).unwrap_or(&[])
+    }
+}
+
+impl Document {
+    pub fn symmetry_groups(&self) -> SymmetryGroups {
+        let mut groups = SymmetryGroups::default();
+        for (key, node) in &self.nodes {
+            if let Some(group) = node.symmetry_group {
+                groups.insert(group, key);
+            }
+        }
+        groups
+    }
+
+    /// Node of the same symmetry group at the mirrored position, can be the node itself when
+    /// it lies on the axis
+    pub fn counterpart_node(
+        &self,
+        groups: &SymmetryGroups,
+        key: GraphNodeKey,
+        t: &Affine2,
+    ) -> Option<GraphNodeKey> {
+        let node = self.nodes.get(key)?;
+        let group = node.symmetry_group?;
+        let pos = transform_pos(t, node.pos).as_vec2();
+        groups.members(group).iter().copied().find(|&k| {
+            self.nodes.get(k).is_some_and(|n| {
+                n.symmetry_group == Some(group) && n.pos.as_vec2().distance(pos) <= 1.0
+            })
+        })
+    }
+
+    pub fn counterpart_edge(
+        &self,
+        groups: &SymmetryGroups,
+        key: GraphEdgeKey,
+        t: &Affine2,
+    ) -> Option<GraphEdgeKey> {
+        let edge = self.edges.get(key)?;
+        let start = self.counterpart_node(groups, edge.start, t)?;
+        let end = self.counterpart_node(groups, edge.end, t)?;
+        self.find_edge(start, end)
+    }
+
+    fn find_edge(&self, a: GraphNodeKey, b: GraphNodeKey) -> Option<GraphEdgeKey> {
+        self.edges
+            .iter()
+            .find(|(_, e)| (e.start == a && e.end == b) || (e.start == b && e.end == a))
+            .map(|(k, _)| k)
+    }
+
+    /// Adds missing counterparts of nodes and of their edges, linking them into symmetry
+    /// groups. Returns area of the added shapes.
+    pub fn add_symmetric_nodes(&mut self, keys: &[GraphNodeKey]) -> [Vec2; 2] {
+        let transforms = self.symmetry.transforms();
+        let mut bounds: [Vec2; 2] = Rect::invalid();
+        if transforms.is_empty() || keys.is_empty() {
+            return bounds;
+        }
+        let mut groups = self.symmetry_groups();
+        let mut copies = HashMap::new();
+        for &key in keys {
+            let Some(node) = self.nodes.get_mut(key) else { continue };
+            let group = *node.symmetry_group.get_or_insert(key);
+            groups.insert(group, key);
+            for (index, t) in transforms.iter().enumerate() {
+                let copy = match self.counterpart_node(&groups, key, t) {
+                    Some(copy) => copy,
+                    None => {
+                        let node = GraphNode {
+                            symmetry_group: Some(group),
+                            ..transform_node(t, &self.nodes[key])
+                        };
+                        bounds = bounds.union(node.bounds());
+                        let copy = self.nodes.insert(node);
+                        groups.insert(group, copy);
+                        copy
+                    }
+                };
+                copies.insert((key, index), copy);
+            }
+        }
+
+        let edges: Vec<GraphEdge> = self
+            .edges
+            .values()
+            .filter(|e| keys.contains(&e.start) || keys.contains(&e.end))
+            .cloned()
+            .collect();
+        for edge in edges {
+            for (index, t) in transforms.iter().enumerate() {
+                let map = |k| {
+                    copies
+                        .get(&(k, index))
+                        .copied()
+                        .or_else(|| self.counterpart_node(&groups, k, t))
+                };
+                let (Some(start), Some(end)) = (map(edge.start), map(edge.end)) else { continue };
+                if start == end || self.find_edge(start, end).is_some() {
+                    continue;
+                }
+                let copy = GraphEdge {
+                    start,
+                    end,
+                    control_points: edge
+                        .control_points
+                        .iter()
+                        .map(|&p| transform_pos(t, p))
+                        .collect(),
+                    ..edge.clone()
+                };
+                if let Some(edge_bounds) = copy.bounds(&self.nodes) {
+                    bounds = bounds.union(edge_bounds);
+                }
+                self.edges.insert(copy);
+            }
+        }
+        bounds
+    }
+
+    /// Adds counterparts of linked nodes that were moved off the symmetry axis. Such a node
+    /// was joined with both sides of the axis, so of each pair of mirrored edges the copy
+    /// takes the one that ends closer to it. Returns area of the added shapes.
+    pub fn separate_axis_nodes(&mut self, keys: &[GraphNodeKey]) -> [Vec2; 2] {
+        let mut bounds: [Vec2; 2] = Rect::invalid();
+        if keys.is_empty() {
+            return bounds;
+        }
+        let mut groups = self.symmetry_groups();
+        for &key in keys {
+            for t in &self.symmetry.transforms() {
+                if self.counterpart_node(&groups, key, t).is_some() {
+                    continue;
+                }
+                let Some(node) = self.nodes.get(key) else { continue };
+                let Some(group) = node.symmetry_group else { continue };
+                let node_pos = node.pos.as_vec2();
+                let copy = GraphNode {
+                    symmetry_group: Some(group),
+                    ..transform_node(t, node)
+                };
+                let copy_pos = copy.pos.as_vec2();
+                bounds = bounds.union(copy.bounds());
+                let copy_key = self.nodes.insert(copy);
+                groups.insert(group, copy_key);
+
+                let taken: Vec<GraphEdgeKey> = self
+                    .edges
+                    .iter()
+                    .filter_map(|(edge_key, edge)| {
+                        let other = match (edge.start == key, edge.end == key) {
+                            (true, false) => edge.end,
+                            (false, true) => edge.start,
+                            _ => return None,
+                        };
+                        let other_image = self.counterpart_node(&groups, other, t)?;
+                        if other_image == other {
+                            return None;
+                        }
+                        self.find_edge(key, other_image)?;
+                        let other_pos = self.nodes.get(other)?.pos.as_vec2();
+                        (other_pos.distance(copy_pos) < other_pos.distance(node_pos))
+                            .then_some(edge_key)
+                    })
+                    .collect();
+                for edge_key in taken {
+                    let edge = &mut self.edges[edge_key];
+                    if edge.start == key {
+                        edge.start = copy_key;
+                    } else {
+                        edge.end = copy_key;
+                    }
+                    if let Some(edge_bounds) = self.edges[edge_key].bounds(&self.nodes) {
+                        bounds = bounds.union(edge_bounds);
+                    }
+                }
+            }
+        }
+        bounds.union(self.add_symmetric_nodes(keys))
+    }
+
+    /// Zones at the mirrored positions of `zone`, along with transforms into them
+    pub fn symmetric_zones(&self, zone: ZoneRef) -> Vec<(Affine2, ZoneRef)> {
+        if !zone.is_valid(&self.markup) {
+            return Vec::new();
+        }
+        let value = zone.fetch(&self.markup);
+        let mut result = Vec::new();
+        for t in self.symmetry.transforms() {
+            let image = transform_zone(&t, &value);
+            let counterpart = zone_refs(&self.markup)
+                .filter(|&r| r != zone)
+                .find(|r| zone_equals(&r.fetch(&self.markup), &image));
+            if let Some(counterpart) = counterpart {
+                result.push((t, counterpart));
+            }
+        }
+        result
+    }
+
+    /// Adds missing counterparts of the zone. Race finish is unique and is never copied.
+    pub fn add_symmetric_zone(&mut self, zone: ZoneRef) {
+        if !zone.is_valid(&self.markup) {
+            return;
+        }
+        let value = zone.fetch(&self.markup);
+        if let AnyZone::Rect(rect) = value {
+            if rect.kind == MarkupRectKind::RaceFinish {
+                return;
+            }
+        }
+        for t in self.symmetry.transforms() {
+            let image = transform_zone(&t, &value);
+            if zone_refs(&self.markup).any(|r| zone_equals(&r.fetch(&self.markup), &image)) {
+                continue;
+            }
+            match image {
+                AnyZone::Point(p) => self.markup.points.push(p),
+                AnyZone::Rect(r) => self.markup.rects.push(r),
+                AnyZone::Segment(s) => self.markup.segments.push(s),
+            }
+        }
+    }
+
+    /// Removes the zone along with its counterparts
+    pub fn remove_symmetric_zone(&mut self, zone: ZoneRef) {
+        let mut removed = vec![zone];
+        for (_, counterpart) in self.symmetric_zones(zone) {
+            if !removed.contains(&counterpart) {
+                removed.push(counterpart);
+            }
+        }
+        // later indices first, so that earlier ones stay valid
+        removed.sort_by_key(|r| match *r {
+            ZoneRef::Point(i) | ZoneRef::Rect(i) | ZoneRef::Segment(i) => std::cmp::Reverse(i),
+        });
+        for r in removed {
+            r.remove_zone(&mut self.markup);
+        }
+    }
+
+    /// Turns mirrored copies on the current layer into independent objects. Missing
+    /// counterparts of nodes, cells and zones are added, e.g. of ones that were created
+    /// before symmetry was enabled, and nodes are unlinked from their copies.
+    pub fn bake_symmetry(&mut self) {
+        let layer = self.current_layer;
+        let keys: Vec<GraphNodeKey> = self
+            .nodes
+            .iter()
+            .filter(|(_, n)| n.layer == layer)
+            .map(|(k, _)| k)
+            .collect();
+        self.add_symmetric_nodes(&keys);
+        for node in self.nodes.values_mut().filter(|n| n.layer == layer) {
+            node.symmetry_group = None;
+        }
+
+        let grid_key = Document::get_or_add_layer_grid(&mut self.layers, layer, &mut self.grids);
+        let (symmetry, cell_size) = (self.symmetry, self.cell_size);
+        if let Some(grid) = self.grids.get_mut(grid_key).map(Arc::make_mut) {
+            let filled: Vec<(IVec2, u8)> = (grid.bounds[0].y..grid.bounds[1].y)
+                .flat_map(|y| (grid.bounds[0].x..grid.bounds[1].x).map(move |x| IVec2::new(x, y)))
+                .filter_map(|pos| {
+                    let value = grid.cells[grid.grid_pos_index(pos.x, pos.y)];
+                    (value != 0).then_some((pos, value))
+                })
+                .collect();
+            for (pos, value) in filled {
+                for image in symmetry.cell_images(pos, cell_size) {
+                    grid.resize_to_include_amortized(Rect::from_point(image));
+                    let index = grid.grid_pos_index(image.x, image.y);
+                    // painted cells are not overwritten by copies
+                    if grid.cells[index] == 0 {
+                        grid.cells[index] = value;
+                    }
+                }
+            }
+        }
+
+        let zones: Vec<ZoneRef> = zone_refs(&self.markup).collect();
+        for zone in zones {
+            self.add_symmetric_zone(zone);
+        }
+    }
+}
@@ -1,8 +1,8 @@
-use std::mem::discriminant;
+use std::mem::{discriminant, take};
 use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Context, Result};
-use glam::vec2;
+use glam::{vec2, IVec2};
 use rimui::*;
 
 use cbmap::{
@@ -10,11 +10,19 @@ use cbmap::{
     MarkupSegmentKind, MaterialSlot,
 };
 
-use crate::app::{App, PlayState};
-use crate::document::{ChangeMask, Document, GridKey, Layer, LayerKey, SelectRef, Vec2Ord};
-use crate::graph::{GraphNodeKey, GraphNodeShape};
-use crate::interaction::{action_add_graph_node, action_add_plant};
+use crate::app::{App, NumberDrag, PlayState};
+use crate::document::{ChangeMask, Document, Layer, LayerEntry, LayerKey, SelectRef, Vec2Ord};
+use crate::graph::{GraphEdgeKey, GraphNodeKey, GraphNodeShape, NodeOperation};
+use crate::interaction::{
+    action_add_graph_node, action_add_plant, action_align_selection, action_connect_nodes,
+    action_disconnect_nodes, action_dissolve_nodes, action_distribute_selection,
+    action_equalize_radii, action_subdivide_edges, Alignment, Distribute,
+};
+use crate::lsystem::{LSystem, MAX_ITERATIONS};
+use crate::math::Rect;
 use crate::net_client_connection::{ClientConnection, ConnectionState};
+use crate::plant::{Plant, PlantKey, SurfaceMode};
+use crate::some_or::some_or;
 use crate::tool::Tool;
 use crate::zone::{EditorBounds, ZoneRef};
 use bincode::Options;
@@ -39,6 +47,9 @@ impl App {
             }
             _ => {}
         }
+        if self.show_history {
+            self.ui_history(context);
+        }
 
         self.ui_status_bar(context);
 
@@ -91,6 +102,36 @@ impl App {
             }
         }
 
+        let active_material = self.active_material as usize;
+        let outline_width = self
+            .doc
+            .outline_widths
+            .get(active_material)
+            .copied()
+            .flatten();
+        let h = self.ui.add(rows, hbox());
+        self.ui.add(h, label("Outline").expand(true));
+        let widths = [None, Some(0.0), Some(1.0), Some(2.0), Some(4.0), Some(8.0)];
+        for width in widths {
+            let text = width.map_or("Default".to_owned(), |w| format!("{}", w));
+            if self
+                .ui
+                .add(h, button(&text).down(outline_width == width))
+                .clicked
+            {
+                self.push_undo("Material Outline");
+                let outline_widths = &mut self.doc.outline_widths;
+                if outline_widths.len() <= active_material {
+                    outline_widths.resize(active_material + 1, None);
+                }
+                outline_widths[active_material] = width;
+                while outline_widths.last() == Some(&None) {
+                    outline_widths.pop();
+                }
+                self.graphics.borrow_mut().update_materials(&self.doc);
+            }
+        }
+
         if self
             .ui
             .add(
@@ -108,6 +149,10 @@ impl App {
 
         self.ui.add(rows, separator());
 
+        self.ui_symmetry(rows);
+
+        self.ui.add(rows, separator());
+
         self.ui.add(rows, label("Reference"));
         if self.doc.reference_path.is_some() {
             let show_reference = self.doc.show_reference;
@@ -179,7 +224,7 @@ impl App {
             self.generation_profiler.begin_frame();
             self.graphics.borrow_mut().generate(
                 &self.doc,
-                ChangeMask {
+                &ChangeMask {
                     reference_path: true,
                     ..ChangeMask::default()
                 },
@@ -191,149 +236,397 @@ impl App {
     }
 
     fn ui_layer_list(&mut self, rows: AreaRef) {
+        if self
+            .current_layer_group
+            .is_some_and(|group| !self.doc.layer_groups.contains_key(group))
+        {
+            self.current_layer_group = None;
+        }
+        let current_layer = self.doc.current_layer;
+        let current_entry = match self.current_layer_group {
+            Some(group) => Some(LayerEntry::Group { group }),
+            None => self
+                .doc
+                .layers
+                .contains_key(current_layer)
+                .then_some(LayerEntry::Layer(current_layer)),
+        };
+
         let h = self.ui.add(rows, hbox());
         self.ui.add(h, label("Layers").expand(true));
         let mut new_layer = None;
         if button_drop_down(&mut self.ui, h, "Add", None, Align::Left, true, false, 0).clicked {
-            new_layer = Some(Layer {
-                grid: GridKey::default(),
-                hidden: false,
-            });
+            new_layer = Some(Layer::new());
         }
 
-        let can_remove = self.doc.layers.contains_key(self.doc.current_layer);
-        if self.ui.add(h, button("Delete").enabled(can_remove)).clicked && can_remove {
-            self.push_undo("Remove Layer");
-            let doc = &mut self.doc;
-            let current_layer = doc.current_layer;
-            let current_layer_index = doc.layer_order.iter().position(|l| *l == current_layer);
-            if let Some(current_layer_index) = current_layer_index {
-                doc.layer_order.remove(current_layer_index);
+        if self
+            .ui
+            .add(h, button("Group").enabled(current_entry.is_some()))
+            .clicked
+        {
+            if let Some(entry) = current_entry {
+                self.push_undo("Group Layers");
+                self.current_layer_group = self.doc.group_layer_entry(entry);
             }
-            if let Some(removed) = doc.layers.remove(current_layer) {
-                let mut nodes_to_remove = Vec::new();
-                for (node_key, node) in &doc.nodes {
-                    if node.layer == current_layer {
-                        nodes_to_remove.push(node_key);
+        }
+        tooltip(&mut self.ui, h, "Put into a New Group");
+
+        if let Some(group) = self.current_layer_group {
+            if self.ui.add(h, button("Ungroup")).clicked {
+                self.push_undo("Ungroup Layers");
+                self.doc.ungroup_layers(group);
+                self.current_layer_group = None;
+                self.dirty_mask.all_layers = true;
+            }
+        } else {
+            let can_remove = self.doc.layers.contains_key(current_layer);
+            if self.ui.add(h, button("Delete").enabled(can_remove)).clicked && can_remove {
+                self.push_undo("Remove Layer");
+                let doc = &mut self.doc;
+                let current_layer_index =
+                    doc.flat_layers().iter().position(|l| *l == current_layer);
+                doc.remove_layer_entry(LayerEntry::Layer(current_layer));
+                if let Some(removed) = doc.layers.remove(current_layer) {
+                    let mut nodes_to_remove = Vec::new();
+                    for (node_key, node) in &doc.nodes {
+                        if node.layer == current_layer {
+                            nodes_to_remove.push(node_key);
+                        }
                     }
-                }
-                let mut edges_to_remove = Vec::new();
-                for (edge_key, edge) in &doc.edges {
-                    if nodes_to_remove.contains(&edge.start) || nodes_to_remove.contains(&edge.end)
-                    {
-                        edges_to_remove.push(edge_key);
+                    let mut edges_to_remove = Vec::new();
+                    for (edge_key, edge) in &doc.edges {
+                        if nodes_to_remove.contains(&edge.start)
+                            || nodes_to_remove.contains(&edge.end)
+                        {
+                            edges_to_remove.push(edge_key);
+                        }
                     }
-                }
-                doc.nodes
-                    .retain(|node_key, _| !nodes_to_remove.contains(&node_key));
-                doc.edges
-                    .retain(|edge_key, _| !edges_to_remove.contains(&edge_key));
+                    doc.nodes
+                        .retain(|node_key, _| !nodes_to_remove.contains(&node_key));
+                    doc.edges
+                        .retain(|edge_key, _| !edges_to_remove.contains(&edge_key));
 
-                doc.grids.remove(removed.grid);
+                    doc.grids.remove(removed.grid);
+                }
+                let flat_layers = doc.flat_layers();
+                doc.current_layer = flat_layers
+                    .get(current_layer_index.unwrap_or(flat_layers.len()))
+                    .or(flat_layers.last())
+                    .cloned()
+                    .unwrap_or(LayerKey::default());
+                drop(doc);
+                self.dirty_mask.all_layers = true;
             }
-            doc.current_layer = doc
-                .layer_order
-                .get(current_layer_index.unwrap_or(doc.layer_order.len() - 1))
-                .cloned()
-                .unwrap_or(LayerKey::default());
-            drop(doc);
-            self.dirty_mask.cell_layers = u64::MAX;
         }
 
         if let Some(new_layer) = new_layer {
             self.push_undo("Add Layer");
             let doc = &mut self.doc;
             let new_layer_key = doc.layers.insert(new_layer);
-            doc.layer_order.push(new_layer_key);
+            doc.layers[new_layer_key].name = new_layer_key.label();
+            // new layer goes above the current one
+            let (parent, index) = current_entry
+                .and_then(|entry| doc.find_layer_entry(entry))
+                .map_or((None, doc.layer_order.len()), |(parent, index)| {
+                    (parent, index + 1)
+                });
+            doc.insert_layer_entry(parent, index, LayerEntry::Layer(new_layer_key));
 
             doc.current_layer = new_layer_key;
+            self.current_layer_group = None;
         }
 
-        let current_layer_index = self
-            .doc
-            .layer_order
-            .iter()
-            .position(|l| *l == self.doc.current_layer)
-            .unwrap_or(0);
-        for (i, &layer_key) in self.doc.layer_order.iter().enumerate().rev() {
-            let layer = &mut self.doc.layers[layer_key];
+        let flat_layers = self.doc.flat_layers();
+        let list_rows = layer_list_rows(&self.doc);
+        let mut locked = false;
+        let mut hovered_row = None;
+        let mut pressed_row = None;
+        for (row_index, &(entry, depth)) in list_rows.iter().enumerate() {
             let h = self.ui.add(rows, hbox());
+            let is_drop_target =
+                self.layer_drag.is_some_and(|drag| drag != entry) && self.ui.is_last_hovered(rows);
+            if self.ui.is_last_hovered(rows) {
+                hovered_row = Some(row_index);
+            }
+            let (hidden, is_locked) = match entry {
+                LayerEntry::Layer(layer_key) => {
+                    let layer = &self.doc.layers[layer_key];
+                    (layer.hidden, layer.locked)
+                }
+                LayerEntry::Group { group } => {
+                    let group = &self.doc.layer_groups[group];
+                    (group.hidden, group.locked)
+                }
+            };
             if self
                 .ui
                 .add(
                     h,
                     button(&format!(
                         "{}_vis#{}",
-                        i,
-                        if layer.hidden { "X" } else { " " }
+                        row_index,
+                        if hidden { "X" } else { " " }
                     ))
                     .item(true)
-                    .down(layer.hidden)
+                    .down(hidden)
                     .align(Some(Align::Center))
                     .min_size([16, 0]),
                 )
                 .clicked
             {
-                layer.hidden = !layer.hidden;
-                self.dirty_mask.cell_layers = u64::MAX;
+                match entry {
+                    LayerEntry::Layer(layer_key) => {
+                        self.doc.layers[layer_key].hidden = !hidden;
+                        self.dirty_mask.mark_dirty_layer(layer_key);
+                    }
+                    LayerEntry::Group { group } => {
+                        self.doc.layer_groups[group].hidden = !hidden;
+                        self.dirty_mask.all_layers = true;
+                    }
+                }
             }
             tooltip(
                 &mut self.ui,
                 h,
-                if !layer.hidden {
-                    "Hide Layer"
+                if !hidden { "Hide Layer" } else { "Show Layer" },
+            );
+            if self
+                .ui
+                .add(
+                    h,
+                    button(&format!(
+                        "{}_lock#{}",
+                        row_index,
+                        if is_locked { "L" } else { " " }
+                    ))
+                    .item(true)
+                    .down(is_locked)
+                    .align(Some(Align::Center))
+                    .min_size([16, 0]),
+                )
+                .clicked
+            {
+                self.push_undo(if is_locked {
+                    "Unlock Layer"
+                } else {
+                    "Lock Layer"
+                });
+                match entry {
+                    LayerEntry::Layer(layer_key) => self.doc.layers[layer_key].locked = !is_locked,
+                    LayerEntry::Group { group } => self.doc.layer_groups[group].locked = !is_locked,
+                }
+                locked |= !is_locked;
+            }
+            tooltip(
+                &mut self.ui,
+                h,
+                if !is_locked {
+                    "Lock Layer"
                 } else {
-                    "Show Layer"
+                    "Unlock Layer"
                 },
             );
+            if depth > 0 {
+                self.ui
+                    .add(h, spacer().expand(false).min_size([depth as u16 * 12, 0]));
+            }
+            let (text, down) = match entry {
+                LayerEntry::Layer(layer_key) => {
+                    let index = flat_layers
+                        .iter()
+                        .position(|l| *l == layer_key)
+                        .unwrap_or(0);
+                    let text = format!(
+                        "{}. {}",
+                        index + 1,
+                        self.doc.layers[layer_key].label(layer_key)
+                    );
+                    let down =
+                        self.current_layer_group.is_none() && layer_key == self.doc.current_layer;
+                    (text, down)
+                }
+                LayerEntry::Group { group } => {
+                    let collapsed = self.doc.layer_groups[group].collapsed;
+                    if self
+                        .ui
+                        .add(
+                            h,
+                            button(&format!(
+                                "{}_fold#{}",
+                                row_index,
+                                if collapsed { "+" } else { "-" }
+                            ))
+                            .item(true)
+                            .align(Some(Align::Center))
+                            .min_size([16, 0]),
+                        )
+                        .clicked
+                    {
+                        self.doc.layer_groups[group].collapsed = !collapsed;
+                    }
+                    tooltip(
+                        &mut self.ui,
+                        h,
+                        if collapsed { "Expand" } else { "Collapse" },
+                    );
+                    let text = format!("{}_name#{}", row_index, self.doc.layer_groups[group].name);
+                    (text, self.current_layer_group == Some(group))
+                }
+            };
             if self
                 .ui
                 .add(
                     h,
-                    button(&format!("{}. {}", i + 1, layer_key.label()))
-                        .down(layer_key == self.doc.current_layer)
+                    button(&text)
+                        .down(down || is_drop_target)
                         .align(Some(Align::Left))
                         .expand(true),
                 )
                 .clicked
             {
-                self.doc.current_layer = layer_key;
+                match entry {
+                    LayerEntry::Layer(layer_key) => {
+                        self.doc.current_layer = layer_key;
+                        self.current_layer_group = None;
+                    }
+                    LayerEntry::Group { group } => self.current_layer_group = Some(group),
+                }
+            }
+            if self.ui.hit_item().is_some() && self.ui.hit_item() == self.ui.last_item(h) {
+                pressed_row = Some(row_index);
+            }
+        }
+
+        // entries are dragged by their names
+        if self.ui.is_mouse_released(1) {
+            let drag = self.layer_drag.take();
+            let drag_row = drag.and_then(|drag| list_rows.iter().position(|(e, _)| *e == drag));
+            if let (Some(drag), Some(drag_row), Some(drop_row)) = (drag, drag_row, hovered_row) {
+                let (target, _) = list_rows[drop_row];
+                if drop_row != drag_row {
+                    self.push_undo("Move Layer");
+                    match target {
+                        LayerEntry::Group { group } => {
+                            let index = self.doc.layer_groups[group].children.len();
+                            self.doc.move_layer_entry(drag, Some(group), index);
+                            self.doc.layer_groups[group].collapsed = false;
+                        }
+                        LayerEntry::Layer(_) => {
+                            if let Some((parent, index)) = self.doc.find_layer_entry(target) {
+                                // rows are listed from top to bottom
+                                let index = if drop_row < drag_row {
+                                    index + 1
+                                } else {
+                                    index
+                                };
+                                self.doc.move_layer_entry(drag, parent, index);
+                            }
+                        }
+                    }
+                    self.dirty_mask.all_layers = true;
+                }
+            }
+        } else if let Some(pressed_row) = pressed_row {
+            if self.layer_drag.is_none() {
+                self.layer_drag = Some(list_rows[pressed_row].0);
+            }
+        } else {
+            self.layer_drag = None;
+        }
+
+        if locked {
+            // elements of locked layers can not stay selected
+            let selected = take(&mut self.doc.selected);
+            self.doc.selected = selected
+                .into_iter()
+                .filter(|s| self.doc.is_selectable(*s))
+                .collect();
+        }
+
+        if let Some(group) = self.current_layer_group {
+            let mut name = self.doc.layer_groups[group].name.clone();
+            let h = self.ui.add(rows, hbox());
+            self.ui.add(h, label("Name"));
+            if self.ui.add(h, edit("layer_name", &mut name).expand(true)) {
+                self.push_undo("Group Name");
+                self.doc.layer_groups[group].name = name;
+            }
+        } else if let Some(layer) = self.doc.layers.get(current_layer) {
+            let mut name = layer.name.clone();
+            let current_opacity = layer.opacity;
+
+            let h = self.ui.add(rows, hbox());
+            self.ui.add(h, label("Name"));
+            if self.ui.add(h, edit("layer_name", &mut name).expand(true)) {
+                self.push_undo("Layer Name");
+                self.doc.layers[current_layer].name = name;
+            }
+
+            let h = self.ui.add(rows, hbox());
+            self.ui.add(h, label("Opacity, %").expand(true));
+            let percent = (current_opacity * 100.0).round();
+            if let Some(percent) = drag_number(
+                &mut self.ui,
+                &mut self.number_drag,
+                h,
+                "layer_opacity",
+                percent,
+                1.0,
+                [0.0, 100.0],
+            ) {
+                self.push_undo("Layer Opacity");
+                self.doc.layers[current_layer].opacity = percent / 100.0;
+                self.dirty_mask.mark_dirty_layer(current_layer);
             }
         }
 
         let h = self.ui.add(rows, hbox());
         self.ui.add(h, spacer());
-        let is_layer_selected = self.doc.layers.contains_key(self.doc.current_layer);
-
-        let mut swap_index = None;
+        let location = current_entry.and_then(|entry| self.doc.find_layer_entry(entry));
+        let (can_move_up, can_move_down) = match location {
+            Some((parent, index)) => (
+                parent.is_some() || index + 1 < self.doc.layer_entries(parent).len(),
+                parent.is_some() || index > 0,
+            ),
+            None => (false, false),
+        };
 
+        let mut move_up = None;
         self.ui.add(h, label("Move"));
-        if self
-            .ui
-            .add(
-                h,
-                button("Up").enabled(
-                    is_layer_selected && current_layer_index + 1 < self.doc.layer_order.len(),
-                ),
-            )
-            .clicked
-        {
-            swap_index = Some(current_layer_index + 1);
+        if self.ui.add(h, button("Up").enabled(can_move_up)).clicked {
+            move_up = Some(true);
         }
         if self
             .ui
-            .add(
-                h,
-                button("Down").enabled(is_layer_selected && current_layer_index > 0),
-            )
+            .add(h, button("Down").enabled(can_move_down))
             .clicked
         {
-            swap_index = Some(current_layer_index - 1);
+            move_up = Some(false);
         }
 
-        if let Some(swap_index) = swap_index {
-            self.doc.layer_order.swap(current_layer_index, swap_index);
-            self.dirty_mask.cell_layers = u64::MAX;
+        if let (Some(move_up), Some(entry), Some((parent, index))) =
+            (move_up, current_entry, location)
+        {
+            let len = self.doc.layer_entries(parent).len();
+            let grand_parent = parent.and_then(|parent| {
+                self.doc
+                    .find_layer_entry(LayerEntry::Group { group: parent })
+            });
+            // entries leave their group at its ends
+            let target = match (move_up, grand_parent) {
+                (true, _) if index + 1 < len => Some((parent, index + 2)),
+                (false, _) if index > 0 => Some((parent, index - 1)),
+                (true, Some((grand_parent, parent_index))) => {
+                    Some((grand_parent, parent_index + 1))
+                }
+                (false, Some((grand_parent, parent_index))) => Some((grand_parent, parent_index)),
+                _ => None,
+            };
+            if let Some((parent, index)) = target {
+                self.push_undo("Move Layer");
+                self.doc.move_layer_entry(entry, parent, index);
+                self.dirty_mask.all_layers = true;
+            }
         }
     }
 
@@ -421,6 +714,8 @@ impl App {
                         kind: MarkupPointKind::Start,
                         pos: center,
                     });
+                    let added = ZoneRef::Point(self.doc.markup.points.len() - 1);
+                    self.doc.add_symmetric_zone(added);
                 }
                 tooltip(&mut self.ui, p, MarkupPointKind::Start.tooltip());
             }
@@ -450,6 +745,8 @@ impl App {
                     start: [center[0], center[1] - 128],
                     end: [center[0], center[1] + 128],
                 });
+                let added = ZoneRef::Segment(self.doc.markup.segments.len() - 1);
+                self.doc.add_symmetric_zone(added);
             }
             tooltip(&mut self.ui, p, MarkupSegmentKind::Boost.tooltip());
 
@@ -463,6 +760,8 @@ impl App {
                     start: [center[0], center[1] - 128],
                     end: [center[0], center[1] + 128],
                 });
+                let added = ZoneRef::Segment(self.doc.markup.segments.len() - 1);
+                self.doc.add_symmetric_zone(added);
             }
             tooltip(&mut self.ui, p, MarkupSegmentKind::Bounce.tooltip());
         }
@@ -539,7 +838,7 @@ impl App {
         {
             if let Some(selection) = selection {
                 self.push_undo("Delete Zone");
-                selection.remove_zone(&mut self.doc.markup);
+                self.doc.remove_symmetric_zone(selection);
                 if !selection.is_valid(&self.doc.markup) {
                     self.doc.zone_selection = None;
                 }
@@ -557,6 +856,168 @@ impl App {
         }
     }
 
+    fn ui_history(&mut self, _context: &mut miniquad::Context) {
+        let sidebar_width = 280;
+        let window = self.ui.window(
+            "History",
+            WindowPlacement::Absolute {
+                pos: [
+                    self.window_size[0] as i32 - 24 - sidebar_width,
+                    self.window_size[1] as i32 - 56,
+                ],
+                size: [0, 0],
+                expand: EXPAND_LEFT | EXPAND_UP,
+            },
+            0,
+            0,
+        );
+
+        let frame = self.ui.add(window, Frame::default());
+        let rows = self.ui.add(
+            frame,
+            vbox()
+                .padding(2)
+                .margins([2, 2, 2, 4])
+                .min_size([sidebar_width as u16, 0]),
+        );
+        self.ui.add(rows, label("History"));
+
+        let undo = self.undo.borrow();
+        let redo = self.redo.borrow();
+        let saved_position = *self.undo_saved_position.borrow();
+        let current_position = undo.position();
+
+        // oldest state first, undone records follow the current state
+        let first_position = undo.first_position();
+        let mut entries = vec![(
+            first_position,
+            if first_position == 0 {
+                "Initial State"
+            } else {
+                "Oldest Available State"
+            },
+        )];
+        entries.extend(
+            undo.records
+                .iter()
+                .chain(redo.records.iter().rev())
+                .enumerate()
+                .map(|(i, record)| (first_position + i + 1, record.text.as_str())),
+        );
+
+        let scroll = self.ui.add(
+            rows,
+            scroll_area("history_scroll")
+                .min_size([sidebar_width as u16, 0])
+                .max_size([sidebar_width as u16, 320]),
+        );
+        let list = self.ui.add(scroll, vbox());
+        let mut jump_position = None;
+        for (position, text) in entries {
+            let text = if position == saved_position {
+                format!("{} (saved)", text)
+            } else {
+                text.to_owned()
+            };
+            if self
+                .ui
+                .add(
+                    list,
+                    button(&text).item(true).down(position == current_position),
+                )
+                .clicked
+            {
+                jump_position = Some(position);
+            }
+        }
+        drop(undo);
+        drop(redo);
+
+        if let Some(position) = jump_position {
+            self.jump_to_undo_position(position);
+        }
+    }
+
+    fn ui_symmetry(&mut self, rows: AreaRef) {
+        self.ui.add(rows, label("Symmetry"));
+        let symmetry = self.doc.symmetry;
+        let mut new_symmetry = symmetry;
+
+        let h = self.ui.add(rows, hbox());
+        if self
+            .ui
+            .add(h, button("Mirror X").down(symmetry.mirror_x))
+            .clicked
+        {
+            new_symmetry.mirror_x = !symmetry.mirror_x;
+        }
+        if self
+            .ui
+            .add(h, button("Mirror Y").down(symmetry.mirror_y))
+            .clicked
+        {
+            new_symmetry.mirror_y = !symmetry.mirror_y;
+        }
+
+        let h = self.ui.add(rows, hbox());
+        self.ui.add(h, label("Radial").expand(true));
+        for count in [0, 2, 3, 4, 6, 8] {
+            let text = if count == 0 {
+                "Off".to_owned()
+            } else {
+                format!("{}", count)
+            };
+            let down = if count == 0 {
+                symmetry.radial < 2
+            } else {
+                symmetry.radial == count
+            };
+            if self.ui.add(h, button(&text).down(down)).clicked {
+                new_symmetry.radial = count;
+            }
+        }
+
+        let h = self.ui.add(rows, hbox());
+        self.ui.add(h, label("Pivot").expand(true));
+        if self.ui.add(h, button("Origin")).clicked {
+            new_symmetry.pivot = IVec2::ZERO;
+        }
+        let has_selection = !self.doc.selected.is_empty();
+        if self
+            .ui
+            .add(h, button("Selection").enabled(has_selection))
+            .clicked
+            && has_selection
+        {
+            let [min, max] = self.doc.selected_bounds();
+            new_symmetry.pivot =
+                Document::snap_to_grid((min + max) * 0.5, self.doc.cell_size).as_ivec2();
+        }
+
+        let can_bake = symmetry.is_enabled() && !self.doc.is_layer_locked(self.doc.current_layer);
+        if self
+            .ui
+            .add(rows, button("Bake Symmetry").enabled(can_bake))
+            .clicked
+            && can_bake
+        {
+            self.push_undo("Bake Symmetry");
+            self.doc.bake_symmetry();
+            self.dirty_mask.mark_dirty_layer(self.doc.current_layer);
+        }
+        tooltip(
+            &mut self.ui,
+            rows,
+            "Adds missing mirrored copies of nodes, cells and zones of the current layer and \
+             unlinks nodes from their copies.",
+        );
+
+        if new_symmetry != symmetry {
+            self.push_undo("Symmetry");
+            self.doc.symmetry = new_symmetry;
+        }
+    }
+
     fn ui_select_panel(&mut self, _context: &mut miniquad::Context) {
         let sidebar_width = 280;
         let zone_window = self.ui.window(
@@ -592,7 +1053,9 @@ impl App {
 
             let selected_nodes = || {
                 self.doc.selected.iter().filter_map(|n| match *n {
-                    SelectRef::Node(key) | SelectRef::NodeRadius(key) => Some(key),
+                    SelectRef::Node(key)
+                    | SelectRef::NodeRadius(key)
+                    | SelectRef::NodeVertex(key, _) => Some(key),
                     _ => None,
                 })
             };
@@ -647,9 +1110,15 @@ impl App {
                 let thickness = first_node.as_ref().map(|n| n.thickness).unwrap_or(0);
                 for i in 0..=4 {
                     let t = i * cell_size as i32;
+                    // zero uses the default thickness
+                    let text = if t == 0 {
+                        "Default".to_owned()
+                    } else {
+                        format!("{}", t)
+                    };
                     if self
                         .ui
-                        .add(h, button(&format!("{}", t)).down(t == thickness as i32))
+                        .add(h, button(&text).down(t == thickness as i32))
                         .clicked
                     {
                         let selected_nodes: Vec<GraphNodeKey> = selected_nodes().collect();
@@ -665,31 +1134,162 @@ impl App {
                     }
                 }
 
-                let h = self.ui.add(rows, hbox());
-                self.ui.add(h, label("Shape").expand(true));
-                let shapes = [
-                    ("Square", GraphNodeShape::Square),
-                    ("Octogon", GraphNodeShape::Octogon),
-                    ("Circle", GraphNodeShape::Circle),
+                let shape_rows: [&[(&str, GraphNodeShape)]; 2] = [
+                    &[
+                        ("Square", GraphNodeShape::Square),
+                        ("Octogon", GraphNodeShape::Octogon),
+                        ("Circle", GraphNodeShape::Circle),
+                        ("Hexagon", GraphNodeShape::Hexagon),
+                        ("Ellipse", GraphNodeShape::Ellipse { aspect: 0.5 }),
+                    ],
+                    &[
+                        ("Rectangle", GraphNodeShape::Rectangle { aspect: 0.5 }),
+                        (
+                            "Rounded",
+                            GraphNodeShape::RoundedBox {
+                                aspect: 0.5,
+                                rounding: 0.5,
+                            },
+                        ),
+                        ("N-gon", GraphNodeShape::RegularPolygon { sides: 5 }),
+                        ("Polygon", GraphNodeShape::Polygon { points: Vec::new() }),
+                    ],
                 ];
-                for (label, shape) in shapes {
+                for (row, shapes) in shape_rows.into_iter().enumerate() {
+                    let h = self.ui.add(rows, hbox());
+                    self.ui
+                        .add(h, label(if row == 0 { "Shape" } else { "" }).expand(true));
+                    for (label, shape) in shapes.iter().cloned() {
+                        if self
+                            .ui
+                            .add(
+                                h,
+                                button(label).down(selected_nodes().any(|k| {
+                                    self.doc.nodes.get(k).map(|n| discriminant(&n.shape))
+                                        == Some(discriminant(&shape))
+                                })),
+                            )
+                            .clicked
+                        {
+                            let selected_nodes: Vec<_> = selected_nodes().collect();
+                            change = Some(Box::new(move |app: &mut App| {
+                                app.push_undo("Node Shape");
+                                for &key in &selected_nodes {
+                                    let node = &mut app.doc.nodes[key];
+                                    // parameters of the shape are kept
+                                    if discriminant(&node.shape) == discriminant(&shape) {
+                                        continue;
+                                    }
+                                    node.shape = match shape {
+                                        GraphNodeShape::Polygon { .. } => node.shape.to_polygon(),
+                                        _ => shape.clone(),
+                                    };
+                                }
+                            }));
+                        }
+                    }
+                }
+
+                let first_shape = first_node.as_ref().map(|n| n.shape.clone());
+                let first_aspect = match first_shape {
+                    Some(
+                        GraphNodeShape::Rectangle { aspect }
+                        | GraphNodeShape::RoundedBox { aspect, .. }
+                        | GraphNodeShape::Ellipse { aspect },
+                    ) => Some(aspect),
+                    _ => None,
+                };
+                if let Some(first_aspect) = first_aspect {
+                    let h = self.ui.add(rows, hbox());
+                    self.ui.add(h, label("Aspect").expand(true));
+                    for value in [0.25, 0.5, 0.75, 1.0, 1.5, 2.0] {
+                        if self
+                            .ui
+                            .add(h, button(&format!("{}", value)).down(first_aspect == value))
+                            .clicked
+                        {
+                            let selected_nodes: Vec<_> = selected_nodes().collect();
+                            change = Some(Box::new(move |app: &mut App| {
+                                app.push_undo("Node Aspect");
+                                for &key in &selected_nodes {
+                                    match &mut app.doc.nodes[key].shape {
+                                        GraphNodeShape::Rectangle { aspect }
+                                        | GraphNodeShape::RoundedBox { aspect, .. }
+                                        | GraphNodeShape::Ellipse { aspect } => *aspect = value,
+                                        _ => {}
+                                    }
+                                }
+                            }));
+                        }
+                    }
+                }
+
+                if let Some(GraphNodeShape::RoundedBox { rounding, .. }) = first_shape {
+                    let h = self.ui.add(rows, hbox());
+                    self.ui.add(h, label("Rounding").expand(true));
+                    for value in [0.1, 0.25, 0.5, 0.75, 1.0] {
+                        if self
+                            .ui
+                            .add(h, button(&format!("{}", value)).down(rounding == value))
+                            .clicked
+                        {
+                            let selected_nodes: Vec<_> = selected_nodes().collect();
+                            change = Some(Box::new(move |app: &mut App| {
+                                app.push_undo("Node Rounding");
+                                for &key in &selected_nodes {
+                                    if let GraphNodeShape::RoundedBox { rounding, .. } =
+                                        &mut app.doc.nodes[key].shape
+                                    {
+                                        *rounding = value;
+                                    }
+                                }
+                            }));
+                        }
+                    }
+                }
+
+                if let Some(GraphNodeShape::RegularPolygon { sides }) = first_shape {
+                    let h = self.ui.add(rows, hbox());
+                    self.ui.add(h, label("Sides").expand(true));
+                    for value in 3..=8 {
+                        if self
+                            .ui
+                            .add(h, button(&format!("{}", value)).down(sides == value))
+                            .clicked
+                        {
+                            let selected_nodes: Vec<_> = selected_nodes().collect();
+                            change = Some(Box::new(move |app: &mut App| {
+                                app.push_undo("Node Sides");
+                                for &key in &selected_nodes {
+                                    if let GraphNodeShape::RegularPolygon { sides } =
+                                        &mut app.doc.nodes[key].shape
+                                    {
+                                        *sides = value;
+                                    }
+                                }
+                            }));
+                        }
+                    }
+                }
+
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Rotation").expand(true));
+                let rotation = first_node.as_ref().map_or(0.0, |n| n.rotation);
+                for degrees in [0, 15, 30, 45, 60, 90] {
+                    let value = (degrees as f32).to_radians();
                     if self
                         .ui
                         .add(
                             h,
-                            button(label).down(selected_nodes().any(|k| {
-                                self.doc.nodes.get(k).map(|n| discriminant(&n.shape))
-                                    == Some(discriminant(&shape))
-                            })),
+                            button(&format!("{}", degrees)).down((rotation - value).abs() < 1e-4),
                         )
                         .clicked
                     {
                         let selected_nodes: Vec<_> = selected_nodes().collect();
                         change = Some(Box::new(move |app: &mut App| {
-                            app.push_undo("Node Shape");
+                            app.push_undo("Node Rotation");
                             for &key in &selected_nodes {
-                                let node = &mut app.doc.nodes[key];
-                                node.shape = shape;
+                                app.doc.nodes[key].rotation = value;
                             }
                         }));
                     }
@@ -709,27 +1309,659 @@ impl App {
                     }))
                 }
 
-                let no_outline = first_node.as_ref().map(|n| n.no_outline).unwrap_or(false);
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Operation").expand(true));
+                let operation = first_node.as_ref().map(|n| n.operation);
+                let operations = [
+                    ("Union", NodeOperation::Union),
+                    ("Subtract", NodeOperation::Subtract),
+                    ("Intersect", NodeOperation::Intersect),
+                ];
+                for (label, value) in operations {
+                    if self
+                        .ui
+                        .add(h, button(label).down(operation == Some(value)))
+                        .clicked
+                    {
+                        let selected_nodes: Vec<_> = selected_nodes().collect();
+                        change = Some(Box::new(move |app: &mut App| {
+                            app.push_undo("Node Operation");
+                            for &key in &selected_nodes {
+                                let node = &mut app.doc.nodes[key];
+                                // intersection affects the whole layer
+                                if node.operation == NodeOperation::Intersect
+                                    || value == NodeOperation::Intersect
+                                {
+                                    app.dirty_mask.mark_dirty_layer(node.layer);
+                                }
+                                node.operation = value;
+                            }
+                        }));
+                    }
+                }
+
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Blend").expand(true));
+                let blend = first_node.as_ref().map_or(0, |n| n.blend);
+                for i in [0, 1, 2, 4, 8] {
+                    let value = i * cell_size as usize;
+                    if self
+                        .ui
+                        .add(h, button(&format!("{}", value)).down(value == blend))
+                        .clicked
+                    {
+                        let selected_nodes: Vec<_> = selected_nodes().collect();
+                        change = Some(Box::new(move |app: &mut App| {
+                            app.push_undo("Node Blend");
+                            for &key in &selected_nodes {
+                                app.doc.nodes[key].blend = value;
+                            }
+                        }));
+                    }
+                }
+            }
+
+            let selected_plants: Vec<PlantKey> = self
+                .doc
+                .selected
+                .iter()
+                .filter_map(|s| match *s {
+                    SelectRef::Plant(key) | SelectRef::PlantDirection(key) => Some(key),
+                    _ => None,
+                })
+                .collect();
+            if let Some(first_plant) = selected_plants
+                .first()
+                .and_then(|&k| self.doc.plants.get(k))
+                .cloned()
+            {
+                self.ui.add(rows, label("Plant").expand(true));
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Material").expand(true));
+                let mut material = first_plant.material;
+                if material_drop_down(&mut self.ui, h, &mut material, &self.doc.materials) {
+                    let selected_plants = selected_plants.clone();
+                    change = Some(Box::new(move |app| {
+                        app.push_undo("Plant: Material");
+                        for &key in &selected_plants {
+                            let Some(plant) = app.doc.plants.get_mut(key) else { continue };
+                            plant.material = material;
+                        }
+                    }));
+                }
+
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Growth").expand(true));
                 if self
                     .ui
-                    .add(rows, button("No Outline").down(no_outline))
+                    .add(h, button("Branching").down(first_plant.lsystem.is_none()))
                     .clicked
                 {
-                    let selected_nodes: Vec<_> = selected_nodes().collect();
+                    let selected_plants = selected_plants.clone();
                     change = Some(Box::new(move |app| {
-                        app.push_undo("Node: No Outline");
-                        for &key in &selected_nodes {
-                            let node = &mut app.doc.nodes[key];
-                            node.no_outline = !no_outline;
+                        app.push_undo("Plant: Growth");
+                        for &key in &selected_plants {
+                            let Some(plant) = app.doc.plants.get_mut(key) else { continue };
+                            plant.lsystem = None;
+                        }
+                    }));
+                }
+                for (name, preset) in LSystem::presets() {
+                    let down = first_plant.lsystem.as_ref() == Some(&preset);
+                    if self.ui.add(h, button(name).down(down)).clicked {
+                        let selected_plants = selected_plants.clone();
+                        change = Some(Box::new(move |app| {
+                            app.push_undo("Plant: Growth");
+                            for &key in &selected_plants {
+                                let Some(plant) = app.doc.plants.get_mut(key) else { continue };
+                                plant.lsystem = Some(preset.clone());
+                            }
+                        }));
+                    }
+                }
+
+                type PlantField = (
+                    &'static str,
+                    fn(&Plant) -> f32,
+                    fn(&mut Plant, f32),
+                    f32,
+                    f32,
+                    f32,
+                );
+                // label, getter, setter, change per pixel, minimum and maximum
+                let fields: [PlantField; 12] = [
+                    (
+                        "Thickness",
+                        |p| p.thickness,
+                        |p, v| p.thickness = v,
+                        0.25,
+                        1.0,
+                        64.0,
+                    ),
+                    (
+                        "Segment Length",
+                        |p| p.segment_length,
+                        |p, v| p.segment_length = v,
+                        0.25,
+                        1.0,
+                        64.0,
+                    ),
+                    (
+                        "Branch Period",
+                        |p| p.branch_period,
+                        |p, v| p.branch_period = v,
+                        1.0,
+                        4.0,
+                        2048.0,
+                    ),
+                    (
+                        "Max Length",
+                        |p| p.max_length,
+                        |p, v| p.max_length = v,
+                        2.0,
+                        8.0,
+                        4096.0,
+                    ),
+                    (
+                        "Seed",
+                        |p| p.seed as f32,
+                        |p, v| p.seed = v as u32,
+                        1.0,
+                        0.0,
+                        65535.0,
+                    ),
+                    (
+                        "Bend",
+                        |p| p.bend.to_degrees(),
+                        |p, v| p.bend = v.to_radians(),
+                        0.05,
+                        -45.0,
+                        45.0,
+                    ),
+                    (
+                        "Bend Jitter",
+                        |p| p.bend_jitter.to_degrees(),
+                        |p, v| p.bend_jitter = v.to_radians(),
+                        0.05,
+                        0.0,
+                        45.0,
+                    ),
+                    (
+                        "Branch Angle",
+                        |p| p.branch_angle.to_degrees(),
+                        |p, v| p.branch_angle = v.to_radians(),
+                        0.5,
+                        0.0,
+                        180.0,
+                    ),
+                    (
+                        "Angle Jitter",
+                        |p| p.branch_angle_jitter.to_degrees(),
+                        |p, v| p.branch_angle_jitter = v.to_radians(),
+                        0.5,
+                        0.0,
+                        90.0,
+                    ),
+                    ("Taper", |p| p.taper, |p, v| p.taper = v, 0.01, 0.0, 1.0),
+                    (
+                        "Branch Decay",
+                        |p| p.branch_decay,
+                        |p, v| p.branch_decay = v,
+                        0.01,
+                        0.0,
+                        1.0,
+                    ),
+                    (
+                        "Max Depth",
+                        |p| p.max_depth as f32,
+                        |p, v| p.max_depth = v as u32,
+                        0.1,
+                        0.0,
+                        16.0,
+                    ),
+                ];
+                let lsystem_fields: [PlantField; 3] = [
+                    (
+                        "Angle",
+                        |p| p.lsystem.as_ref().map_or(0.0, |l| l.angle.to_degrees()),
+                        |p, v| {
+                            if let Some(l) = &mut p.lsystem {
+                                l.angle = v.to_radians();
+                            }
+                        },
+                        0.5,
+                        0.0,
+                        180.0,
+                    ),
+                    (
+                        "Step Length",
+                        |p| p.lsystem.as_ref().map_or(0.0, |l| l.step_length),
+                        |p, v| {
+                            if let Some(l) = &mut p.lsystem {
+                                l.step_length = v;
+                            }
+                        },
+                        0.25,
+                        1.0,
+                        64.0,
+                    ),
+                    (
+                        "Iterations",
+                        |p| p.lsystem.as_ref().map_or(0.0, |l| l.iterations as f32),
+                        |p, v| {
+                            if let Some(l) = &mut p.lsystem {
+                                l.iterations = v as u32;
+                            }
+                        },
+                        0.1,
+                        0.0,
+                        MAX_ITERATIONS as f32,
+                    ),
+                ];
+                // the rest only applies to branching
+                let lsystem_shared = [
+                    "Thickness",
+                    "Max Length",
+                    "Seed",
+                    "Bend Jitter",
+                    "Angle Jitter",
+                    "Taper",
+                ];
+                let uses_lsystem = first_plant.lsystem.is_some();
+                let fields = fields
+                    .into_iter()
+                    .filter(|f| !uses_lsystem || lsystem_shared.contains(&f.0))
+                    .chain(lsystem_fields.into_iter().filter(|_| uses_lsystem));
+                for (name, get, set, step, min, max) in fields {
+                    let h = self.ui.add(rows, hbox());
+                    self.ui.add(h, label(name).expand(true));
+                    let value = get(&first_plant);
+                    // dragging regenerates the map, showing new growth right away
+                    let Some(value) = drag_number(
+                        &mut self.ui,
+                        &mut self.number_drag,
+                        h,
+                        name,
+                        value,
+                        step,
+                        [min, max],
+                    ) else {
+                        continue;
+                    };
+                    let selected_plants = selected_plants.clone();
+                    change = Some(Box::new(move |app| {
+                        // integer fields change only every few pixels
+                        let changed = selected_plants.iter().any(|&key| {
+                            app.doc.plants.get(key).is_some_and(|plant| {
+                                let mut changed = plant.clone();
+                                set(&mut changed, value);
+                                changed != *plant
+                            })
+                        });
+                        if !changed {
+                            return;
+                        }
+                        // consecutive changes of a drag are merged into one undo record
+                        app.push_undo(&format!("Plant: {}", name));
+                        for &key in &selected_plants {
+                            let Some(plant) = app.doc.plants.get_mut(key) else { continue };
+                            set(plant, value);
+                        }
+                    }));
+                }
+
+                if let Some(lsystem) = &first_plant.lsystem {
+                    let mut edited = None;
+                    let h = self.ui.add(rows, hbox());
+                    self.ui.add(h, label("Axiom"));
+                    let mut axiom = lsystem.axiom.clone();
+                    if self
+                        .ui
+                        .add(h, edit("lsystem_axiom", &mut axiom).expand(true))
+                    {
+                        edited = Some(LSystem {
+                            axiom,
+                            ..lsystem.clone()
+                        });
+                    }
+                    for (index, rule) in lsystem.rules.iter().enumerate() {
+                        let h = self.ui.add(rows, hbox());
+                        self.ui.add(h, label(&format!("Rule {}", index + 1)));
+                        let mut rule = rule.clone();
+                        let id = format!("lsystem_rule{}", index);
+                        if self.ui.add(h, edit(&id, &mut rule).expand(true)) {
+                            let mut rules = lsystem.rules.clone();
+                            rules[index] = rule;
+                            edited = Some(LSystem {
+                                rules,
+                                ..lsystem.clone()
+                            });
+                        }
+                        if self.ui.add(h, button("X").min_size([16, 0])).clicked {
+                            let mut rules = lsystem.rules.clone();
+                            rules.remove(index);
+                            edited = Some(LSystem {
+                                rules,
+                                ..lsystem.clone()
+                            });
+                        }
+                    }
+                    if self.ui.add(rows, button("Add Rule")).clicked {
+                        let mut rules = lsystem.rules.clone();
+                        rules.push(String::new());
+                        edited = Some(LSystem {
+                            rules,
+                            ..lsystem.clone()
+                        });
+                    }
+                    let error = match &self.lsystem_error {
+                        Some((validated, error)) if validated == lsystem => error.clone(),
+                        _ => {
+                            let error = lsystem.validate().err().map(|e| format!("{:#}", e));
+                            self.lsystem_error = Some((lsystem.clone(), error.clone()));
+                            error
+                        }
+                    };
+                    if let Some(error) = error {
+                        self.ui.add(
+                            rows,
+                            wrapped_text("lsystem_error", &error)
+                                .max_width(sidebar_width as u16)
+                                .color(Some([255, 128, 128, 255])),
+                        );
+                    }
+                    if let Some(edited) = edited {
+                        let selected_plants = selected_plants.clone();
+                        change = Some(Box::new(move |app| {
+                            app.push_undo("Plant: Grammar");
+                            for &key in &selected_plants {
+                                let Some(plant) = app.doc.plants.get_mut(key) else { continue };
+                                plant.lsystem = Some(edited.clone());
+                            }
+                        }));
+                    }
+                }
+
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Surface").expand(true));
+                let modes = [
+                    ("Ignore", SurfaceMode::Ignore),
+                    ("Cling", SurfaceMode::Cling),
+                    ("Stop", SurfaceMode::StopAtCollision),
+                ];
+                for (label, mode) in modes {
+                    if self
+                        .ui
+                        .add(h, button(label).down(first_plant.surface == mode))
+                        .clicked
+                    {
+                        let selected_plants = selected_plants.clone();
+                        change = Some(Box::new(move |app| {
+                            app.push_undo("Plant: Surface");
+                            for &key in &selected_plants {
+                                let Some(plant) = app.doc.plants.get_mut(key) else { continue };
+                                plant.surface = mode;
+                            }
+                        }));
+                    }
+                }
+
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Avoid").expand(true));
+                // first material slot stands for none
+                let mut avoid = first_plant.avoid_material.unwrap_or(0);
+                if material_drop_down(&mut self.ui, h, &mut avoid, &self.doc.materials) {
+                    let selected_plants = selected_plants.clone();
+                    change = Some(Box::new(move |app| {
+                        app.push_undo("Plant: Avoid");
+                        for &key in &selected_plants {
+                            let Some(plant) = app.doc.plants.get_mut(key) else { continue };
+                            plant.avoid_material = (avoid != 0).then_some(avoid);
                         }
                     }));
                 }
             }
+
+            let node_count = selected_nodes().count();
+            let has_edges = self.doc.selected.iter().any(|s| {
+                matches!(
+                    s,
+                    SelectRef::Edge(_) | SelectRef::EdgePoint(_, _) | SelectRef::EdgeControl(_, _)
+                )
+            });
+            if node_count > 0 || has_edges {
+                let h = self.ui.add(rows, hbox());
+                if self
+                    .ui
+                    .add(h, button("Connect").enabled(node_count > 1))
+                    .clicked
+                    && node_count > 1
+                {
+                    change = Some(Box::new(action_connect_nodes));
+                }
+                if self.ui.add(h, button("Disconnect")).clicked {
+                    change = Some(Box::new(action_disconnect_nodes));
+                }
+                if self
+                    .ui
+                    .add(h, button("Dissolve").enabled(node_count > 0))
+                    .clicked
+                    && node_count > 0
+                {
+                    change = Some(Box::new(action_dissolve_nodes));
+                }
+            }
+
+            let layout_count = self
+                .doc
+                .selected
+                .iter()
+                .filter(|s| matches!(s, SelectRef::Node(_) | SelectRef::Plant(_)))
+                .count();
+            if layout_count > 1 {
+                self.ui.add(rows, label("Layout").expand(true));
+                let aligns = [
+                    [
+                        ("Left", Alignment::Left),
+                        ("Center", Alignment::CenterX),
+                        ("Right", Alignment::Right),
+                    ],
+                    [
+                        ("Top", Alignment::Top),
+                        ("Middle", Alignment::CenterY),
+                        ("Bottom", Alignment::Bottom),
+                    ],
+                ];
+                for row in aligns {
+                    let h = self.ui.add(rows, hbox());
+                    self.ui.add(h, label("Align").expand(true));
+                    for (label, align) in row {
+                        if self.ui.add(h, button(label)).clicked {
+                            change = Some(Box::new(move |app| action_align_selection(app, align)));
+                        }
+                    }
+                }
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Distribute").expand(true));
+                let distributions = [
+                    ("Horizontal", Distribute::Horizontal),
+                    ("Vertical", Distribute::Vertical),
+                    ("Along Path", Distribute::Path),
+                ];
+                for (label, distribute) in distributions {
+                    if self
+                        .ui
+                        .add(h, button(label).enabled(layout_count > 2))
+                        .clicked
+                        && layout_count > 2
+                    {
+                        change = Some(Box::new(move |app| {
+                            action_distribute_selection(app, distribute)
+                        }));
+                    }
+                }
+                if node_count > 1 && self.ui.add(rows, button("Equalize Radii")).clicked {
+                    change = Some(Box::new(action_equalize_radii));
+                }
+            }
+
+            let selected_edges = || {
+                self.doc.selected.iter().filter_map(|n| match *n {
+                    SelectRef::Edge(key)
+                    | SelectRef::EdgePoint(key, _)
+                    | SelectRef::EdgeControl(key, _) => Some(key),
+                    _ => None,
+                })
+            };
+
+            if let Some(first_key) = selected_edges().next() {
+                self.ui.add(rows, label("Edge").expand(true));
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Curve").expand(true));
+                let count = self
+                    .doc
+                    .edges
+                    .get(first_key)
+                    .map_or(0, |e| e.control_points.len());
+                let curves = [("Straight", 0), ("Quadratic", 1), ("Cubic", 2)];
+                for (label, new_count) in curves {
+                    if self
+                        .ui
+                        .add(h, button(label).down(count == new_count))
+                        .clicked
+                    {
+                        let selected_edges: Vec<GraphEdgeKey> = selected_edges().collect();
+                        change = Some(Box::new(move |app| {
+                            app.push_undo("Edge Curve");
+                            let doc = &mut app.doc;
+                            for &key in &selected_edges {
+                                let Some(edge) = doc.edges.get_mut(key) else { continue };
+                                edge.set_control_point_count(&doc.nodes, new_count);
+                            }
+                            // removed control points can not stay selected
+                            let edges = &doc.edges;
+                            doc.selected.retain(|s| match *s {
+                                SelectRef::EdgeControl(key, index) => edges
+                                    .get(key)
+                                    .is_some_and(|e| index < e.control_points.len()),
+                                _ => true,
+                            });
+                        }));
+                    }
+                }
+
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Subdivide").expand(true));
+                for count in [1, 2, 3, 4] {
+                    if self.ui.add(h, button(&format!("{}", count))).clicked {
+                        change = Some(Box::new(move |app| action_subdivide_edges(app, count)));
+                    }
+                }
+
+                let first_edge = self.doc.edges.get(first_key).cloned();
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Material").expand(true));
+                let own_material = first_edge.as_ref().and_then(|e| e.material);
+                if self
+                    .ui
+                    .add(h, button("Auto").down(own_material.is_none()))
+                    .clicked
+                {
+                    let selected_edges: Vec<GraphEdgeKey> = selected_edges().collect();
+                    change = Some(Box::new(move |app| {
+                        app.push_undo("Edge: Material");
+                        for &key in &selected_edges {
+                            let Some(edge) = app.doc.edges.get_mut(key) else { continue };
+                            edge.material = None;
+                        }
+                    }));
+                }
+                let mut material = first_edge.as_ref().map_or(0, |e| {
+                    e.material.unwrap_or_else(|| e.material(&self.doc.nodes))
+                });
+                if material_drop_down(&mut self.ui, h, &mut material, &self.doc.materials) {
+                    let selected_edges: Vec<GraphEdgeKey> = selected_edges().collect();
+                    change = Some(Box::new(move |app| {
+                        app.push_undo("Edge: Material");
+                        for &key in &selected_edges {
+                            let Some(edge) = app.doc.edges.get_mut(key) else { continue };
+                            edge.material = Some(material);
+                        }
+                    }));
+                }
+
+                // width profile, radii of the nodes are used by default
+                let radius = first_edge.as_ref().and_then(|e| e.radius);
+                for (index, name) in ["Start Width", "End Width"].into_iter().enumerate() {
+                    let h = self.ui.add(rows, hbox());
+                    self.ui.add(h, label(name).expand(true));
+                    let current = radius.map(|r| r[index]);
+                    let widths = [None, Some(8), Some(16), Some(32), Some(64), Some(128)];
+                    for width in widths {
+                        let text = width.map_or("Node".to_owned(), |w| w.to_string());
+                        if self.ui.add(h, button(&text).down(current == width)).clicked {
+                            let selected_edges: Vec<GraphEdgeKey> = selected_edges().collect();
+                            change = Some(Box::new(move |app| {
+                                app.push_undo("Edge: Width");
+                                let doc = &mut app.doc;
+                                for &key in &selected_edges {
+                                    let Some(edge) = doc.edges.get_mut(key) else { continue };
+                                    let node_radius = |k| doc.nodes.get(k).map_or(0, |n| n.radius);
+                                    let node_radii =
+                                        [node_radius(edge.start), node_radius(edge.end)];
+                                    let mut r = edge.radius.unwrap_or(node_radii);
+                                    r[index] = width.unwrap_or(node_radii[index]);
+                                    edge.radius = (r != node_radii).then_some(r);
+                                }
+                            }));
+                        }
+                    }
+                }
+
+                let h = self.ui.add(rows, hbox());
+                self.ui.add(h, label("Operation").expand(true));
+                let operation = first_edge.as_ref().and_then(|e| e.operation);
+                let operations = [
+                    ("Node", None),
+                    ("Union", Some(NodeOperation::Union)),
+                    ("Subtract", Some(NodeOperation::Subtract)),
+                    ("Intersect", Some(NodeOperation::Intersect)),
+                ];
+                for (label, value) in operations {
+                    if self
+                        .ui
+                        .add(h, button(label).down(operation == value))
+                        .clicked
+                    {
+                        let selected_edges: Vec<GraphEdgeKey> = selected_edges().collect();
+                        change = Some(Box::new(move |app: &mut App| {
+                            app.push_undo("Edge: Operation");
+                            let doc = &mut app.doc;
+                            for &key in &selected_edges {
+                                let Some(edge) = doc.edges.get_mut(key) else { continue };
+                                let old_operation = edge.operation(&doc.nodes);
+                                edge.operation = value;
+                                // intersection affects the whole layer
+                                if old_operation == NodeOperation::Intersect
+                                    || edge.operation(&doc.nodes) == NodeOperation::Intersect
+                                {
+                                    for node in [edge.start, edge.end] {
+                                        let Some(node) = doc.nodes.get(node) else { continue };
+                                        app.dirty_mask.mark_dirty_layer(node.layer);
+                                    }
+                                }
+                            }
+                        }));
+                    }
+                }
+            }
         }
 
         if let Some(mut change) = change {
+            let old_bounds = self.doc.selected_bounds();
             change(self);
-            self.dirty_mask.mark_dirty_layer(layer);
+            let bounds = old_bounds.union(self.doc.selected_bounds());
+            self.dirty_mask.mark_dirty_rect(layer, bounds);
         }
     }
 
@@ -771,32 +2003,24 @@ impl App {
             self.ui.key_pressed(KeyCode::Z))
             && !self.undo.borrow().is_empty()
         {
-            let doc: &mut Document = &mut self.doc;
-            let err = self
-                .undo
-                .borrow_mut()
-                .apply(doc, &mut self.redo.borrow_mut());
-            self.report_error(err);
-            self.dirty_mask = ChangeMask {
-                cell_layers: u64::MAX,
-                reference_path: false,
-            };
+            let position = self.undo.borrow().position();
+            self.jump_to_undo_position(position - 1);
         }
         if (self.ui.add(cols, button("Redo").enabled(!self.redo.borrow().is_empty())).clicked ||
             //self.ui.key_pressed_with_modifiers(KeyCode::Z, true, true, false)
             self.ui.key_pressed(KeyCode::Y))
             && !self.redo.borrow().is_empty()
         {
-            let doc: &mut Document = &mut self.doc;
-            let err = self
-                .redo
-                .borrow_mut()
-                .apply(doc, &mut self.undo.borrow_mut());
-            self.report_error(err);
-            self.dirty_mask = ChangeMask {
-                cell_layers: u64::MAX,
-                reference_path: false,
-            };
+            let position = self.undo.borrow().position();
+            self.jump_to_undo_position(position + 1);
+        }
+
+        if self
+            .ui
+            .add(cols, button("History").down(self.show_history))
+            .clicked
+        {
+            self.show_history = !self.show_history;
         }
 
         self.ui.add(cols, label("Tool"));
@@ -872,7 +2096,7 @@ impl App {
                 let state_res = self.save_app_state();
                 self.report_error(state_res);
             }
-            self.dirty_mask.cell_layers = u64::MAX;
+            self.dirty_mask.all_layers = true;
         };
     }
 
@@ -918,7 +2142,7 @@ impl App {
 
     fn ui_confirm_unsaved_changes(&mut self, context: &mut miniquad::Context) {
         if let Some(mut post_action) = self.confirm_unsaved_changes.take() {
-            if *self.undo_saved_position.borrow() == self.undo.borrow().records.len() {
+            if *self.undo_saved_position.borrow() == self.undo.borrow().position() {
                 return;
             }
             let window = self.ui.window(
@@ -967,7 +2191,7 @@ impl App {
                 .clicked
             {
                 self.undo_saved_position
-                    .replace(self.undo.borrow().records.len());
+                    .replace(self.undo.borrow().position());
                 post_action(self, context);
             }
 
@@ -997,8 +2221,9 @@ impl App {
         self.redo.borrow_mut().clear();
         self.undo_saved_position.replace(0);
         self.dirty_mask = ChangeMask {
-            cell_layers: u64::MAX,
+            all_layers: true,
             reference_path: true,
+            ..ChangeMask::default()
         }
     }
 
@@ -1006,29 +2231,37 @@ impl App {
     where
         T: for<'a> FnMut(&mut App, &mut miniquad::Context) + 'static,
     {
-        if *self.undo_saved_position.borrow() != self.undo.borrow().records.len() {
+        if *self.undo_saved_position.borrow() != self.undo.borrow().position() {
             self.confirm_unsaved_changes = Some(Box::new(post_action));
             return true;
         }
         false
     }
 
+    /// Saved map image has to include all changes, including ones that are still being
+    /// generated in background
+    fn generate_for_save(&mut self, context: &mut miniquad::Context) {
+        // exported map includes hidden layers, editor view is restored afterwards
+        self.dirty_mask.all_layers = true;
+        self.generation_profiler.begin_frame();
+        self.graphics.borrow_mut().generate(
+            &self.doc,
+            &ChangeMask {
+                all_layers: true,
+                ..ChangeMask::default()
+            },
+            true,
+            Some(context),
+            &mut self.generation_profiler,
+        );
+    }
+
     fn on_map_save(&mut self, context: &mut miniquad::Context) -> bool {
-        if let Some(path) = &self.doc_path {
+        if let Some(path) = self.doc_path.clone() {
             self.doc.pre_save_cleanup();
-            self.generation_profiler.begin_frame();
-            self.graphics.borrow_mut().generate(
-                &self.doc,
-                ChangeMask {
-                    cell_layers: u64::MAX,
-                    reference_path: false,
-                },
-                true,
-                Some(context),
-                &mut self.generation_profiler,
-            );
+            self.generate_for_save(context);
             let save_res = App::save_doc(
-                path,
+                &path,
                 &self.doc,
                 &self.graphics.borrow(),
                 self.white_texture.clone(),
@@ -1041,7 +2274,7 @@ impl App {
             let result = save_res.is_ok();
             if save_res.is_ok() {
                 self.undo_saved_position
-                    .replace(self.undo.borrow().records.len());
+                    .replace(self.undo.borrow().position());
                 self.confirm_unsaved_changes = None;
             } else {
                 self.report_error(save_res);
@@ -1061,6 +2294,7 @@ impl App {
 
         if let Some(nfd2::Response::Okay(path)) = path {
             self.doc.pre_save_cleanup();
+            self.generate_for_save(context);
             let save_res = App::save_doc(
                 Path::new(&path),
                 &self.doc,
@@ -1075,7 +2309,7 @@ impl App {
             let result = save_res.is_ok();
             if save_res.is_ok() {
                 self.undo_saved_position
-                    .replace(self.undo.borrow().records.len());
+                    .replace(self.undo.borrow().position());
                 self.confirm_unsaved_changes = None;
             } else {
                 self.report_error(save_res);
@@ -1116,6 +2350,16 @@ impl App {
             );
         }
 
+        if self.generation.is_pending() {
+            const SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+            let time = miniquad::date::now() - self.start_time;
+            let frame = (time * 8.0) as usize % SPINNER.len();
+            self.ui.add(
+                rows,
+                label(&format!("{} Generating...", SPINNER[frame])).min_size([120, 0]),
+            );
+        }
+
         if let Some(last_generation_time) = self.generation_profiler.total_duration() {
             let h = self.ui.add(rows, hbox().padding(2));
             if self
@@ -1234,6 +2478,80 @@ impl App {
     }
 }
 
+/// Entries of the layer list from top to bottom, with their nesting depth
+fn layer_list_rows(doc: &Document) -> Vec<(LayerEntry, usize)> {
+    fn add(
+        doc: &Document,
+        entries: &[LayerEntry],
+        depth: usize,
+        rows: &mut Vec<(LayerEntry, usize)>,
+    ) {
+        for &entry in entries.iter().rev() {
+            match entry {
+                LayerEntry::Layer(layer_key) => {
+                    if doc.layers.contains_key(layer_key) {
+                        rows.push((entry, depth));
+                    }
+                }
+                LayerEntry::Group { group } => {
+                    let group = some_or!(doc.layer_groups.get(group), continue);
+                    rows.push((entry, depth));
+                    if !group.collapsed {
+                        add(doc, &group.children, depth + 1, rows);
+                    }
+                }
+            }
+        }
+    }
+    let mut rows = Vec::new();
+    add(doc, &doc.layer_order, 0, &mut rows);
+    rows
+}
+
+/// Number that changes by dragging it sideways, by `step` per pixel. Returns the new value while
+/// it is being dragged.
+fn drag_number(
+    ui: &mut UI,
+    number_drag: &mut Option<NumberDrag>,
+    parent: AreaRef,
+    id: &str,
+    value: f32,
+    step: f32,
+    range: [f32; 2],
+) -> Option<f32> {
+    let text = if value.fract() == 0.0 {
+        format!("{}", value)
+    } else {
+        format!("{:.2}", value)
+    };
+    let dragged = number_drag.as_ref().is_some_and(|d| d.id == id);
+    ui.add(
+        parent,
+        button(&format!("{}#{}", id, text))
+            .down(dragged)
+            .min_size([64, 0]),
+    );
+    let pressed = ui.hit_item().is_some() && ui.hit_item() == ui.last_item(parent);
+    if !pressed {
+        if dragged {
+            *number_drag = None;
+        }
+        return None;
+    }
+    let mouse_x = ui.mouse_position()[0];
+    if !dragged {
+        *number_drag = Some(NumberDrag {
+            id: id.to_owned(),
+            start_x: mouse_x,
+            start_value: value,
+        });
+    }
+    let drag = number_drag.as_ref()?;
+    let new_value = drag.start_value + (mouse_x - drag.start_x) as f32 * step;
+    let new_value = ((new_value / step).round() * step).clamp(range[0], range[1]);
+    (new_value != value).then_some(new_value)
+}
+
 fn material_drop_down(
     ui: &mut UI,
     area: rimui::AreaRef,
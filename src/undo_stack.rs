@@ -1,43 +1,1085 @@
+use std::collections::HashSet;
+use std::iter::once;
+use std::mem::{size_of, size_of_val, take};
+use std::sync::Arc;
+
 use anyhow::{anyhow, Context, Result};
-use serde::de::DeserializeOwned;
-use serde::Serialize;
+use cbmap::{MapMarkup, MaterialSlot};
+use glam::IVec2;
+use serde::de::value::{self, MapDeserializer, SeqDeserializer};
+use serde::de::{DeserializeOwned, IntoDeserializer, Visitor};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer, Serialize};
+use slotmap::{Key, SlotMap};
+
+use crate::document::{
+    ChangeMask, Document, GridKey, Layer, LayerEntry, LayerGroup, LayerGroupKey, LayerKey,
+    SelectRef,
+};
+use crate::graph::{GraphEdge, GraphEdgeKey, GraphNode, GraphNodeKey};
+use crate::grid::Grid;
+use crate::math::Rect;
+use crate::plant::{Plant, PlantKey};
+use crate::symmetry::Symmetry;
+use crate::zone::ZoneRef;
+
+/// Consecutive records with the same text that are pushed closer than this (in seconds)
+/// are merged into one, so a single drag or a quick series of clicks is undone at once.
+const MERGE_INTERVAL: f64 = 0.5;
+
+/// Oldest records are dropped when the stack takes more memory than this.
+const MEMORY_LIMIT: usize = 256 * 1024 * 1024;
 
 pub struct UndoStack {
-    pub records: Vec<(Vec<u8>, String)>,
+    pub records: Vec<UndoRecord>,
+    /// Number of records dropped from the bottom of the stack due to memory limit
+    dropped: usize,
+    /// Copy of the tracked parts of the document, as of the last `close` or `apply`.
+    /// It is updated only where the document was changed, so pushing a record does not
+    /// copy the whole document. `None` until the first push.
+    state: Option<DocumentState>,
+    /// Parts of the document that were changed since `state` was updated
+    changes: ChangeMask,
+    /// Time of the last push, set while changes are collected into the top record
+    open: Option<f64>,
+    memory_used: usize,
+    memory_limit: usize,
+}
+
+pub struct UndoRecord {
+    pub text: String,
+    delta: DocumentDelta,
+    memory: usize,
+}
+
+/// Copy of the parts of the document that are tracked by undo
+struct DocumentState {
+    nodes: SlotMap<GraphNodeKey, GraphNode>,
+    edges: SlotMap<GraphEdgeKey, GraphEdge>,
+    plants: SlotMap<PlantKey, Plant>,
+    grids: SlotMap<GridKey, Arc<Grid<u8>>>,
+    selection: Grid<u8>,
+    markup: MapMarkup,
+    properties: Vec<u8>,
+}
+
+/// Tracked parts of either the document or its `DocumentState` copy, except properties
+struct TrackedParts<'a> {
+    nodes: &'a mut SlotMap<GraphNodeKey, GraphNode>,
+    edges: &'a mut SlotMap<GraphEdgeKey, GraphEdge>,
+    plants: &'a mut SlotMap<PlantKey, Plant>,
+    grids: &'a mut SlotMap<GridKey, Arc<Grid<u8>>>,
+    selection: &'a mut Grid<u8>,
+    markup: &'a mut MapMarkup,
+}
+
+/// Remaining fields of the document, these are small and stored as a whole
+#[derive(Serialize, Deserialize)]
+struct DocumentProperties {
+    materials: Vec<MaterialSlot>,
+    outline_widths: Vec<Option<f32>>,
+    cell_size: i32,
+    layers: SlotMap<LayerKey, Layer>,
+    layer_order: Vec<LayerEntry>,
+    layer_groups: SlotMap<LayerGroupKey, LayerGroup>,
+    current_layer: LayerKey,
+    zone_selection: Option<ZoneRef>,
+    reference_path: Option<String>,
+    reference_scale: i32,
+    show_reference: bool,
+    selected: Vec<SelectRef>,
+    symmetry: Symmetry,
+}
+
+/// Difference between two states of the document. Applying a delta restores `before`
+/// state, reversed delta restores `after` state.
+#[derive(Default)]
+struct DocumentDelta {
+    nodes: Vec<EntryDelta<GraphNodeKey, GraphNode>>,
+    edges: Vec<EntryDelta<GraphEdgeKey, GraphEdge>>,
+    plants: Vec<EntryDelta<PlantKey, Plant>>,
+    /// Grids that were added or removed
+    grids: Vec<EntryDelta<GridKey, Arc<Grid<u8>>>>,
+    /// Changed cells of grids that exist in both states
+    grid_cells: Vec<(GridKey, GridDelta)>,
+    selection: Option<GridDelta>,
+    markup: Option<[MapMarkup; 2]>,
+    properties: Option<[Vec<u8>; 2]>,
+}
+
+/// Slot map entry before and after, `None` stands for a missing entry
+struct EntryDelta<K, V> {
+    key: K,
+    values: [Option<V>; 2],
+}
+
+/// Cells of a rectangle that contains all changed cells of a grid
+struct GridDelta {
+    rect: [IVec2; 2],
+    bounds: [[IVec2; 2]; 2],
+    cells: [Vec<u8>; 2],
 }
 
 impl UndoStack {
     pub fn new() -> UndoStack {
         UndoStack {
             records: Vec::new(),
+            dropped: 0,
+            state: None,
+            changes: ChangeMask::default(),
+            open: None,
+            memory_used: 0,
+            memory_limit: MEMORY_LIMIT,
         }
     }
-    pub fn push(&mut self, instance: &impl Serialize, text: &str) -> Result<()> {
-        let bytes = serde_json::to_vec(instance)?;
-        self.records.push((bytes, text.to_owned()));
+
+    /// Starts a new record. Changes to the document are collected into it until the next
+    /// `push` or `apply`. When `allow_merge` is set, a record with the same text that was
+    /// pushed just before is continued instead.
+    pub fn push(&mut self, doc: &Document, text: &str, allow_merge: bool) -> Result<()> {
+        let time = miniquad::date::now();
+        if allow_merge {
+            if let (Some(last_push_time), Some(top)) = (&mut self.open, self.records.last()) {
+                if top.text == text && time - *last_push_time < MERGE_INTERVAL {
+                    *last_push_time = time;
+                    return Ok(());
+                }
+            }
+        }
+        self.close(doc)?;
+        if self.state.is_none() {
+            self.state = Some(DocumentState::capture(doc)?);
+            self.changes = ChangeMask::default();
+        }
+        self.open = Some(time);
+        self.records.push(UndoRecord {
+            text: text.to_owned(),
+            delta: DocumentDelta::default(),
+            memory: 0,
+        });
         Ok(())
     }
 
-    pub fn apply(
-        &mut self,
-        instance: &mut (impl Serialize + DeserializeOwned),
-        redo: &mut Self,
-    ) -> Result<()> {
-        let (bytes, text) = self
+    /// Notes parts of the document that were changed. Only these parts are compared when
+    /// the changes are collected into a record, so every change of layer cells, nodes,
+    /// edges and plants has to be marked here, like it is for generation.
+    pub fn mark_changes(&mut self, changes: &ChangeMask) {
+        if self.state.is_some() {
+            self.changes.merge(changes.clone());
+        }
+    }
+
+    /// Undoes top record and moves it to `redo`. Returns parts of the document that were
+    /// changed.
+    pub fn apply(&mut self, doc: &mut Document, redo: &mut Self) -> Result<ChangeMask> {
+        self.close(doc)?;
+        redo.close(doc)?;
+        let mut record = self
             .records
             .pop()
             .ok_or_else(|| anyhow!("Empty undo stack"))?;
-        let redo_bytes = serde_json::to_vec(&instance).context("Serializing redo record")?;
-        redo.records.push((redo_bytes, text));
-        *instance = serde_json::from_slice(&bytes).context("Deserializing undo record")?;
+        self.memory_used -= record.memory;
+        record.delta.apply(doc).context("Applying undo record")?;
+        for state in [&mut self.state, &mut redo.state].into_iter().flatten() {
+            record.delta.update(state, 0)?;
+        }
+        let changes = record.delta.changes(doc);
+        record.delta.reverse();
+        redo.memory_used += record.memory;
+        redo.records.push(record);
+        Ok(changes)
+    }
+
+    /// Computes changes since the state was updated and stores them in the open record,
+    /// if there is one.
+    fn close(&mut self, doc: &Document) -> Result<()> {
+        let Some(state) = &mut self.state else { return Ok(()) };
+        let delta = DocumentDelta::compute(state, doc, &take(&mut self.changes))?;
+        delta.update(state, 1)?;
+        debug_assert!(
+            state.matches(doc)?,
+            "Undo state differs from the document, an edit did not mark its changes"
+        );
+        if self.open.take().is_none() {
+            return Ok(());
+        }
+        let top = self
+            .records
+            .last_mut()
+            .ok_or_else(|| anyhow!("Open undo record is missing"))?;
+        top.delta = delta;
+        top.memory = top.delta.memory();
+        self.memory_used += top.memory;
+        self.enforce_memory_limit();
         Ok(())
     }
 
+    fn enforce_memory_limit(&mut self) {
+        let mut num_dropped = 0;
+        while self.memory_used > self.memory_limit && num_dropped + 1 < self.records.len() {
+            self.memory_used -= self.records[num_dropped].memory;
+            num_dropped += 1;
+        }
+        if num_dropped > 0 {
+            self.records.drain(..num_dropped);
+            self.dropped += num_dropped;
+        }
+    }
+
+    /// Number of records pushed since the stack was cleared, including dropped ones.
+    /// Used to track unsaved changes.
+    pub fn position(&self) -> usize {
+        self.dropped + self.records.len()
+    }
+
+    /// Position of the oldest state that can still be restored.
+    pub fn first_position(&self) -> usize {
+        self.dropped
+    }
+
     pub fn is_empty(&self) -> bool {
         self.records.is_empty()
     }
 
     pub fn clear(&mut self) {
         self.records.clear();
+        self.dropped = 0;
+        self.state = None;
+        self.changes = ChangeMask::default();
+        self.open = None;
+        self.memory_used = 0;
+    }
+}
+
+impl DocumentState {
+    fn capture(doc: &Document) -> Result<DocumentState> {
+        Ok(DocumentState {
+            nodes: doc.nodes.clone(),
+            edges: doc.edges.clone(),
+            plants: doc.plants.clone(),
+            grids: doc.grids.clone(),
+            selection: doc.selection.clone(),
+            markup: doc.markup.clone(),
+            properties: DocumentProperties::serialize(doc)?,
+        })
+    }
+
+    /// True when the copy equals tracked parts of the document. It differs when an edit
+    /// did not mark the parts it changed, so they were left out of the delta.
+    fn matches(&self, doc: &Document) -> Result<bool> {
+        let same_cells = |a: &Grid<u8>, b: &Grid<u8>| GridDelta::compute(a, b, None).is_none();
+        Ok(self.nodes.iter().eq(doc.nodes.iter())
+            && self.edges.iter().eq(doc.edges.iter())
+            && self.plants.iter().eq(doc.plants.iter())
+            && self.grids.len() == doc.grids.len()
+            && self.grids.iter().all(|(key, grid)| {
+                doc.grids
+                    .get(key)
+                    .is_some_and(|doc_grid| same_cells(grid, doc_grid))
+            })
+            && same_cells(&self.selection, &doc.selection)
+            && self.markup == doc.markup
+            && self.properties == DocumentProperties::serialize(doc)?)
+    }
+
+    fn parts(&mut self) -> TrackedParts<'_> {
+        TrackedParts {
+            nodes: &mut self.nodes,
+            edges: &mut self.edges,
+            plants: &mut self.plants,
+            grids: &mut self.grids,
+            selection: &mut self.selection,
+            markup: &mut self.markup,
+        }
+    }
+}
+
+impl<'a> TrackedParts<'a> {
+    fn of_document(doc: &'a mut Document) -> TrackedParts<'a> {
+        TrackedParts {
+            nodes: &mut doc.nodes,
+            edges: &mut doc.edges,
+            plants: &mut doc.plants,
+            grids: &mut doc.grids,
+            selection: &mut doc.selection,
+            markup: &mut doc.markup,
+        }
+    }
+}
+
+impl DocumentProperties {
+    fn serialize(doc: &Document) -> Result<Vec<u8>> {
+        let properties = DocumentProperties {
+            materials: doc.materials.clone(),
+            outline_widths: doc.outline_widths.clone(),
+            cell_size: doc.cell_size,
+            layers: doc.layers.clone(),
+            layer_order: doc.layer_order.clone(),
+            layer_groups: doc.layer_groups.clone(),
+            current_layer: doc.current_layer,
+            zone_selection: doc.zone_selection,
+            reference_path: doc.reference_path.clone(),
+            reference_scale: doc.reference_scale,
+            show_reference: doc.show_reference,
+            selected: doc.selected.clone(),
+            symmetry: doc.symmetry,
+        };
+        serde_json::to_vec(&properties).context("Serializing document properties")
+    }
+
+    fn apply(bytes: &[u8], doc: &mut Document) -> Result<()> {
+        let properties: DocumentProperties =
+            serde_json::from_slice(bytes).context("Deserializing document properties")?;
+        doc.materials = properties.materials;
+        doc.outline_widths = properties.outline_widths;
+        doc.cell_size = properties.cell_size;
+        doc.layers = properties.layers;
+        doc.layer_order = properties.layer_order;
+        doc.layer_groups = properties.layer_groups;
+        doc.current_layer = properties.current_layer;
+        doc.zone_selection = properties.zone_selection;
+        doc.reference_path = properties.reference_path;
+        doc.reference_scale = properties.reference_scale;
+        doc.show_reference = properties.show_reference;
+        doc.selected = properties.selected;
+        doc.symmetry = properties.symmetry;
+        Ok(())
+    }
+}
+
+impl DocumentDelta {
+    /// Compares `state` with the document. Layer cells, nodes, edges and plants are compared
+    /// only within `changes`, remaining parts are small enough to be compared as a whole.
+    fn compute(
+        state: &DocumentState,
+        doc: &Document,
+        changes: &ChangeMask,
+    ) -> Result<DocumentDelta> {
+        let changed_layer =
+            |layer: LayerKey| changes.all_layers || changes.cell_layers.contains(&layer);
+        let cell_size = doc.cell_size as f32;
+        let changed_cells = changes
+            .cell_rect
+            .filter(|_| !changes.all_layers)
+            .map(|rect| {
+                [
+                    (rect[0] / cell_size).floor().as_ivec2(),
+                    (rect[1] / cell_size).ceil().as_ivec2(),
+                ]
+                .inflate(1)
+            });
+        let changed_grids: HashSet<GridKey> = doc
+            .layers
+            .iter()
+            .filter(|(key, _)| changed_layer(*key))
+            .map(|(_, layer)| layer.grid)
+            .collect();
+
+        let mut grids = Vec::new();
+        let mut grid_cells = Vec::new();
+        for (key, after_grid) in &doc.grids {
+            match state.grids.get(key) {
+                Some(before_grid) => {
+                    if !changed_grids.contains(&key) {
+                        continue;
+                    }
+                    if let Some(delta) = GridDelta::compute(before_grid, after_grid, changed_cells)
+                    {
+                        grid_cells.push((key, delta));
+                    }
+                }
+                None => grids.push(EntryDelta {
+                    key,
+                    values: [None, Some(after_grid.clone())],
+                }),
+            }
+        }
+        for (key, before_grid) in &state.grids {
+            if !doc.grids.contains_key(key) {
+                grids.push(EntryDelta {
+                    key,
+                    values: [Some(before_grid.clone()), None],
+                });
+            }
+        }
+
+        let changed_node = |key: GraphNodeKey| {
+            [&state.nodes, &doc.nodes]
+                .iter()
+                .any(|nodes| nodes.get(key).is_some_and(|n| changed_layer(n.layer)))
+        };
+        let properties = DocumentProperties::serialize(doc)?;
+        Ok(DocumentDelta {
+            nodes: EntryDelta::compute(&state.nodes, &doc.nodes, |n| changed_layer(n.layer)),
+            edges: EntryDelta::compute(&state.edges, &doc.edges, |e| {
+                changed_node(e.start) || changed_node(e.end)
+            }),
+            plants: EntryDelta::compute(&state.plants, &doc.plants, |p| changed_layer(p.layer)),
+            grids,
+            grid_cells,
+            selection: GridDelta::compute(&state.selection, &doc.selection, None),
+            markup: (state.markup != doc.markup)
+                .then(|| [state.markup.clone(), doc.markup.clone()]),
+            properties: (state.properties != properties)
+                .then(|| [state.properties.clone(), properties]),
+        })
+    }
+
+    /// Restores `before` state of the changed parts
+    fn apply(&self, doc: &mut Document) -> Result<()> {
+        self.restore(TrackedParts::of_document(doc), 0)?;
+        if let Some([properties, _]) = &self.properties {
+            DocumentProperties::apply(properties, doc)?;
+        }
+        Ok(())
+    }
+
+    /// Brings the copy of the document to `before` (`side` 0) or `after` (`side` 1) state
+    fn update(&self, state: &mut DocumentState, side: usize) -> Result<()> {
+        self.restore(state.parts(), side)?;
+        if let Some(properties) = &self.properties {
+            state.properties = properties[side].clone();
+        }
+        Ok(())
+    }
+
+    fn restore(&self, parts: TrackedParts, side: usize) -> Result<()> {
+        EntryDelta::apply(&self.nodes, parts.nodes, side)?;
+        EntryDelta::apply(&self.edges, parts.edges, side)?;
+        EntryDelta::apply(&self.plants, parts.plants, side)?;
+        EntryDelta::apply(&self.grids, parts.grids, side)?;
+        for (key, delta) in &self.grid_cells {
+            let grid = parts
+                .grids
+                .get_mut(*key)
+                .ok_or_else(|| anyhow!("Missing grid for undo"))?;
+            delta.apply(Arc::make_mut(grid), side);
+        }
+        if let Some(delta) = &self.selection {
+            delta.apply(parts.selection, side);
+        }
+        if let Some(markup) = &self.markup {
+            *parts.markup = markup[side].clone();
+        }
+        Ok(())
+    }
+
+    /// Parts of the document that differ between the two states, `doc` should be in
+    /// either of them
+    fn changes(&self, doc: &Document) -> ChangeMask {
+        let mut changes = ChangeMask::default();
+        if self.properties.is_some() || !self.grids.is_empty() {
+            // layers could be added, removed, reordered or have different materials
+            changes.all_layers = true;
+            return changes;
+        }
+        for (key, delta) in &self.grid_cells {
+            match doc.layers.iter().find(|(_, layer)| layer.grid == *key) {
+                Some((layer, _)) => changes.mark_dirty_cells(layer, delta.rect, doc.cell_size),
+                None => changes.all_layers = true,
+            }
+        }
+        let node_layers = self.nodes.iter().flat_map(|e| e.values.iter().flatten());
+        let plant_layers = self.plants.iter().flat_map(|e| e.values.iter().flatten());
+        let edge_nodes = self
+            .edges
+            .iter()
+            .flat_map(|e| e.values.iter().flatten())
+            .flat_map(|edge| [edge.start, edge.end])
+            .filter_map(|key| doc.nodes.get(key));
+        let layers: Vec<LayerKey> = node_layers
+            .chain(edge_nodes)
+            .map(|n| n.layer)
+            .chain(plant_layers.map(|p| p.layer))
+            .collect();
+        for layer in layers {
+            changes.mark_dirty_layer(layer);
+        }
+        changes
+    }
+
+    fn reverse(&mut self) {
+        self.nodes.iter_mut().for_each(|e| e.values.swap(0, 1));
+        self.edges.iter_mut().for_each(|e| e.values.swap(0, 1));
+        self.plants.iter_mut().for_each(|e| e.values.swap(0, 1));
+        self.grids.iter_mut().for_each(|e| e.values.swap(0, 1));
+        self.grid_cells.iter_mut().for_each(|(_, d)| d.reverse());
+        if let Some(delta) = &mut self.selection {
+            delta.reverse();
+        }
+        if let Some(markup) = &mut self.markup {
+            markup.swap(0, 1);
+        }
+        if let Some(properties) = &mut self.properties {
+            properties.swap(0, 1);
+        }
+    }
+
+    /// Approximate amount of memory held by the delta
+    fn memory(&self) -> usize {
+        fn entries_memory<K, V>(entries: &[EntryDelta<K, V>]) -> usize {
+            size_of_val(entries)
+        }
+        let grid_memory = |grid: &Grid<u8>| grid.cells.len() + size_of::<Grid<u8>>();
+        let grid_delta_memory = |delta: &GridDelta| {
+            delta.cells[0].len() + delta.cells[1].len() + size_of::<GridDelta>()
+        };
+
+        size_of::<DocumentDelta>()
+            + entries_memory(&self.nodes)
+            + entries_memory(&self.edges)
+            + entries_memory(&self.plants)
+            + self
+                .grids
+                .iter()
+                .flat_map(|e| e.values.iter().flatten())
+                .map(|grid| grid_memory(grid))
+                .sum::<usize>()
+            + self
+                .grid_cells
+                .iter()
+                .map(|(_, d)| grid_delta_memory(d))
+                .sum::<usize>()
+            + self.selection.as_ref().map(grid_delta_memory).unwrap_or(0)
+            + self
+                .markup
+                .as_ref()
+                .map(|_| 2 * size_of::<MapMarkup>())
+                .unwrap_or(0)
+            + self
+                .properties
+                .as_ref()
+                .map(|[a, b]| a.len() + b.len())
+                .unwrap_or(0)
+    }
+}
+
+impl<K: Key, V: Clone + PartialEq> EntryDelta<K, V> {
+    /// Compares entries for which `changed` is true in either state
+    fn compute(
+        before: &SlotMap<K, V>,
+        after: &SlotMap<K, V>,
+        changed: impl Fn(&V) -> bool,
+    ) -> Vec<EntryDelta<K, V>> {
+        let mut entries = Vec::new();
+        for (key, value) in after {
+            let before_value = before.get(key);
+            if !changed(value) && !before_value.is_some_and(&changed) {
+                continue;
+            }
+            if before_value != Some(value) {
+                entries.push(EntryDelta {
+                    key,
+                    values: [before_value.cloned(), Some(value.clone())],
+                });
+            }
+        }
+        for (key, value) in before {
+            if changed(value) && !after.contains_key(key) {
+                entries.push(EntryDelta {
+                    key,
+                    values: [Some(value.clone()), None],
+                });
+            }
+        }
+        entries
+    }
+}
+
+impl<K: Key, V: Clone + DeserializeOwned> EntryDelta<K, V> {
+    fn apply(entries: &[EntryDelta<K, V>], map: &mut SlotMap<K, V>, side: usize) -> Result<()> {
+        let mut reinserted = Vec::new();
+        for entry in entries {
+            match (&entry.values[side], map.get_mut(entry.key)) {
+                (Some(value), Some(existing)) => *existing = value.clone(),
+                (Some(value), None) => reinserted.push((entry.key, value.clone())),
+                (None, _) => {
+                    map.remove(entry.key);
+                }
+            }
+        }
+        if !reinserted.is_empty() {
+            insert_with_keys(map, reinserted)?;
+        }
+        Ok(())
+    }
+}
+
+/// Inserts values under exact keys, so references to them stay valid after undo.
+///
+/// `SlotMap` only hands out keys that are newer than the ones a slot had before, so the
+/// map is rebuilt. Its slots are recreated vacant, one version below the wanted one, and
+/// filled again by regular inserts, which take vacant slots from the highest index down.
+/// Values are moved, not copied or serialized. Slots that stay vacant keep their
+/// versions, so later inserts never hand out keys that were used before.
+fn insert_with_keys<K: Key, V: Clone + DeserializeOwned>(
+    map: &mut SlotMap<K, V>,
+    entries: Vec<(K, V)>,
+) -> Result<()> {
+    let index_version = |key: K| {
+        let ffi = key.data().as_ffi();
+        ((ffi & 0xffff_ffff) as usize, (ffi >> 32) as u32)
+    };
+    let Some(placeholder) = entries.first().map(|(_, value)| value.clone()) else { return Ok(()) };
+
+    // versions of vacant slots are revealed by filling them, until an unused slot is taken
+    let mut vacant = Vec::new();
+    let num_slots = loop {
+        let (index, version) = index_version(map.insert(placeholder.clone()));
+        if version == 1 {
+            break index;
+        }
+        vacant.push((index, version - 1));
+    };
+
+    // version and value of each slot
+    let mut slots: Vec<(u32, Option<V>)> = (0..num_slots).map(|_| (0, None)).collect();
+    for (key, value) in map.drain() {
+        let (index, version) = index_version(key);
+        if index < num_slots {
+            slots[index] = (version, Some(value));
+        }
+    }
+    for (index, version) in vacant {
+        slots[index] = (version, None);
+    }
+    for (key, value) in entries {
+        let (index, version) = index_version(key);
+        if index >= slots.len() {
+            // slots that were never allocated
+            slots.resize_with(index + 1, || (0, None));
+        }
+        slots[index] = (version, Some(value));
+    }
+
+    // an insert makes the version odd and a removal makes it even again
+    let versions: Vec<u32> = slots
+        .iter()
+        .map(|(version, value)| match value {
+            Some(_) => version - 1,
+            None => version.saturating_sub(2),
+        })
+        .collect();
+    *map = SlotMap::deserialize(VacantSlots(&versions)).context("Rebuilding slot map")?;
+    let mut emptied = Vec::new();
+    for (index, (_, value)) in slots.into_iter().enumerate().skip(1).rev() {
+        let key = match value {
+            Some(value) => map.insert(value),
+            None => {
+                let key = map.insert(placeholder.clone());
+                emptied.push(key);
+                key
+            }
+        };
+        debug_assert_eq!(index_version(key).0, index);
+    }
+    for key in emptied {
+        map.remove(key);
+    }
+    Ok(())
+}
+
+/// Serialized form of a slot map with vacant slots of the given versions
+struct VacantSlots<'a>(&'a [u32]);
+
+/// Serialized form of a vacant slot with the given version
+struct VacantSlot(u32);
+
+impl<'de, 'a> Deserializer<'de> for VacantSlots<'a> {
+    type Error = value::Error;
+
+    fn deserialize_any<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, value::Error> {
+        visitor.visit_seq(SeqDeserializer::new(self.0.iter().map(|&v| VacantSlot(v))))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de> Deserializer<'de> for VacantSlot {
+    type Error = value::Error;
+
+    fn deserialize_any<W: Visitor<'de>>(self, visitor: W) -> Result<W::Value, value::Error> {
+        // missing value reads as `None`
+        visitor.visit_map(MapDeserializer::new(once(("version", self.0))))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option
+        unit unit_struct newtype_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, value::Error> for VacantSlot {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl GridDelta {
+    /// Compares cells of both grids within `area`, or everywhere when it is not set
+    fn compute(before: &Grid<u8>, after: &Grid<u8>, area: Option<[IVec2; 2]>) -> Option<GridDelta> {
+        let mut compared = before.bounds.union(after.bounds);
+        if let Some(area) = area {
+            compared = compared.intersect(area)?;
+        }
+        let mut changed = <[IVec2; 2]>::invalid();
+        for y in compared[0].y..compared[1].y {
+            let (first, last) = if before.bounds == after.bounds {
+                let start = before.grid_pos_index(compared[0].x, y);
+                let range = start..start + compared.size().x as usize;
+                let (before_row, after_row) = (&before.cells[range.clone()], &after.cells[range]);
+                if before_row == after_row {
+                    continue;
+                }
+                let differs = |(b, a): (&u8, &u8)| b != a;
+                let first = before_row.iter().zip(after_row).position(differs);
+                let last = before_row.iter().zip(after_row).rposition(differs);
+                (first.unwrap(), last.unwrap())
+            } else {
+                let differs = |x: i32| {
+                    let p = IVec2::new(x, y);
+                    cell_value(before, p) != cell_value(after, p)
+                };
+                let Some(first) = (compared[0].x..compared[1].x).position(differs) else { continue };
+                let last = (compared[0].x..compared[1].x).rposition(differs).unwrap();
+                (first, last)
+            };
+            changed = changed.union([
+                IVec2::new(compared[0].x + first as i32, y),
+                IVec2::new(compared[0].x + last as i32 + 1, y + 1),
+            ]);
+        }
+        if !changed.is_valid() {
+            return None;
+        }
+
+        let rect = changed;
+        let read_rect = |grid: &Grid<u8>| {
+            let mut cells = Vec::with_capacity((rect.size().x * rect.size().y) as usize);
+            for y in rect[0].y..rect[1].y {
+                for x in rect[0].x..rect[1].x {
+                    cells.push(cell_value(grid, IVec2::new(x, y)));
+                }
+            }
+            cells
+        };
+        Some(GridDelta {
+            rect,
+            bounds: [before.bounds, after.bounds],
+            cells: [read_rect(before), read_rect(after)],
+        })
+    }
+
+    /// Restores `before` (`side` 0) or `after` (`side` 1) state of the grid
+    fn apply(&self, grid: &mut Grid<u8>, side: usize) {
+        grid.resize(self.bounds[side]);
+        let Some(rect) = self.rect.intersect(grid.bounds) else { return };
+        let width = self.rect.size().x;
+        for y in rect[0].y..rect[1].y {
+            for x in rect[0].x..rect[1].x {
+                let source = (y - self.rect[0].y) * width + (x - self.rect[0].x);
+                let index = grid.grid_pos_index(x, y);
+                grid.cells[index] = self.cells[side][source as usize];
+            }
+        }
+    }
+
+    fn reverse(&mut self) {
+        self.bounds.swap(0, 1);
+        self.cells.swap(0, 1);
+    }
+}
+
+fn cell_value(grid: &Grid<u8>, p: IVec2) -> u8 {
+    if grid.bounds.contains_point(p) {
+        grid.cells[grid.grid_pos_index(p.x, p.y)]
+    } else {
+        grid.default_value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::ivec2;
+
+    use super::*;
+
+    struct History {
+        doc: Document,
+        undo: UndoStack,
+        redo: UndoStack,
+    }
+
+    impl History {
+        fn new() -> History {
+            History {
+                doc: Document::new(),
+                undo: UndoStack::new(),
+                redo: UndoStack::new(),
+            }
+        }
+
+        /// Edits the document the way the editor does it: pushes a record, then marks
+        /// the parts changed by `edit`.
+        fn edit<T>(
+            &mut self,
+            text: &str,
+            allow_merge: bool,
+            edit: impl FnOnce(&mut Document, &mut ChangeMask) -> T,
+        ) -> T {
+            self.undo.push(&self.doc, text, allow_merge).unwrap();
+            self.redo.clear();
+            let mut changes = ChangeMask::default();
+            let result = edit(&mut self.doc, &mut changes);
+            self.undo.mark_changes(&changes);
+            result
+        }
+
+        fn undo(&mut self) {
+            let changes = self.undo.apply(&mut self.doc, &mut self.redo).unwrap();
+            self.undo.mark_changes(&changes);
+        }
+
+        fn redo(&mut self) {
+            let changes = self.redo.apply(&mut self.doc, &mut self.undo).unwrap();
+            self.undo.mark_changes(&changes);
+        }
+
+        /// Non-empty cells of the current layer
+        fn cells(&self) -> Vec<(IVec2, u8)> {
+            let grid_key = self.doc.layers[self.doc.current_layer].grid;
+            let Some(grid) = self.doc.grids.get(grid_key) else { return Vec::new() };
+            let bounds = grid.bounds;
+            (bounds[0].y..bounds[1].y)
+                .flat_map(|y| (bounds[0].x..bounds[1].x).map(move |x| ivec2(x, y)))
+                .map(|p| (p, cell_value(grid, p)))
+                .filter(|(_, value)| *value != 0)
+                .collect()
+        }
+
+        fn node_positions(&self) -> Vec<(GraphNodeKey, IVec2)> {
+            self.doc.nodes.iter().map(|(key, n)| (key, n.pos)).collect()
+        }
+    }
+
+    fn paint(doc: &mut Document, changes: &mut ChangeMask, pos: IVec2, value: u8) {
+        let layer = doc.current_layer;
+        let grid_key = Document::get_or_add_layer_grid(&mut doc.layers, layer, &mut doc.grids);
+        let grid = Arc::make_mut(&mut doc.grids[grid_key]);
+        grid.resize_to_include_amortized(Rect::from_point(pos));
+        let index = grid.grid_pos_index(pos.x, pos.y);
+        grid.cells[index] = value;
+        changes.mark_dirty_cells(layer, Rect::from_point(pos), doc.cell_size);
+    }
+
+    fn add_node(doc: &mut Document, changes: &mut ChangeMask, pos: IVec2) -> GraphNodeKey {
+        changes.mark_dirty_layer(doc.current_layer);
+        doc.nodes.insert(GraphNode {
+            pos,
+            layer: doc.current_layer,
+            ..GraphNode::new()
+        })
+    }
+
+    fn remove_node(doc: &mut Document, changes: &mut ChangeMask, key: GraphNodeKey) {
+        changes.mark_dirty_layer(doc.current_layer);
+        doc.nodes.remove(key);
+    }
+
+    #[test]
+    fn undo_redo_round_trip() {
+        let mut h = History::new();
+        h.edit("Paint", true, |doc, changes| {
+            paint(doc, changes, ivec2(1, 2), 1)
+        });
+        let node = h.edit("Add Node", true, |doc, changes| {
+            add_node(doc, changes, ivec2(16, 8))
+        });
+        h.edit("Paint", true, |doc, changes| {
+            paint(doc, changes, ivec2(-40, 30), 2);
+            paint(doc, changes, ivec2(1, 2), 3);
+        });
+        let painted = h.cells();
+        assert_eq!(painted, vec![(ivec2(1, 2), 3), (ivec2(-40, 30), 2)]);
+
+        h.undo();
+        assert_eq!(h.cells(), vec![(ivec2(1, 2), 1)]);
+        assert_eq!(h.node_positions(), vec![(node, ivec2(16, 8))]);
+        h.undo();
+        assert!(h.node_positions().is_empty());
+        h.undo();
+        assert!(h.cells().is_empty());
+        assert!(h.undo.is_empty());
+
+        h.redo();
+        h.redo();
+        h.redo();
+        assert_eq!(h.cells(), painted);
+        assert_eq!(h.node_positions(), vec![(node, ivec2(16, 8))]);
+        assert!(h.redo.is_empty());
+    }
+
+    #[test]
+    fn merges_records_with_same_text() {
+        let mut h = History::new();
+        let node = h.edit("Add Node", true, |doc, changes| {
+            add_node(doc, changes, ivec2(0, 0))
+        });
+        for x in 1..=3 {
+            h.edit("Move", true, |doc, changes| {
+                doc.nodes[node].pos.x = x;
+                changes.mark_dirty_layer(doc.current_layer);
+            });
+        }
+        assert_eq!(h.undo.records.len(), 2);
+
+        h.edit("Move", false, |doc, changes| {
+            doc.nodes[node].pos.y = 5;
+            changes.mark_dirty_layer(doc.current_layer);
+        });
+        assert_eq!(h.undo.records.len(), 3);
+
+        h.undo();
+        assert_eq!(h.node_positions(), vec![(node, ivec2(3, 0))]);
+        h.undo();
+        assert_eq!(h.node_positions(), vec![(node, ivec2(0, 0))]);
+    }
+
+    #[test]
+    fn drops_oldest_records_over_memory_limit() {
+        let mut h = History::new();
+        h.undo.memory_limit = 1;
+        for x in 0..5 {
+            h.edit("Paint", false, |doc, changes| {
+                paint(doc, changes, ivec2(x, 0), 1)
+            });
+        }
+        // the open record is never dropped
+        assert_eq!(h.undo.records.len(), 2);
+        assert_eq!(h.undo.first_position(), 3);
+        assert_eq!(h.undo.position(), 5);
+
+        // closing the top record drops the one below it
+        h.undo();
+        assert!(h.undo.is_empty());
+        assert_eq!(h.undo.first_position(), 4);
+        let remaining: Vec<IVec2> = h.cells().into_iter().map(|(p, _)| p).collect();
+        assert_eq!(remaining, (0..4).map(|x| ivec2(x, 0)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keeps_keys_of_restored_entries() {
+        let mut h = History::new();
+        let a = h.edit("Add Node", false, |doc, changes| {
+            add_node(doc, changes, ivec2(0, 0))
+        });
+        let b = h.edit("Add Node", false, |doc, changes| {
+            add_node(doc, changes, ivec2(8, 0))
+        });
+        h.edit("Remove", false, |doc, changes| remove_node(doc, changes, a));
+        h.edit("Remove", false, |doc, changes| remove_node(doc, changes, b));
+
+        h.undo();
+        assert_eq!(h.node_positions(), vec![(b, ivec2(8, 0))]);
+        h.undo();
+        assert_eq!(h.node_positions(), vec![(a, ivec2(0, 0)), (b, ivec2(8, 0))]);
+        h.redo();
+        h.redo();
+        assert!(h.node_positions().is_empty());
+
+        // slot of `a` stays vacant while `b` is restored, new nodes should not get its key
+        h.undo();
+        let c = h.edit("Add Node", false, |doc, changes| {
+            add_node(doc, changes, ivec2(16, 0))
+        });
+        assert!(c != a && c != b);
+        h.undo();
+        h.undo();
+        assert_eq!(h.node_positions(), vec![(a, ivec2(0, 0)), (b, ivec2(8, 0))]);
+    }
+
+    /// Document as it is saved, with vacant slots left out: they keep newer versions
+    /// after undo, so that their keys are not handed out again
+    fn snapshot(doc: &Document) -> serde_json::Value {
+        let mut value = serde_json::to_value(doc).unwrap();
+        for map in [
+            "layer_map",
+            "layer_groups",
+            "grids",
+            "nodes",
+            "edges",
+            "plants",
+        ] {
+            let slots = value[map].as_array().cloned().unwrap_or_default();
+            value[map] = slots
+                .into_iter()
+                .enumerate()
+                .filter(|(_, slot)| !slot["value"].is_null())
+                .map(|(index, slot)| serde_json::json!([index, slot]))
+                .collect();
+        }
+        value
+    }
+
+    #[test]
+    fn undo_and_redo_restore_full_snapshots() {
+        let mut h = History::new();
+        let mut snapshots = vec![snapshot(&h.doc)];
+        h.edit("Paint", false, |doc, changes| {
+            for x in 0..4 {
+                paint(doc, changes, ivec2(x, -x), 1);
+            }
+        });
+        snapshots.push(snapshot(&h.doc));
+        let (a, b) = h.edit("Add Nodes", false, |doc, changes| {
+            let a = add_node(doc, changes, ivec2(0, 0));
+            let b = add_node(doc, changes, ivec2(64, 0));
+            doc.edges.insert(GraphEdge::new(a, b));
+            (a, b)
+        });
+        snapshots.push(snapshot(&h.doc));
+        h.edit("Add Plant", false, |doc, changes| {
+            changes.mark_dirty_layer(doc.current_layer);
+            doc.plants.insert(Plant {
+                layer: doc.current_layer,
+                ..Plant::new()
+            });
+        });
+        snapshots.push(snapshot(&h.doc));
+        h.edit("Move", false, |doc, changes| {
+            doc.nodes[b].pos += ivec2(8, 8);
+            doc.selected = vec![SelectRef::Node(b)];
+            changes.mark_dirty_layer(doc.current_layer);
+        });
+        snapshots.push(snapshot(&h.doc));
+        h.edit("Delete", false, |doc, changes| {
+            remove_node(doc, changes, a);
+            doc.edges.retain(|_, edge| edge.start != a && edge.end != a);
+            paint(doc, changes, ivec2(1, -1), 0);
+        });
+        snapshots.push(snapshot(&h.doc));
+        h.edit("Add Layer", false, |doc, changes| {
+            let layer = doc.layers.insert(Layer::new());
+            doc.layer_order.push(LayerEntry::Layer(layer));
+            doc.current_layer = layer;
+            changes.all_layers = true;
+            paint(doc, changes, ivec2(5, 5), 2);
+        });
+        snapshots.push(snapshot(&h.doc));
+
+        for expected in snapshots.iter().rev().skip(1) {
+            h.undo();
+            assert_eq!(snapshot(&h.doc), *expected);
+        }
+        for expected in snapshots.iter().skip(1) {
+            h.redo();
+            assert_eq!(snapshot(&h.doc), *expected);
+        }
     }
 }